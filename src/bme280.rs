@@ -3,19 +3,19 @@ use std::thread;
 use anyhow::{Context, Result};
 use bme280::i2c::BME280;
 use crate::mqtt::MQTTMessage;
-use crate::packet::{Data, DataType, Packet, BME280 as PacketBME280};
+use crate::packet::{Data, Packet, BME280HighRes, BME280 as PacketBME280};
+use crate::tx_queue::TxMessage;
 use linux_embedded_hal::{Delay, I2cdev};
 use log::{error, info};
 use crate::BME280Config;
 use std::sync::mpsc::Sender;
 
-macro_rules! handle_error_continue {
+macro_rules! log_error {
     ($func:expr) => {
         match $func {
             Err(e) => {
                 eprintln!("{:?}", e);
                 error!("{:?}", e);
-                continue;
             }
             Ok(s) => s,
         }
@@ -25,6 +25,7 @@ macro_rules! handle_error_continue {
 pub struct BME280Sensor {
     bme280: BME280<I2cdev>,
     delay: Delay,
+    high_resolution: bool,
 }
 
 impl BME280Sensor {
@@ -40,31 +41,53 @@ impl BME280Sensor {
             .map_err(|e| anyhow::anyhow!("BME280 initialization failed: {:?}", e))?;
 
         info!("BME280 initialized successfully");
-        Ok(BME280Sensor { bme280, delay })
+        Ok(BME280Sensor {
+            bme280,
+            delay,
+            high_resolution: config.high_resolution,
+        })
     }
 
-    pub fn read_measurements(&mut self) -> Result<PacketBME280> {
+    pub fn read_measurements(&mut self) -> Result<Data> {
         let measurements = self
             .bme280
             .measure(&mut self.delay)
             .map_err(|e| anyhow::anyhow!("Failed to read BME280 sensor: {:?}", e))?;
 
+        if self.high_resolution {
+            return Ok(Data::Bme280HighRes(BME280HighRes {
+                temperature: (measurements.temperature * 100.0).round() as i16,
+                humidity: (measurements.humidity * 100.0).round() as u16,
+                pressure: measurements.pressure.round() as u32,
+            }));
+        }
+
         // cast pressure & temperature to i8 (two's complement), then fit into u8 to send through LoRa
         let temperature = (measurements.temperature * 2.0).round() as i8 as u8;
         let pressure = ((measurements.pressure / 100.0) - 1000.0).round() as i8 as u8;
         let humidity = measurements.humidity.round() as u8;
     
-        Ok(PacketBME280 {
+        Ok(Data::Bme280(PacketBME280 {
             temperature,
             pressure,
             humidity,
-        })
+        }))
     }
 
-    pub fn print(&self, data: &PacketBME280) -> Result<()> {
-        let temperature = data.temperature as i8 as f32 / 2.0;
-        let pressure = (data.pressure as i8 as f32 + 1000.0) * 100.0;
-        let humidity = data.humidity as f32;
+    pub fn print(&self, data: &Data) -> Result<()> {
+        let (temperature, pressure, humidity) = match data {
+            Data::Bme280(data) => (
+                data.temperature as i8 as f32 / 2.0,
+                (data.pressure as i8 as f32 + 1000.0) * 100.0,
+                data.humidity as f32,
+            ),
+            Data::Bme280HighRes(data) => (
+                data.temperature as f32 / 100.0,
+                data.pressure as f32,
+                data.humidity as f32 / 100.0,
+            ),
+            _ => return Err(anyhow::anyhow!("Not a BME280 reading")).context("BME280Sensor::print"),
+        };
 
         println!("BME280 Sensor Measurements:");
         println!("Temperature: {:.1} °C", temperature);
@@ -78,7 +101,14 @@ impl BME280Sensor {
         Ok(())
     }
 
-    pub fn thread_run(&mut self, bme280_config: BME280Config, mqtt_enabled: bool, option_device_id: Option<u8>, option_sender: Option<Sender<MQTTMessage>>) {
+    pub fn thread_run(
+        &mut self,
+        bme280_config: BME280Config,
+        mqtt_enabled: bool,
+        option_device_id: Option<u8>,
+        option_sender: Option<Sender<MQTTMessage>>,
+        option_tx_sender: Option<Sender<TxMessage>>,
+    ) {
         let measurement_interval = bme280_config.measurement_interval;
 
         loop {
@@ -95,18 +125,19 @@ impl BME280Sensor {
                             id: option_device_id.expect("option_device_id shouldn't be none if MQTT is enabled"),
                             msg_id: 0,
                             msg_count: 0,
-                            data_type: DataType::BME280,
-                            data: Data::Bme280(PacketBME280 {
-                                temperature: data.temperature,
-                                humidity: data.humidity,
-                                pressure: data.pressure,
-                            })
+                            data_type: data.data_type(),
+                            data: data.clone(),
                         };
 
                         if let Some(bme280_sender) = &option_sender {
-                            handle_error_continue!(bme280_sender.send(MQTTMessage::Packet(packet)));
+                            log_error!(bme280_sender.send(MQTTMessage::Packet(packet)));
                         }
                     }
+
+                    // the LoRa thread transmits the latest reading every transmit interval
+                    if let Some(tx_sender) = &option_tx_sender {
+                        log_error!(tx_sender.send(TxMessage::Reading(data)));
+                    }
                 }
                 Err(e) => println!("Error reading measurements: {:?}", e),
             }
@@ -0,0 +1,383 @@
+use crate::packet::Data;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/*
+ * Cayenne Low Power Payload: a stream of records, each a channel byte, a type byte and
+ * a big-endian value of a size fixed by the type. Values are kept as sent, the resolution
+ * of the type is applied only when they are formatted
+ */
+
+const DIGITAL_INPUT: u8 = 0;
+const DIGITAL_OUTPUT: u8 = 1;
+const ANALOG_INPUT: u8 = 2;
+const ANALOG_OUTPUT: u8 = 3;
+const ILLUMINANCE: u8 = 101;
+const PRESENCE: u8 = 102;
+const TEMPERATURE: u8 = 103;
+const HUMIDITY: u8 = 104;
+const ACCELEROMETER: u8 = 113;
+const BAROMETER: u8 = 115;
+const GYROMETER: u8 = 134;
+const GPS: u8 = 136;
+
+const I24_MIN: i32 = -(1 << 23);
+const I24_MAX: i32 = (1 << 23) - 1;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LppValue {
+    DigitalInput(u8),
+    DigitalOutput(u8),
+    /// 0.01
+    AnalogInput(i16),
+    /// 0.01
+    AnalogOutput(i16),
+    /// lux
+    Illuminance(u16),
+    Presence(u8),
+    /// 0.1 °C
+    Temperature(i16),
+    /// 0.5 %
+    Humidity(u8),
+    /// 0.001 G
+    Accelerometer { x: i16, y: i16, z: i16 },
+    /// 0.1 hPa
+    Barometer(u16),
+    /// 0.01 °/s
+    Gyrometer { x: i16, y: i16, z: i16 },
+    /// 0.0001 ° latitude and longitude, 0.01 m altitude, 24 bits each
+    Gps { latitude: i32, longitude: i32, altitude: i32 },
+}
+
+impl LppValue {
+    fn lpp_type(&self) -> u8 {
+        match self {
+            LppValue::DigitalInput(_) => DIGITAL_INPUT,
+            LppValue::DigitalOutput(_) => DIGITAL_OUTPUT,
+            LppValue::AnalogInput(_) => ANALOG_INPUT,
+            LppValue::AnalogOutput(_) => ANALOG_OUTPUT,
+            LppValue::Illuminance(_) => ILLUMINANCE,
+            LppValue::Presence(_) => PRESENCE,
+            LppValue::Temperature(_) => TEMPERATURE,
+            LppValue::Humidity(_) => HUMIDITY,
+            LppValue::Accelerometer { .. } => ACCELEROMETER,
+            LppValue::Barometer(_) => BAROMETER,
+            LppValue::Gyrometer { .. } => GYROMETER,
+            LppValue::Gps { .. } => GPS,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            LppValue::DigitalInput(_) => "digital_in",
+            LppValue::DigitalOutput(_) => "digital_out",
+            LppValue::AnalogInput(_) => "analog_in",
+            LppValue::AnalogOutput(_) => "analog_out",
+            LppValue::Illuminance(_) => "luminosity",
+            LppValue::Presence(_) => "presence",
+            LppValue::Temperature(_) => "temperature",
+            LppValue::Humidity(_) => "relative_humidity",
+            LppValue::Accelerometer { .. } => "accelerometer",
+            LppValue::Barometer(_) => "barometric_pressure",
+            LppValue::Gyrometer { .. } => "gyrometer",
+            LppValue::Gps { .. } => "gps",
+        }
+    }
+
+    fn size(lpp_type: u8) -> Result<usize> {
+        match lpp_type {
+            DIGITAL_INPUT | DIGITAL_OUTPUT | PRESENCE | HUMIDITY => Ok(1),
+            ANALOG_INPUT | ANALOG_OUTPUT | ILLUMINANCE | TEMPERATURE | BAROMETER => Ok(2),
+            ACCELEROMETER | GYROMETER => Ok(6),
+            GPS => Ok(9),
+            _ => Err(anyhow!("Unknown Cayenne LPP type {}", lpp_type)).context("LppValue::size"),
+        }
+    }
+
+    fn from_bytes(lpp_type: u8, bytes: &[u8]) -> Self {
+        let i16_at = |start: usize| i16::from_be_bytes([bytes[start], bytes[start + 1]]);
+        let u16_at = |start: usize| u16::from_be_bytes([bytes[start], bytes[start + 1]]);
+        // sign extended from the top byte
+        let i24_at = |start: usize| i32::from_be_bytes([bytes[start], bytes[start + 1], bytes[start + 2], 0]) >> 8;
+
+        match lpp_type {
+            DIGITAL_INPUT => LppValue::DigitalInput(bytes[0]),
+            DIGITAL_OUTPUT => LppValue::DigitalOutput(bytes[0]),
+            ANALOG_INPUT => LppValue::AnalogInput(i16_at(0)),
+            ANALOG_OUTPUT => LppValue::AnalogOutput(i16_at(0)),
+            ILLUMINANCE => LppValue::Illuminance(u16_at(0)),
+            PRESENCE => LppValue::Presence(bytes[0]),
+            TEMPERATURE => LppValue::Temperature(i16_at(0)),
+            HUMIDITY => LppValue::Humidity(bytes[0]),
+            ACCELEROMETER => LppValue::Accelerometer { x: i16_at(0), y: i16_at(2), z: i16_at(4) },
+            BAROMETER => LppValue::Barometer(u16_at(0)),
+            GYROMETER => LppValue::Gyrometer { x: i16_at(0), y: i16_at(2), z: i16_at(4) },
+            _ => LppValue::Gps { latitude: i24_at(0), longitude: i24_at(3), altitude: i24_at(6) },
+        }
+    }
+
+    fn to_bytes(self) -> Result<Vec<u8>> {
+        let bytes = match self {
+            LppValue::DigitalInput(value)
+            | LppValue::DigitalOutput(value)
+            | LppValue::Presence(value)
+            | LppValue::Humidity(value) => vec![value],
+            LppValue::AnalogInput(value) | LppValue::AnalogOutput(value) | LppValue::Temperature(value) => {
+                value.to_be_bytes().to_vec()
+            }
+            LppValue::Illuminance(value) | LppValue::Barometer(value) => value.to_be_bytes().to_vec(),
+            LppValue::Accelerometer { x, y, z } | LppValue::Gyrometer { x, y, z } => {
+                [x.to_be_bytes(), y.to_be_bytes(), z.to_be_bytes()].concat()
+            }
+            LppValue::Gps { latitude, longitude, altitude } => {
+                let mut bytes = Vec::new();
+                for value in [latitude, longitude, altitude] {
+                    if !(I24_MIN..=I24_MAX).contains(&value) {
+                        return Err(anyhow!("GPS value {} doesn't fit in 24 bits", value))
+                            .context("LppValue::to_bytes");
+                    }
+                    bytes.extend(&value.to_be_bytes()[1..]);
+                }
+                bytes
+            }
+        };
+        Ok(bytes)
+    }
+
+    /*
+     * The value in the units of the type, objects for the values with several axes
+     */
+    fn to_json(self) -> String {
+        match self {
+            LppValue::DigitalInput(value)
+            | LppValue::DigitalOutput(value)
+            | LppValue::Presence(value) => value.to_string(),
+            LppValue::AnalogInput(value) | LppValue::AnalogOutput(value) => (value as f64 / 100.0).to_string(),
+            LppValue::Illuminance(value) => value.to_string(),
+            LppValue::Temperature(value) => (value as f64 / 10.0).to_string(),
+            LppValue::Humidity(value) => (value as f64 / 2.0).to_string(),
+            LppValue::Accelerometer { x, y, z } => format!(
+                r#"{{ "x": {}, "y": {}, "z": {} }}"#,
+                x as f64 / 1000.0, y as f64 / 1000.0, z as f64 / 1000.0
+            ),
+            LppValue::Barometer(value) => (value as f64 / 10.0).to_string(),
+            LppValue::Gyrometer { x, y, z } => format!(
+                r#"{{ "x": {}, "y": {}, "z": {} }}"#,
+                x as f64 / 100.0, y as f64 / 100.0, z as f64 / 100.0
+            ),
+            LppValue::Gps { latitude, longitude, altitude } => format!(
+                r#"{{ "latitude": {}, "longitude": {}, "altitude": {} }}"#,
+                latitude as f64 / 10_000.0, longitude as f64 / 10_000.0, altitude as f64 / 100.0
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LppRecord {
+    pub channel: u8,
+    pub value: LppValue,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct CayenneLpp {
+    pub records: Vec<LppRecord>,
+}
+
+impl CayenneLpp {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.is_empty() {
+            return Err(anyhow!("Empty Cayenne LPP payload")).context("CayenneLpp::from_bytes");
+        }
+
+        let mut records = Vec::new();
+        let mut start = 0;
+        while start < bytes.len() {
+            if start + 2 > bytes.len() {
+                return Err(anyhow!("Cayenne LPP record at byte {} is cut off", start))
+                    .context("CayenneLpp::from_bytes");
+            }
+            let channel = bytes[start];
+            let lpp_type = bytes[start + 1];
+            let size = LppValue::size(lpp_type).context("CayenneLpp::from_bytes")?;
+            let value_start = start + 2;
+            if value_start + size > bytes.len() {
+                return Err(anyhow!("Cayenne LPP record at byte {} is cut off", start))
+                    .context("CayenneLpp::from_bytes");
+            }
+
+            records.push(LppRecord {
+                channel,
+                value: LppValue::from_bytes(lpp_type, &bytes[value_start..value_start + size]),
+            });
+            start = value_start + size;
+        }
+
+        Ok(Self { records })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        for record in &self.records {
+            bytes.push(record.channel);
+            bytes.push(record.value.lpp_type());
+            bytes.extend(record.value.to_bytes().context("CayenneLpp::to_bytes")?);
+        }
+        Ok(bytes)
+    }
+
+    /*
+     * The records keyed by type and channel, as the common LPP decoders do
+     */
+    pub fn to_json(&self) -> String {
+        let records: Vec<String> = self
+            .records
+            .iter()
+            .map(|record| format!(r#""{}_{}": {}"#, record.value.name(), record.channel, record.value.to_json()))
+            .collect();
+        format!("{{ {} }}", records.join(", "))
+    }
+
+    /*
+     * Records for a reading in the TX queue, on the channel of its data type byte.
+     * None for the data types LPP has no type for
+     */
+    pub fn records_from_reading(data: &Data) -> Option<Vec<LppRecord>> {
+        let channel = data.data_type().to_byte();
+        let values = match data {
+            Data::Bme280(data) => vec![
+                LppValue::Temperature(data.temperature as i8 as i16 * 5),
+                LppValue::Humidity(data.humidity.saturating_mul(2)),
+                LppValue::Barometer((data.pressure as i8 as i16 + 1000) as u16 * 10),
+            ],
+            Data::Bme280HighRes(data) => vec![
+                LppValue::Temperature((data.temperature as f64 / 10.0).round() as i16),
+                LppValue::Humidity((data.humidity as f64 / 50.0).round().min(u8::MAX as f64) as u8),
+                LppValue::Barometer((data.pressure as f64 / 10.0).round().min(u16::MAX as f64) as u16),
+            ],
+            Data::Gps(data) => vec![LppValue::Gps {
+                latitude: (data.latitude as f64 / 1000.0).round() as i32,
+                longitude: (data.longitude as f64 / 1000.0).round() as i32,
+                altitude: data.altitude.saturating_mul(100).clamp(I24_MIN, I24_MAX),
+            }],
+            _ => return None,
+        };
+
+        Some(values.into_iter().map(|value| LppRecord { channel, value }).collect())
+    }
+}
+
+impl fmt::Debug for CayenneLpp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_json().replace('"', ""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{Gps, BME280};
+
+    #[test]
+    fn from_bytes_correct() {
+        // examples from the Cayenne LPP documentation
+        let bytes = [
+            0x03, 0x67, 0x01, 0x10, 0x05, 0x67, 0x00, 0xFF, // temperature 27.2 and 25.5 °C
+            0x06, 0x71, 0x04, 0xD2, 0xFB, 0x2E, 0x00, 0x00, // accelerometer 1.234, -1.234, 0 G
+            0x01, 0x88, 0x06, 0x76, 0x5F, 0xF2, 0x96, 0x0A, 0x00, 0x03, 0xE8, // GPS 42.3519, -87.9094, 10 m
+        ];
+        let lpp = CayenneLpp::from_bytes(&bytes).unwrap();
+        assert_eq!(4, lpp.records.len());
+        assert_eq!(LppRecord { channel: 3, value: LppValue::Temperature(272) }, lpp.records[0]);
+        assert_eq!(
+            LppRecord { channel: 1, value: LppValue::Gps { latitude: 423_519, longitude: -879_094, altitude: 1000 } },
+            lpp.records[3]
+        );
+        assert_eq!(
+            r#"{ "temperature_3": 27.2, "temperature_5": 25.5, "accelerometer_6": { "x": 1.234, "y": -1.234, "z": 0 }, "gps_1": { "latitude": 42.3519, "longitude": -87.9094, "altitude": 10 } }"#,
+            lpp.to_json()
+        );
+        assert_eq!(bytes.to_vec(), lpp.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn from_bytes_all_types() {
+        let lpp = CayenneLpp {
+            records: vec![
+                LppRecord { channel: 0, value: LppValue::DigitalInput(1) },
+                LppRecord { channel: 1, value: LppValue::DigitalOutput(0) },
+                LppRecord { channel: 2, value: LppValue::AnalogInput(-150) },
+                LppRecord { channel: 3, value: LppValue::AnalogOutput(1234) },
+                LppRecord { channel: 4, value: LppValue::Illuminance(65_000) },
+                LppRecord { channel: 5, value: LppValue::Presence(1) },
+                LppRecord { channel: 6, value: LppValue::Humidity(97) },
+                LppRecord { channel: 7, value: LppValue::Barometer(10_132) },
+                LppRecord { channel: 8, value: LppValue::Gyrometer { x: -100, y: 200, z: 32_767 } },
+            ],
+        };
+        let bytes = lpp.to_bytes().unwrap();
+        assert_eq!(3 + 3 + 4 + 4 + 4 + 3 + 3 + 4 + 8, bytes.len());
+        assert_eq!(lpp, CayenneLpp::from_bytes(&bytes).unwrap());
+        assert_eq!(
+            r#"{ "digital_in_0": 1, "digital_out_1": 0, "analog_in_2": -1.5, "analog_out_3": 12.34, "luminosity_4": 65000, "presence_5": 1, "relative_humidity_6": 48.5, "barometric_pressure_7": 1013.2, "gyrometer_8": { "x": -1, "y": 2, "z": 327.67 } }"#,
+            lpp.to_json()
+        );
+    }
+
+    #[test]
+    fn from_bytes_incorrect() {
+        assert!(CayenneLpp::from_bytes(&[]).is_err());
+        // unknown type, cut off value, cut off header
+        assert!(CayenneLpp::from_bytes(&[0x01, 0x42, 0x00]).is_err());
+        assert!(CayenneLpp::from_bytes(&[0x01, 0x67, 0x01]).is_err());
+        assert!(CayenneLpp::from_bytes(&[0x01, 0x67, 0x01, 0x10, 0x02]).is_err());
+    }
+
+    #[test]
+    fn to_bytes_gps_out_of_range() {
+        let lpp = CayenneLpp {
+            records: vec![LppRecord {
+                channel: 1,
+                value: LppValue::Gps { latitude: I24_MAX + 1, longitude: 0, altitude: 0 },
+            }],
+        };
+        assert!(lpp.to_bytes().is_err());
+    }
+
+    #[test]
+    fn records_from_reading_correct() {
+        // 21.5 °C, 46 %, 1013 hPa in the compact BME280 format
+        let records = CayenneLpp::records_from_reading(&Data::Bme280(BME280 {
+            temperature: 43,
+            humidity: 46,
+            pressure: 13,
+        }))
+        .unwrap();
+        assert_eq!(
+            vec![
+                LppRecord { channel: 1, value: LppValue::Temperature(215) },
+                LppRecord { channel: 1, value: LppValue::Humidity(92) },
+                LppRecord { channel: 1, value: LppValue::Barometer(10_130) },
+            ],
+            records
+        );
+
+        let records = CayenneLpp::records_from_reading(&Data::Gps(Gps {
+            status: 0,
+            altitude: 120,
+            latitude: 521_234_567,
+            longitude: 210_123_456,
+        }))
+        .unwrap();
+        assert_eq!(
+            vec![LppRecord {
+                channel: 4,
+                value: LppValue::Gps { latitude: 521_235, longitude: 210_123, altitude: 12_000 },
+            }],
+            records
+        );
+
+        assert!(CayenneLpp::records_from_reading(&Data::Sms("hello".to_string())).is_none());
+    }
+}
@@ -1,11 +1,18 @@
 use crate::{
-    defines::{Bandwidth, CodingRate, SpreadingFactor},
-    Chip,
+    defines::{
+        fsk_rx_bandwidth, ocp_trim, Bandwidth, CodingRate, FrequencyBand, GaussianFilter, LnaGain,
+        Modulation, PaOutput, SX1278PADac, SpreadingFactor,
+    },
+    fragment::MAX_PARTS,
+    packet::{DataType, PacketVersion, DATA_SIZE},
+    Chip, ChipFamily,
 };
 use anyhow::{anyhow, Context, Result};
-use gpiod::{EdgeDetect, Input, Lines, Options, Output};
+use gpio_cdev::{EventRequestFlags, LineEventHandle, LineRequestFlags};
+use gpiod::{Lines, Options, Output};
 use log::info;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -18,10 +25,18 @@ pub struct Config {
 impl Config {
     pub fn from_file(config_path: String) -> Result<Config> {
         let config_file = fs::read_to_string(config_path).context("Config::from_file")?;
-        let config = toml::from_str(config_file.as_str()).context("Config::from_file")?;
+        let config: Config = toml::from_str(config_file.as_str()).context("Config::from_file")?;
+        config.validate().context("Config::from_file")?;
         info!("Succesfully read config file.");
         Ok(config)
     }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(lora_config) = &self.lora_config {
+            lora_config.validate().context("Config::validate")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -40,6 +55,9 @@ pub struct BME280Config {
     pub i2c_bus_path: String,
     pub i2c_address: u8,
     pub measurement_interval: u64,
+    /// Send readings as BME280HighRes instead of the compact BME280 type
+    #[serde(default)]
+    pub high_resolution: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -54,20 +72,673 @@ pub struct SPIConfig {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct LoRaConfig {
     pub chip: Chip,
+    #[serde(default)]
+    pub backend: Backend,
     pub mode: Mode,
     pub reset_gpio: GPIOPinNumber,
-    pub dio0_gpio: GPIOPinNumber,
+    /// Required with the SX127x family
+    pub dio0_gpio: Option<GPIOPinNumber>,
+    /// Required for RX_SINGLE receive with symbol timeout, FHSS and the SX126x family
+    pub dio1_gpio: Option<GPIOPinNumber>,
+    /// Required with the SX126x family
+    pub busy_gpio: Option<GPIOPinNumber>,
     pub spi_config: SPIConfig,
     pub radio_config: RadioConfig,
+    /// Required in the TX, TX_RANGE_TEST and TRX modes
+    pub tx_config: Option<TxConfig>,
+    pub reliable_delivery: Option<ReliableDeliveryConfig>,
+    pub encryption: Option<EncryptionConfig>,
+    /// Parts of a multi-part message that doesn't complete within this time are dropped
+    #[serde(default = "default_reassembly_timeout_ms")]
+    pub reassembly_timeout_ms: u64,
+    /// Data types decoded from their description here, see README
+    #[serde(default)]
+    pub custom_data_types: Vec<CustomDataTypeConfig>,
+}
+
+impl LoRaConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.radio_config
+            .validate(self.chip)
+            .context("LoRaConfig::validate")?;
+
+        match self.chip.family() {
+            ChipFamily::SX127x if self.dio0_gpio.is_none() => {
+                return Err(anyhow!("{:?} requires dio0_gpio", self.chip))
+                    .context("LoRaConfig::validate");
+            }
+            ChipFamily::SX126x if self.busy_gpio.is_none() || self.dio1_gpio.is_none() => {
+                return Err(anyhow!("{:?} requires busy_gpio and dio1_gpio", self.chip))
+                    .context("LoRaConfig::validate");
+            }
+            _ => {}
+        }
+
+        if self.radio_config.symbol_timeout.is_some() && self.dio1_gpio.is_none() {
+            return Err(anyhow!("symbol_timeout requires dio1_gpio for the RxTimeout interrupt"))
+                .context("LoRaConfig::validate");
+        }
+        if self.radio_config.fhss.is_some() && self.dio1_gpio.is_none() {
+            return Err(anyhow!("fhss requires dio1_gpio for the FhssChangeChannel interrupt"))
+                .context("LoRaConfig::validate");
+        }
+
+        if let Mode::TRX = self.mode {
+            if self.radio_config.symbol_timeout.is_some() || self.radio_config.rx_timeout_ms.is_some() {
+                return Err(anyhow!("TRX mode receives continuously, without symbol_timeout and rx_timeout_ms"))
+                    .context("LoRaConfig::validate");
+            }
+        }
+
+        match (&self.mode, &self.tx_config) {
+            (Mode::TX | Mode::TX_RANGE_TEST | Mode::TRX, None) => {
+                return Err(anyhow!("{:?} mode requires tx_config", self.mode))
+                    .context("LoRaConfig::validate");
+            }
+            (_, Some(tx_config)) if tx_config.transmit_interval == 0 => {
+                return Err(anyhow!("transmit_interval should be at least 1 second"))
+                    .context("LoRaConfig::validate");
+            }
+            (_, Some(tx_config)) => {
+                PacketVersion::new(tx_config.packet_version).context("LoRaConfig::validate")?;
+            }
+            _ => {}
+        }
+
+        if let Some(reliable_delivery) = &self.reliable_delivery {
+            if reliable_delivery.ack_timeout_ms == 0 {
+                return Err(anyhow!("ack_timeout_ms should be at least 1 ms"))
+                    .context("LoRaConfig::validate");
+            }
+        }
+
+        if self.reassembly_timeout_ms == 0 {
+            return Err(anyhow!("reassembly_timeout_ms should be at least 1 ms"))
+                .context("LoRaConfig::validate");
+        }
+
+        let mut ids = HashSet::new();
+        for custom_data_type in &self.custom_data_types {
+            custom_data_type.validate().context("LoRaConfig::validate")?;
+            if !ids.insert(custom_data_type.id) {
+                return Err(anyhow!("Custom data type id {} is declared twice", custom_data_type.id))
+                    .context("LoRaConfig::validate");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What a node transmits: the latest reading of every source is sent each `transmit_interval`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TxConfig {
+    /// Id of this node, put in every packet built from a reading
+    pub device_id: u8,
+    /// Seconds
+    pub transmit_interval: u64,
+    /// Version byte, and so the data layout, of the packets built from readings and the status
+    #[serde(default = "default_packet_version")]
+    pub packet_version: u8,
+    /// Send the readings LPP has types for as one Cayenne LPP packet
+    #[serde(default)]
+    pub cayenne_lpp: bool,
+}
+
+/// Acknowledged delivery, both ends need it: the receiver replies to every packet with an ACK
+/// and the sender retransmits with exponential backoff until it gets one
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReliableDeliveryConfig {
+    /// How long the sender listens for the ACK after every transmission
+    pub ack_timeout_ms: u64,
+    pub max_retransmissions: u32,
+    /// Backoff before the first retransmission, doubled before every next one
+    pub initial_backoff_ms: u64,
+    /// Seconds, repeats of a packet within this window are acknowledged again but not forwarded
+    pub duplicate_window: u64,
+}
+
+/// Authenticated encryption of the packet body, both ends need the keys of the device ids they exchange
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EncryptionConfig {
+    /// TOML file with a 256-bit key for every device id, see README
+    pub key_file: String,
+    /// Keeps the frame counter across restarts
+    pub frame_counter_file: String,
+    /// Keeps the frame counters received from every sender across restarts
+    pub received_counter_file: String,
+}
+
+/// A data type described in the config instead of code, the body is the fields one after another
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CustomDataTypeConfig {
+    /// Data type byte, none of the built-in ones
+    pub id: u8,
+    /// Key of the decoded data in MQTT messages
+    pub name: String,
+    pub fields: Vec<CustomFieldConfig>,
+}
+
+impl CustomDataTypeConfig {
+    /*
+     * Bytes of the body, the sum of the field sizes
+     */
+    pub fn size(&self) -> usize {
+        self.fields.iter().map(|field| field.field_type.size()).sum()
+    }
+
+    fn validate(&self) -> Result<()> {
+        if DataType::new(self.id).is_ok() {
+            return Err(anyhow!("Custom data type id {} is a built-in data type", self.id))
+                .context("CustomDataTypeConfig::validate");
+        }
+        if self.name.is_empty() || self.fields.is_empty() {
+            return Err(anyhow!("Custom data type {} needs a name and at least one field", self.id))
+                .context("CustomDataTypeConfig::validate");
+        }
+        // names and units go into MQTT messages as JSON strings without escaping
+        let json_safe = |text: &str| !text.chars().any(|c| c == '"' || c == '\\' || c.is_control());
+        if !json_safe(&self.name) {
+            return Err(anyhow!("Custom data type name {:?} contains '\"', '\\' or a control character", self.name))
+                .context("CustomDataTypeConfig::validate");
+        }
+
+        let mut names = HashSet::new();
+        for field in &self.fields {
+            // "units" holds the units of the fields in MQTT messages
+            if field.name.is_empty() || field.name == "units" || !names.insert(field.name.as_str()) {
+                return Err(anyhow!("Invalid or repeated field name \"{}\" in {}", field.name, self.name))
+                    .context("CustomDataTypeConfig::validate");
+            }
+            if !json_safe(&field.name) || !json_safe(&field.unit) {
+                return Err(anyhow!(
+                    "Field name or unit of {}.{:?} contains '\"', '\\' or a control character",
+                    self.name,
+                    field.name
+                ))
+                .context("CustomDataTypeConfig::validate");
+            }
+            if !field.scale.is_finite() || !field.offset.is_finite() {
+                return Err(anyhow!("scale and offset of {}.{} should be finite", self.name, field.name))
+                    .context("CustomDataTypeConfig::validate");
+            }
+        }
+
+        if self.size() > MAX_PARTS * DATA_SIZE {
+            return Err(anyhow!(
+                "{} is {} bytes long, at most {} fit in a message",
+                self.name,
+                self.size(),
+                MAX_PARTS * DATA_SIZE
+            ))
+            .context("CustomDataTypeConfig::validate");
+        }
+        Ok(())
+    }
+}
+
+/// The value of a field is `raw * scale + offset`, in `unit`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CustomFieldConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: IntegerType,
+    #[serde(default)]
+    pub endianness: Endianness,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+    #[serde(default)]
+    pub unit: String,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerType {
+    u8,
+    i8,
+    u16,
+    i16,
+    u32,
+    i32,
+    u64,
+    i64,
+}
+
+impl IntegerType {
+    pub fn size(self) -> usize {
+        match self {
+            IntegerType::u8 | IntegerType::i8 => 1,
+            IntegerType::u16 | IntegerType::i16 => 2,
+            IntegerType::u32 | IntegerType::i32 => 4,
+            IntegerType::u64 | IntegerType::i64 => 8,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, IntegerType::i8 | IntegerType::i16 | IntegerType::i32 | IntegerType::i64)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RadioConfig {
+    #[serde(default = "default_modulation")]
+    pub modulation: Modulation,
+    /// Required with FSK and OOK modulation
+    pub fsk: Option<FskConfig>,
     pub frequency: u64,
     pub bandwidth: Bandwidth,
     pub coding_rate: CodingRate,
     pub spreading_factor: SpreadingFactor,
-    pub tx_power: u8,
+    /// dBm
+    pub tx_power: i8,
+    #[serde(default = "default_pa_output")]
+    pub pa_output: PaOutput,
+    /// MaxPower field of PA_CONFIG, Pmax = 10.8 + 0.6 * max_power dBm, RFO only
+    #[serde(default = "default_max_power")]
+    pub max_power: u8,
+    /// Over-current protection limit
+    pub ocp_trim_ma: Option<u8>,
+    #[serde(default = "default_preamble_length")]
+    pub preamble_length: u16,
+    #[serde(default = "default_sync_word")]
+    pub sync_word: u8,
+    #[serde(default)]
+    pub implicit_header: bool,
+    /// Fixed payload length, required in implicit header mode
+    #[serde(default)]
+    pub payload_length: u8,
+    /// Overrides LowDataRateOptimize, which is otherwise set when symbol time exceeds 16 ms
+    pub low_data_rate_optimize: Option<bool>,
+    #[serde(default = "default_agc")]
+    pub agc: bool,
+    /// Manual LNA gain, only allowed with AGC disabled
+    pub lna_gain: Option<LnaGain>,
+    #[serde(default)]
+    pub lna_boost: bool,
+    pub listen_before_talk: Option<ListenBeforeTalkConfig>,
+    pub afc: Option<AfcConfig>,
+    pub fhss: Option<FhssConfig>,
+    /// Receive in RX_SINGLE mode, giving up after this many symbols without a preamble
+    pub symbol_timeout: Option<u16>,
+    /// Receive in RX_CONTINUOUS mode, giving up after this many milliseconds
+    pub rx_timeout_ms: Option<u64>,
+}
+
+/// FSK/OOK packet engine settings, packets are variable length with a length byte
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FskConfig {
+    /// Bits per second
+    pub bitrate: u32,
+    /// Hz, FSK only
+    #[serde(default)]
+    pub frequency_deviation: u32,
+    /// Hz, rounded up to the nearest available channel filter
+    pub rx_bandwidth: u32,
+    /// Bytes
+    #[serde(default = "default_fsk_preamble_length")]
+    pub preamble_length: u16,
+    /// 1 to 8 bytes, none of them 0x00
+    #[serde(default = "default_fsk_sync_word")]
+    pub sync_word: Vec<u8>,
+    #[serde(default)]
+    pub whitening: bool,
+    #[serde(default = "default_fsk_crc")]
+    pub crc: bool,
+    /// Gaussian filter, FSK only
+    pub data_shaping: Option<GaussianFilter>,
+}
+
+impl FskConfig {
+    pub fn validate(&self, modulation: Modulation) -> Result<()> {
+        match modulation {
+            Modulation::OOK => {
+                if !(1_200..=32_768).contains(&self.bitrate) {
+                    return Err(anyhow!(
+                        "OOK bitrate {} out of range, should be within 1200-32768",
+                        self.bitrate
+                    ))
+                    .context("FskConfig::validate");
+                }
+                if self.data_shaping.is_some() {
+                    return Err(anyhow!("data_shaping is only available with FSK"))
+                        .context("FskConfig::validate");
+                }
+            }
+            _ => {
+                if !(1_200..=300_000).contains(&self.bitrate) {
+                    return Err(anyhow!(
+                        "FSK bitrate {} out of range, should be within 1200-300000",
+                        self.bitrate
+                    ))
+                    .context("FskConfig::validate");
+                }
+                if !(600..=200_000).contains(&self.frequency_deviation) {
+                    return Err(anyhow!(
+                        "frequency_deviation {} out of range, should be within 600-200000",
+                        self.frequency_deviation
+                    ))
+                    .context("FskConfig::validate");
+                }
+                if self.frequency_deviation + self.bitrate / 2 > 250_000 {
+                    return Err(anyhow!(
+                        "frequency_deviation + bitrate / 2 should not exceed 250 kHz"
+                    ))
+                    .context("FskConfig::validate");
+                }
+            }
+        }
+
+        fsk_rx_bandwidth(modulation, self.rx_bandwidth)
+            .context("FskConfig::validate")?;
+
+        if !(1..=8).contains(&self.sync_word.len()) || self.sync_word.contains(&0x00) {
+            return Err(anyhow!(
+                "sync_word should be 1 to 8 bytes long, without 0x00 bytes"
+            ))
+            .context("FskConfig::validate");
+        }
+
+        Ok(())
+    }
+}
+
+/// Channel Activity Detection performed before every transmission,
+/// with a random backoff between attempts while the channel is busy
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ListenBeforeTalkConfig {
+    pub max_attempts: u32,
+    pub min_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+/// Frequency hopping, every packet starts on the first channel of the hopping sequence
+/// and moves to the next one every `hop_period` symbols
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FhssConfig {
+    /// Hz, all in the band of `frequency`
+    pub channels: Vec<u64>,
+    pub hop_period: u8,
+    /// Shuffles the channels into the hopping sequence, 0 keeps the configured order.
+    /// Both ends need the same channels and seed
+    #[serde(default)]
+    pub seed: u32,
+}
+
+/// Automatic frequency correction, the RX frequency is moved by the average
+/// frequency error of the last `window` packets
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AfcConfig {
+    pub window: usize,
+    /// Maximum distance from `frequency` the correction may reach, in Hz
+    pub max_offset_hz: u64,
+}
+
+fn default_packet_version() -> u8 {
+    1
+}
+
+fn default_reassembly_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_modulation() -> Modulation {
+    Modulation::LoRa
+}
+
+fn default_fsk_preamble_length() -> u16 {
+    5
+}
+
+fn default_fsk_sync_word() -> Vec<u8> {
+    vec![0x2D, 0xD4]
+}
+
+fn default_fsk_crc() -> bool {
+    true
+}
+
+fn default_pa_output() -> PaOutput {
+    PaOutput::PA_BOOST
+}
+
+fn default_max_power() -> u8 {
+    7
+}
+
+fn default_preamble_length() -> u16 {
+    8
+}
+
+fn default_sync_word() -> u8 {
+    0x12
+}
+
+fn default_agc() -> bool {
+    true
+}
+
+impl RadioConfig {
+    /*
+     * Chip default is 100 mA, the +20 dBm mode needs more headroom
+     */
+    pub fn ocp_trim_ma(&self) -> u8 {
+        match self.ocp_trim_ma {
+            Some(ocp_trim_ma) => ocp_trim_ma,
+            None if self.pa_output == PaOutput::PA_BOOST && self.tx_power > 17 => 140,
+            None => 100,
+        }
+    }
+
+    pub fn validate(&self, chip: Chip) -> Result<()> {
+        chip.check_frequency(self.frequency).context("RadioConfig::validate")?;
+        FrequencyBand::from_frequency(self.frequency).context("RadioConfig::validate")?;
+
+        if self.spreading_factor as u8 > chip.max_spreading_factor() as u8 {
+            return Err(anyhow!(
+                "{:?} supports spreading factors up to {}",
+                chip,
+                chip.max_spreading_factor() as u8
+            ))
+            .context("RadioConfig::validate");
+        }
+
+        if chip.family() == ChipFamily::SX126x {
+            self.validate_sx126x(chip).context("RadioConfig::validate")?;
+        }
+
+        if self.modulation != Modulation::LoRa {
+            match &self.fsk {
+                Some(fsk) => fsk.validate(self.modulation).context("RadioConfig::validate")?,
+                None => {
+                    return Err(anyhow!("{:?} modulation requires the fsk settings", self.modulation))
+                        .context("RadioConfig::validate")
+                }
+            }
+            if self.symbol_timeout.is_some() || self.listen_before_talk.is_some() {
+                return Err(anyhow!(
+                    "symbol_timeout and listen_before_talk are only available with LoRa modulation"
+                ))
+                .context("RadioConfig::validate");
+            }
+        }
+
+        if chip.family() == ChipFamily::SX127x {
+            let (_, pa_dac) = self
+                .pa_output
+                .register_values(self.tx_power, self.max_power)
+                .context("RadioConfig::validate")?;
+            let ocp_trim_ma = self.ocp_trim_ma();
+            ocp_trim(ocp_trim_ma).context("RadioConfig::validate")?;
+            if let (SX1278PADac::PA_DAC_HIGH_POWER, 0..=119) = (pa_dac, ocp_trim_ma) {
+                return Err(anyhow!(
+                    "tx_power {} dBm draws up to 120 mA, ocp_trim_ma {} would trip",
+                    self.tx_power,
+                    ocp_trim_ma
+                ))
+                .context("RadioConfig::validate");
+            }
+        }
+
+        if self.preamble_length < 6 {
+            return Err(anyhow!(
+                "Preamble length {} is too short, should be at least 6",
+                self.preamble_length
+            ))
+            .context("RadioConfig::validate");
+        }
+
+        if self.implicit_header && self.payload_length == 0 {
+            return Err(anyhow!("Implicit header mode requires a non-zero payload_length"))
+                .context("RadioConfig::validate");
+        }
+
+        if let (SpreadingFactor::spreading_factor_64, false) =
+            (self.spreading_factor, self.implicit_header)
+        {
+            return Err(anyhow!("Spreading factor 6 requires implicit header mode"))
+                .context("RadioConfig::validate");
+        }
+
+        if self.agc && self.lna_gain.is_some() {
+            return Err(anyhow!("lna_gain can only be set with AGC disabled"))
+                .context("RadioConfig::validate");
+        }
+
+        if let Some(symbol_timeout) = self.symbol_timeout {
+            if !(4..=1023).contains(&symbol_timeout) {
+                return Err(anyhow!(
+                    "Symbol timeout {} out of range, should be within 4-1023",
+                    symbol_timeout
+                ))
+                .context("RadioConfig::validate");
+            }
+            if self.rx_timeout_ms.is_some() {
+                return Err(anyhow!("symbol_timeout and rx_timeout_ms are mutually exclusive"))
+                    .context("RadioConfig::validate");
+            }
+        }
+
+        if let Some(listen_before_talk) = &self.listen_before_talk {
+            if listen_before_talk.max_attempts == 0 {
+                return Err(anyhow!("listen_before_talk.max_attempts should be at least 1"))
+                    .context("RadioConfig::validate");
+            }
+            if listen_before_talk.min_backoff_ms > listen_before_talk.max_backoff_ms {
+                return Err(anyhow!(
+                    "listen_before_talk.min_backoff_ms is greater than max_backoff_ms"
+                ))
+                .context("RadioConfig::validate");
+            }
+        }
+
+        if let Some(fhss) = &self.fhss {
+            if fhss.channels.is_empty() || fhss.hop_period == 0 {
+                return Err(anyhow!("fhss needs at least one channel and a non-zero hop_period"))
+                    .context("RadioConfig::validate");
+            }
+            let band = FrequencyBand::from_frequency(self.frequency)?;
+            for channel in &fhss.channels {
+                if FrequencyBand::from_frequency(*channel).ok() != Some(band) {
+                    return Err(anyhow!(
+                        "fhss channel {} Hz is not in the band of frequency {} Hz",
+                        channel,
+                        self.frequency
+                    ))
+                    .context("RadioConfig::validate");
+                }
+            }
+            if self.modulation != Modulation::LoRa
+                || self.symbol_timeout.is_some()
+                || self.afc.is_some()
+            {
+                return Err(anyhow!(
+                    "fhss requires LoRa modulation and can't be combined with symbol_timeout or afc"
+                ))
+                .context("RadioConfig::validate");
+            }
+        }
+
+        if let Some(afc) = &self.afc {
+            if afc.window == 0 {
+                return Err(anyhow!("afc.window should be at least 1"))
+                    .context("RadioConfig::validate");
+            }
+            let band = FrequencyBand::from_frequency(self.frequency)?;
+            let lowest = self.frequency.saturating_sub(afc.max_offset_hz);
+            let highest = self.frequency.saturating_add(afc.max_offset_hz);
+            for frequency in [lowest, highest] {
+                if FrequencyBand::from_frequency(frequency).ok() != Some(band) {
+                    return Err(anyhow!(
+                        "afc.max_offset_hz {} takes the frequency outside of its band",
+                        afc.max_offset_hz
+                    ))
+                    .context("RadioConfig::validate");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * The SX126x driver covers LoRa packets only, its PA goes up to +22 dBm
+     * and over-current protection is set in 2.5 mA steps
+     */
+    fn validate_sx126x(&self, chip: Chip) -> Result<()> {
+        if self.modulation != Modulation::LoRa
+            || self.listen_before_talk.is_some()
+            || self.afc.is_some()
+            || self.fhss.is_some()
+            || self.symbol_timeout.is_some()
+            || self.lna_gain.is_some()
+        {
+            return Err(anyhow!(
+                "{:?} supports LoRa modulation without listen_before_talk, afc, fhss, symbol_timeout and lna_gain",
+                chip
+            ))
+            .context("RadioConfig::validate_sx126x");
+        }
+        if !(-9..=22).contains(&self.tx_power) {
+            return Err(anyhow!(
+                "tx_power {} dBm out of range, should be within -9-22 for {:?}",
+                self.tx_power,
+                chip
+            ))
+            .context("RadioConfig::validate_sx126x");
+        }
+        if let Some(ocp_trim_ma) = self.ocp_trim_ma {
+            if ocp_trim_ma > 157 {
+                return Err(anyhow!("ocp_trim_ma {} out of range, should be at most 157", ocp_trim_ma))
+                    .context("RadioConfig::validate_sx126x");
+            }
+        }
+        if let Some(0..=262_143) | None = self.rx_timeout_ms {
+            return Ok(());
+        }
+        Err(anyhow!("rx_timeout_ms should be at most 262143 for {:?}", chip))
+            .context("RadioConfig::validate_sx126x")
+    }
+}
+
+/// Where the driver talks to: the chip over spidev/gpiod, or an in-memory simulated chip
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Hardware,
+    Simulator,
 }
 
 #[allow(non_camel_case_types)]
@@ -76,7 +747,9 @@ pub enum Mode {
     RX,
     TX,
     RX_RANGE_TEST,
-    TX_RANGE_TEST
+    TX_RANGE_TEST,
+    /// Half-duplex, receives and transmits packets from the TX queue in between
+    TRX,
 }
 
 #[allow(non_camel_case_types)]
@@ -138,19 +811,23 @@ pub fn config_output_pin(pin_number: GPIOPinNumber) -> Result<Lines<Output>> {
     Ok(line)
 }
 
-pub fn config_input_pin(pin_number: GPIOPinNumber) -> Result<Lines<Input>> {
+/*
+ * Input lines are requested with rising edge detection, the drivers block on the line's
+ * events while waiting for a DIO interrupt. gpio-cdev is used here because it gives
+ * access to the line fd, which gpiod doesn't
+ */
+pub fn config_input_pin(pin_number: GPIOPinNumber) -> Result<LineEventHandle> {
     let pin = GPIOPin::from_gpio_pin_number(pin_number);
 
-    let chip = match gpiod::Chip::new(pin.chip) {
+    let mut chip = match gpio_cdev::Chip::new(format!("/dev/{}", pin.chip)) {
         Ok(chip) => chip,
         Err(e) => return Err(anyhow!("While creating gpio chip got {:#?}", e)),
     };
 
-    let opts = Options::input([pin.offset])
-        .edge(EdgeDetect::Rising)
-        .consumer("input_pin");
-
-    let line = match chip.request_lines(opts) {
+    let line = match chip
+        .get_line(pin.offset)
+        .and_then(|line| line.events(LineRequestFlags::INPUT, EventRequestFlags::RISING_EDGE, "input_pin"))
+    {
         Ok(line) => line,
         Err(e) => return Err(anyhow!("While requesting gpio line got {:#?}", e)),
     };
@@ -214,4 +891,172 @@ mod tests {
     fn config_incomplete() {
         assert!(Config::from_file("./tests/configs/incomplete_conf.toml".to_string()).is_err());
     }
+
+    #[test]
+    fn config_frequency_out_of_band() {
+        assert!(Config::from_file("./tests/configs/out_of_band_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_implicit_header_without_payload_length() {
+        assert!(Config::from_file("./tests/configs/implicit_header_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_sf6_explicit_header() {
+        assert!(Config::from_file("./tests/configs/sf6_explicit_header_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_symbol_timeout_without_dio1() {
+        assert!(Config::from_file("./tests/configs/symbol_timeout_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_lna_gain_with_agc() {
+        assert!(Config::from_file("./tests/configs/lna_gain_with_agc_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_afc_out_of_band() {
+        assert!(Config::from_file("./tests/configs/afc_out_of_band_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_rfo_tx_power_too_high() {
+        assert!(Config::from_file("./tests/configs/rfo_tx_power_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_high_power_low_ocp() {
+        assert!(Config::from_file("./tests/configs/high_power_ocp_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_fsk_correct() {
+        assert!(Config::from_file("./tests/configs/fsk_conf.toml".to_string()).is_ok());
+    }
+
+    #[test]
+    fn config_ook_without_fsk_settings() {
+        assert!(Config::from_file("./tests/configs/ook_without_fsk_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_fhss_correct() {
+        assert!(Config::from_file("./tests/configs/fhss_conf.toml".to_string()).is_ok());
+    }
+
+    #[test]
+    fn config_fhss_without_dio1() {
+        assert!(Config::from_file("./tests/configs/fhss_without_dio1_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_sx1262_correct() {
+        assert!(Config::from_file("./tests/configs/sx1262_conf.toml".to_string()).is_ok());
+    }
+
+    #[test]
+    fn config_sx1262_without_busy() {
+        assert!(Config::from_file("./tests/configs/sx1262_without_busy_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_sx1277_spreading_factor_too_high() {
+        assert!(Config::from_file("./tests/configs/sx1277_sf12_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_rfm95_correct() {
+        assert!(Config::from_file("./tests/configs/rfm95_conf.toml".to_string()).is_ok());
+    }
+
+    #[test]
+    fn config_tx_correct() {
+        let config = Config::from_file("./tests/configs/tx_conf.toml".to_string()).unwrap();
+        let tx_config = config.lora_config.unwrap().tx_config.unwrap();
+        assert_eq!(7, tx_config.device_id);
+        assert_eq!(1, tx_config.transmit_interval);
+    }
+
+    #[test]
+    fn config_tx_without_tx_config() {
+        assert!(Config::from_file("./tests/configs/tx_without_tx_config_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_tx_unknown_packet_version() {
+        let config = Config::from_file("./tests/configs/tx_conf.toml".to_string()).unwrap();
+        assert_eq!(1, config.lora_config.unwrap().tx_config.unwrap().packet_version);
+        assert!(Config::from_file("./tests/configs/tx_unknown_packet_version_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_trx_with_rx_timeout() {
+        assert!(Config::from_file("./tests/configs/trx_rx_timeout_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_reassembly_timeout_default() {
+        let config = Config::from_file("./tests/configs/simulator_conf.toml".to_string()).unwrap();
+        assert_eq!(30_000, config.lora_config.unwrap().reassembly_timeout_ms);
+    }
+
+    #[test]
+    fn config_custom_data_types_correct() {
+        let config = Config::from_file("./tests/configs/custom_data_types_conf.toml".to_string()).unwrap();
+        let custom_data_types = config.lora_config.unwrap().custom_data_types;
+        assert_eq!(2, custom_data_types.len());
+        assert_eq!("SOIL", custom_data_types[0].name);
+        assert_eq!(5, custom_data_types[0].size());
+        let battery = &custom_data_types[0].fields[2];
+        assert_eq!(IntegerType::u8, battery.field_type);
+        assert_eq!(Endianness::Little, battery.endianness);
+        assert_eq!((20.0, 2000.0), (battery.scale, battery.offset));
+        let total = &custom_data_types[1].fields[1];
+        assert_eq!(Endianness::Big, total.endianness);
+        assert_eq!((1.0, 0.0, ""), (total.scale, total.offset, total.unit.as_str()));
+
+        assert!(Config::from_file("./tests/configs/custom_data_type_builtin_id_conf.toml".to_string()).is_err());
+    }
+
+    #[test]
+    fn config_custom_data_types_json_unsafe() {
+        let config = Config::from_file("./tests/configs/custom_data_types_conf.toml".to_string()).unwrap();
+        let custom_data_type = &config.lora_config.unwrap().custom_data_types[0];
+        assert!(custom_data_type.validate().is_ok());
+
+        for (name, field_name, unit) in [("SO\"IL", "moisture", "%"), ("SOIL", "moi\\sture", "%"), ("SOIL", "moisture", "%\n")] {
+            let mut custom_data_type = custom_data_type.clone();
+            custom_data_type.name = name.to_string();
+            custom_data_type.fields[0].name = field_name.to_string();
+            custom_data_type.fields[0].unit = unit.to_string();
+            assert!(custom_data_type.validate().is_err(), "{:?} {:?} {:?}", name, field_name, unit);
+        }
+    }
+
+    #[test]
+    fn config_encryption_correct() {
+        let config = Config::from_file("./tests/configs/encrypted_conf.toml".to_string()).unwrap();
+        let encryption = config.lora_config.unwrap().encryption.unwrap();
+        assert_eq!("./tests/configs/keys.toml", encryption.key_file);
+    }
+
+    #[test]
+    fn config_reliable_delivery_correct() {
+        let config = Config::from_file("./tests/configs/reliable_conf.toml".to_string()).unwrap();
+        let reliable_delivery = config.lora_config.unwrap().reliable_delivery.unwrap();
+        assert_eq!(200, reliable_delivery.ack_timeout_ms);
+        assert_eq!(2, reliable_delivery.max_retransmissions);
+    }
+
+    #[test]
+    fn config_simulator_backend() {
+        let config = Config::from_file("./tests/configs/simulator_conf.toml".to_string()).unwrap();
+        assert_eq!(Backend::Simulator, config.lora_config.unwrap().backend);
+
+        let config = Config::from_file("./conf.toml".to_string()).unwrap();
+        assert_eq!(Backend::Hardware, config.lora_config.unwrap().backend);
+    }
 }
@@ -1,11 +1,23 @@
 use anyhow::{Context, Result};
 
+pub fn vec_to_i16(vec: &[u8], start: usize) -> Result<i16> {
+    let slice = &vec[start..start + 2];
+    let array: [u8; 2] = slice.try_into().context("vec_to_i16")?;
+    Ok(i16::from_le_bytes(array))
+}
+
 pub fn vec_to_u16(vec: &[u8], start: usize) -> Result<u16> {
     let slice = &vec[start..start + 2];
     let array: [u8; 2] = slice.try_into().context("vec_to_u16")?;
     Ok(u16::from_le_bytes(array))
 }
 
+pub fn vec_to_u32(vec: &[u8], start: usize) -> Result<u32> {
+    let slice = &vec[start..start + 4];
+    let array: [u8; 4] = slice.try_into().context("vec_to_u32")?;
+    Ok(u32::from_le_bytes(array))
+}
+
 pub fn vec_to_u64(vec: &[u8], start: usize) -> Result<u64> {
     let slice = &vec[start..start + 8];
     let array: [u8; 8] = slice.try_into().context("vec_to_u64")?;
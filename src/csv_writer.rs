@@ -4,14 +4,15 @@ use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Receiver;
 use crate::config::*;
-use crate::packet::Packet;
+use crate::packet::{Packet, PacketWrapper};
 
 // Data sent here from the LoRa thread through a channel.
-// Either the packet, or information that a CRC error occured.
+// Either the packet (received ones carry SNR and RSSI), or information that a CRC error occured.
 #[allow(non_camel_case_types)]
 #[derive(Debug)]
 pub enum CSVPacketWrapper {
     Packet(Packet),
+    Received(PacketWrapper),
     CRC_ERROR,
 }
 
@@ -48,12 +49,41 @@ impl CSVWriter {
         format!("{}-{}-{:?}.csv", timestamp, lora_config.radio_config.frequency, lora_config.mode)
     }
 
+    /*
+     * Packet, SNR, RSSI and frequency error columns, sent packets and CRC errors have no metadata
+     */
+    fn packet_columns(packet: CSVPacketWrapper) -> [String; 4] {
+        match packet {
+            CSVPacketWrapper::Packet(packet) => {
+                [Self::packet_text(&packet), String::new(), String::new(), String::new()]
+            }
+            CSVPacketWrapper::Received(wrapped) => [
+                Self::packet_text(&wrapped.packet),
+                wrapped.metadata.snr.to_string(),
+                wrapped.metadata.rssi.to_string(),
+                wrapped.metadata.frequency_error.to_string(),
+            ],
+            CSVPacketWrapper::CRC_ERROR => {
+                ["CRC_ERROR".to_string(), String::new(), String::new(), String::new()]
+            }
+        }
+    }
+
+    // the same text for sent and received packets
+    fn packet_text(packet: &Packet) -> String {
+        format!("Packet({:?})", packet)
+    }
+
     fn write_packet(&self, packet: CSVPacketWrapper, writer: &mut Writer<File>) -> Result<()> {
         // Millisecond precision
         let timestamp = format!("{}", chrono::offset::Local::now().format("%Y%m%d-%H%M%S%3f"));
+        let [packet, snr, rssi, frequency_error] = Self::packet_columns(packet);
         writer.write_record([
             timestamp,
-            format!("{:?}", packet),
+            packet,
+            snr,
+            rssi,
+            frequency_error,
             format!("{:?}", self.lora_config.radio_config.bandwidth),
             format!("{:?}", self.lora_config.radio_config.coding_rate),
             format!("{:?}", self.lora_config.radio_config.spreading_factor),
@@ -69,7 +99,7 @@ impl CSVWriter {
         let mut writer = self.init_writer()?;
 
         // Headers 
-        writer.write_record(["Timestamp", "Packet", "Bandwidth", "Coding rate", "Spreading factor", "TX power"])?;
+        writer.write_record(["Timestamp", "Packet", "SNR", "RSSI", "Frequency error", "Bandwidth", "Coding rate", "Spreading factor", "TX power"])?;
 
         loop {
             // Blocks until it gets a packet
@@ -84,10 +114,8 @@ impl CSVWriter {
 mod tests {
     use super::*;
     use regex::Regex;
-    use log::error;
-    use std::sync::mpsc::channel;
 
-    use crate::packet::{Data, DataType};
+    use crate::packet::{Data, DataType, Metadata};
 
     #[test]
     fn generate_csv_filename_correct() {
@@ -148,4 +176,50 @@ mod tests {
 
         assert!(csv_writer.write_packet(packet, &mut writer).is_ok());
     }
+
+    #[test]
+    fn write_packet_received_ok() {
+        let lora_config = Config::from_file("./conf.toml".to_string()).unwrap().lora_config.unwrap();
+        let mut csv_writer = CSVWriter::new(&lora_config);
+        // Different path for testing purposes
+        csv_writer.path = PathBuf::from("./tests/rusty-beagle-csv/");
+        let mut writer = csv_writer.init_writer().unwrap();
+        let packet = CSVPacketWrapper::Received(PacketWrapper {
+            packet: Packet {
+                version: 1,
+                id: 1,
+                msg_id: 1,
+                msg_count: 1,
+                data_type: DataType::Sms,
+                data: Data::Sms(String::from("Buzzverse"))
+            },
+            metadata: Metadata { snr: -6.5, rssi: -121, frequency_error: -1530 },
+        });
+
+        assert!(csv_writer.write_packet(packet, &mut writer).is_ok());
+    }
+
+    #[test]
+    fn packet_columns_correct() {
+        let packet = Packet {
+            version: 1,
+            id: 1,
+            msg_id: 1,
+            msg_count: 1,
+            data_type: DataType::Sms,
+            data: Data::Sms(String::from("Buzzverse"))
+        };
+        let sent = CSVWriter::packet_columns(CSVPacketWrapper::Packet(packet.clone()));
+        assert_eq!(format!("{:?}", CSVPacketWrapper::Packet(packet.clone())), sent[0]);
+        assert_eq!(["", "", ""], sent[1..]);
+
+        let received = CSVWriter::packet_columns(CSVPacketWrapper::Received(PacketWrapper {
+            packet,
+            metadata: Metadata { snr: -6.5, rssi: -121, frequency_error: -1530 },
+        }));
+        assert_eq!(sent[0], received[0]);
+        assert_eq!(["-6.5", "-121", "-1530"], received[1..]);
+
+        assert_eq!(["CRC_ERROR", "", "", ""], CSVWriter::packet_columns(CSVPacketWrapper::CRC_ERROR));
+    }
 }
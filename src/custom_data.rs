@@ -0,0 +1,202 @@
+use crate::config::{CustomDataTypeConfig, Endianness, IntegerType};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+
+/*
+ * Data types declared in the config: the body is a sequence of integers of the declared
+ * types, each turned into a value with the scale and offset of its field. New sensors
+ * only need a config entry on the receiver, not a new Data variant
+ */
+
+#[derive(Debug, Clone, Default)]
+pub struct CustomDataTypes {
+    /// Declarations by data type byte
+    types: HashMap<u8, CustomDataTypeConfig>,
+}
+
+impl CustomDataTypes {
+    pub fn new(custom_data_types: &[CustomDataTypeConfig]) -> Self {
+        Self {
+            types: custom_data_types
+                .iter()
+                .map(|custom_data_type| (custom_data_type.id, custom_data_type.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn contains(&self, id: u8) -> bool {
+        self.types.contains_key(&id)
+    }
+
+    /*
+     * Decodes the body of a packet of the custom data type `id`
+     */
+    pub fn decode(&self, id: u8, body: &[u8]) -> Result<CustomData> {
+        let custom_data_type = self
+            .types
+            .get(&id)
+            .ok_or_else(|| anyhow!("Unknown custom data type {}", id))
+            .context("CustomDataTypes::decode")?;
+        if body.len() != custom_data_type.size() {
+            return Err(anyhow!(
+                "Incorrect length of {}, was {}, should be {}",
+                custom_data_type.name,
+                body.len(),
+                custom_data_type.size()
+            ))
+            .context("CustomDataTypes::decode");
+        }
+
+        let mut values = Vec::new();
+        let mut start = 0;
+        for field in &custom_data_type.fields {
+            let size = field.field_type.size();
+            let raw = integer_to_i128(&body[start..start + size], field.field_type, field.endianness);
+            // unscaled integers are kept exact, 64-bit ones don't fit in a f64
+            let value = if field.scale == 1.0 && field.offset == 0.0 {
+                CustomNumber::Integer(raw)
+            } else {
+                CustomNumber::Real(raw as f64 * field.scale + field.offset)
+            };
+            values.push(CustomValue {
+                name: field.name.clone(),
+                value,
+                unit: field.unit.clone(),
+            });
+            start += size;
+        }
+
+        Ok(CustomData {
+            type_id: id,
+            name: custom_data_type.name.clone(),
+            data: body.to_vec(),
+            values,
+        })
+    }
+}
+
+fn integer_to_i128(bytes: &[u8], integer_type: IntegerType, endianness: Endianness) -> i128 {
+    let mut le_bytes = bytes.to_vec();
+    if endianness == Endianness::Big {
+        le_bytes.reverse();
+    }
+
+    // sign extension up to 8 bytes
+    let fill = if integer_type.is_signed() && le_bytes[le_bytes.len() - 1] & 0x80 != 0 {
+        0xFF
+    } else {
+        0x00
+    };
+    le_bytes.resize(8, fill);
+    let array: [u8; 8] = le_bytes.try_into().unwrap();
+
+    if integer_type.is_signed() {
+        i64::from_le_bytes(array) as i128
+    } else {
+        u64::from_le_bytes(array) as i128
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CustomData {
+    pub type_id: u8,
+    pub name: String,
+    /// The body as received, sent again as it is
+    pub data: Vec<u8>,
+    pub values: Vec<CustomValue>,
+}
+
+// the values follow from the body
+impl Hash for CustomData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.type_id.hash(state);
+        self.data.hash(state);
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CustomValue {
+    pub name: String,
+    pub value: CustomNumber,
+    pub unit: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum CustomNumber {
+    /// Any u64 or i64
+    Integer(i128),
+    Real(f64),
+}
+
+impl Display for CustomNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CustomNumber::Integer(value) => write!(f, "{}", value),
+            CustomNumber::Real(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn custom_data_types() -> CustomDataTypes {
+        let config = Config::from_file("./tests/configs/custom_data_types_conf.toml".to_string()).unwrap();
+        CustomDataTypes::new(&config.lora_config.unwrap().custom_data_types)
+    }
+
+    #[test]
+    fn decode_correct() {
+        // soil: u16 LE moisture * 0.1 %, i16 BE temperature * 0.01 °C, u8 battery * 20 mV + 2000 mV
+        let body = [0x9F, 0x01, 0xFB, 0xE6, 0x2D];
+        let data = custom_data_types().decode(200, &body).unwrap();
+        assert_eq!(200, data.type_id);
+        assert_eq!("SOIL", data.name);
+        assert_eq!(body.to_vec(), data.data);
+
+        let values: Vec<(&str, CustomNumber, &str)> = data
+            .values
+            .iter()
+            .map(|value| (value.name.as_str(), value.value, value.unit.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("moisture", CustomNumber::Real(41.5), "%"),
+                ("temperature", CustomNumber::Real(-10.5), "°C"),
+                ("battery", CustomNumber::Real(2900.0), "mV")
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn decode_wide_integers() {
+        // counter: i32 LE, u64 BE
+        let mut body = (-2i32).to_le_bytes().to_vec();
+        body.extend(u64::MAX.to_be_bytes());
+        let data = custom_data_types().decode(201, &body).unwrap();
+        assert_eq!(CustomNumber::Integer(-2), data.values[0].value);
+        // exact, not rounded to a f64
+        assert_eq!(CustomNumber::Integer(u64::MAX as i128), data.values[1].value);
+        assert_eq!("18446744073709551615", data.values[1].value.to_string());
+
+        let mut body = i32::MAX.to_le_bytes().to_vec();
+        body.extend((u64::MAX - 1).to_be_bytes());
+        let data = custom_data_types().decode(201, &body).unwrap();
+        assert_eq!(CustomNumber::Integer(i32::MAX as i128), data.values[0].value);
+        assert_eq!("18446744073709551614", data.values[1].value.to_string());
+    }
+
+    #[test]
+    fn decode_incorrect() {
+        let custom_data_types = custom_data_types();
+        assert!(custom_data_types.decode(200, &[0x9F, 0x01, 0xFB, 0xE6]).is_err());
+        assert!(custom_data_types.decode(200, &[0x9F, 0x01, 0xFB, 0xE6, 0x2D, 0x00]).is_err());
+        assert!(custom_data_types.decode(202, &[0x00]).is_err());
+    }
+}
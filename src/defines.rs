@@ -1,4 +1,17 @@
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+/// Crystal oscillator frequency
+pub const FXOSC: u64 = 32_000_000;
+
+/*
+ * Anything addressable over SPI, LoRa and FSK/OOK modes have separate register maps
+ */
+pub trait Register: Copy + Debug {
+    fn address(&self) -> u8;
+}
+
 /*
  * Register definitions
  */
@@ -11,6 +24,7 @@ pub enum SX1278LoRaRegister {
     FRF_MID = 0x07,
     FRF_LSB = 0x08,
     PA_CONFIG = 0x09,
+    OCP = 0x0B,
     LNA = 0x0C,
     FIFO_ADDR_PTR = 0x0D,
     FIFO_TX_BASE_ADDR = 0x0E,
@@ -18,14 +32,20 @@ pub enum SX1278LoRaRegister {
     FIFO_RX_CURRENT_ADDR = 0x10,
     IRQ_FLAGS = 0x12,
     RX_NB_BYTES = 0x13,
+    HOP_CHANNEL = 0x1C,
     PKT_SNR_VALUE = 0x19,
     PKT_RSSI_VALUE = 0x1A,
     MODEM_CONFIG_1 = 0x1D,
     MODEM_CONFIG_2 = 0x1E,
+    SYMB_TIMEOUT_LSB = 0x1F,
     PREAMBLE_MSB = 0x20,
     PREAMBLE_LSB = 0x21,
     PAYLOAD_LENGTH = 0x22,
+    HOP_PERIOD = 0x24,
     MODEM_CONFIG_3 = 0x26,
+    FEI_MSB = 0x28,
+    FEI_MID = 0x29,
+    FEI_LSB = 0x2A,
     RSSI_WIDEBAND = 0x2C,
     DETECTION_OPTIMIZE = 0x31,
     DETECTION_THRESHOLD = 0x37,
@@ -34,6 +54,153 @@ pub enum SX1278LoRaRegister {
     DIO_MAPPING_1 = 0x40,
     DIO_MAPPING_2 = 0x41,
     VERSION = 0x42,
+    PA_DAC = 0x4D,
+}
+
+impl Register for SX1278LoRaRegister {
+    fn address(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/*
+ * FSK/OOK register definitions, only the ones that differ from the LoRa map
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278FskRegister {
+    BITRATE_MSB = 0x02,
+    BITRATE_LSB = 0x03,
+    FDEV_MSB = 0x04,
+    FDEV_LSB = 0x05,
+    PA_RAMP = 0x0A,
+    RX_CONFIG = 0x0D,
+    RSSI_VALUE = 0x11,
+    RX_BW = 0x12,
+    AFC_BW = 0x13,
+    FEI_MSB = 0x1D,
+    FEI_LSB = 0x1E,
+    PREAMBLE_DETECT = 0x1F,
+    PREAMBLE_MSB = 0x25,
+    PREAMBLE_LSB = 0x26,
+    SYNC_CONFIG = 0x27,
+    SYNC_VALUE_1 = 0x28,
+    PACKET_CONFIG_1 = 0x30,
+    PACKET_CONFIG_2 = 0x31,
+    PAYLOAD_LENGTH = 0x32,
+    FIFO_THRESH = 0x35,
+    IRQ_FLAGS_1 = 0x3E,
+    IRQ_FLAGS_2 = 0x3F,
+    BITRATE_FRAC = 0x5D,
+}
+
+impl Register for SX1278FskRegister {
+    fn address(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/*
+ * FSK/OOK register values
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278FskRxConfig {
+    AGC_AUTO_ON = 0x08,
+    /// RxTrigger on PreambleDetect
+    RX_TRIGGER_PREAMBLE_DETECT = 0x06,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278FskSyncConfig {
+    /// AutoRestartRxMode on, without waiting for the PLL to lock
+    AUTO_RESTART_RX = 0x40,
+    SYNC_ON = 0x10,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278FskPacketConfig1 {
+    VARIABLE_LENGTH = 0x80,
+    /// DcFree whitening
+    WHITENING = 0x40,
+    CRC_ON = 0x10,
+    /// Packets with a CRC error still raise PayloadReady
+    CRC_AUTO_CLEAR_OFF = 0x08,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278FskPacketConfig2 {
+    PACKET_MODE = 0x40,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278FskConfig {
+    /// PREAMBLE_DETECT: on, 2 bytes, 10 chips tolerance
+    PREAMBLE_DETECTOR_ON = 0xAA,
+    /// FIFO_THRESH: TxStartCondition FifoNotEmpty
+    TX_START_FIFO_NOT_EMPTY = 0x80,
+    /// PA_RAMP: 40 us ramp
+    PA_RAMP_40_US = 0x09,
+}
+
+/*
+ * FSK/OOK IRQ_FLAGS_2 masks
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278FskIRQMask {
+    IRQ_PACKET_SENT_MASK = 0x08,
+    IRQ_PAYLOAD_READY_MASK = 0x04,
+    IRQ_CRC_OK_MASK = 0x02,
+}
+
+/*
+ * Modem used by the transceiver
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Modulation {
+    LoRa,
+    FSK,
+    OOK,
+}
+
+/*
+ * Gaussian filter applied to FSK, the filter's bandwidth-time product
+ */
+#[allow(non_camel_case_types)]
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum GaussianFilter {
+    BT_1_0 = 1,
+    BT_0_5 = 2,
+    BT_0_3 = 3,
+}
+
+/*
+ * Returns the RX_BW value (RxBwMant << 3 | RxBwExp) of the narrowest channel filter
+ * that is at least `bandwidth` Hz wide, RxBw = FXOSC / (RxBwMant * 2^(RxBwExp + 2)),
+ * OOK filters are half as wide
+ */
+pub fn fsk_rx_bandwidth(modulation: Modulation, bandwidth: u32) -> Result<(u8, u32)> {
+    let shift = match modulation {
+        Modulation::OOK => 3,
+        _ => 2,
+    };
+    let mut best: Option<(u8, u32)> = None;
+    for (mantissa_code, mantissa) in [(0u8, 16u64), (1, 20), (2, 24)] {
+        for exponent in 1u8..=7 {
+            let filter = (FXOSC / (mantissa << (exponent + shift))) as u32;
+            if filter >= bandwidth && best.is_none_or(|(_, b)| filter < b) {
+                best = Some(((mantissa_code << 3) | exponent, filter));
+            }
+        }
+    }
+
+    best.ok_or_else(|| anyhow!("rx_bandwidth {} Hz is wider than the widest channel filter", bandwidth))
 }
 
 /*
@@ -43,11 +210,39 @@ pub enum SX1278LoRaRegister {
 #[derive(Debug, Clone, Copy)]
 pub enum SX1278LoRaMode {
     LONG_RANGE = 0x80,
+    /// ModulationType OOK, FSK when both this and LONG_RANGE are clear
+    MODULATION_OOK = 0x20,
+    LOW_FREQUENCY_MODE_ON = 0x08,
     SLEEP = 0x00,
     STDBY = 0x01,
     TX = 0x03,
     RX_CONTINUOUS = 0x05,
     RX_SINGLE = 0x06,
+    CAD = 0x07,
+}
+
+/*
+ * Frequency bands, each one served by a separate RF port of the chip
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrequencyBand {
+    /// 137-525 MHz, RFI_LF/RFO_LF port
+    LF,
+    /// 862-1020 MHz, RFI_HF/RFO_HF port
+    HF,
+}
+
+impl FrequencyBand {
+    pub fn from_frequency(frequency: u64) -> Result<Self> {
+        match frequency {
+            137_000_000..=525_000_000 => Ok(Self::LF),
+            862_000_000..=1_020_000_000 => Ok(Self::HF),
+            _ => Err(anyhow!(
+                "Frequency {} Hz is outside of the supported bands (LF: 137-525 MHz, HF: 862-1020 MHz)",
+                frequency
+            )),
+        }
+    }
 }
 
 /*
@@ -57,6 +252,86 @@ pub enum SX1278LoRaMode {
 #[derive(Debug, Clone, Copy)]
 pub enum SX1278PAConfiguration {
     PA_BOOST = 0x80,
+    OCP_ON = 0x20,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278PADac {
+    /// Default, up to +17 dBm on PA_BOOST
+    PA_DAC_DEFAULT = 0x84,
+    /// +20 dBm on PA_BOOST, at most 1% duty cycle
+    PA_DAC_HIGH_POWER = 0x87,
+}
+
+/*
+ * PA output pin, which one is wired to the antenna depends on the module
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PaOutput {
+    /// -4 to +15 dBm, limited by max_power
+    RFO,
+    /// +2 to +17 dBm, +18 to +20 dBm in high power mode
+    PA_BOOST,
+}
+
+impl PaOutput {
+    /*
+     * Returns (PA_CONFIG, PA_DAC) values for the requested output power in dBm.
+     * RFO: Pout = Pmax - (15 - OutputPower), Pmax = 10.8 + 0.6 * MaxPower
+     * PA_BOOST: Pout = 17 - (15 - OutputPower), or 20 - (15 - OutputPower) with PA_DAC high power
+     */
+    pub fn register_values(&self, tx_power: i8, max_power: u8) -> Result<(u8, SX1278PADac)> {
+        match self {
+            Self::RFO => {
+                if max_power > 7 {
+                    return Err(anyhow!("max_power {} out of range, should be within 0-7", max_power));
+                }
+                let pmax = 10.8 + 0.6 * max_power as f32;
+                let output_power = (tx_power as f32 - pmax + 15.0).round();
+                if !(0.0..=15.0).contains(&output_power) {
+                    return Err(anyhow!(
+                        "tx_power {} dBm not available on RFO with max_power {} ({:.1} to {:.1} dBm)",
+                        tx_power,
+                        max_power,
+                        pmax - 15.0,
+                        pmax
+                    ));
+                }
+                Ok(((max_power << 4) | output_power as u8, SX1278PADac::PA_DAC_DEFAULT))
+            }
+            Self::PA_BOOST => match tx_power {
+                2..=17 => Ok((
+                    SX1278PAConfiguration::PA_BOOST as u8 | (tx_power - 2) as u8,
+                    SX1278PADac::PA_DAC_DEFAULT,
+                )),
+                18..=20 => Ok((
+                    SX1278PAConfiguration::PA_BOOST as u8 | (tx_power - 5) as u8,
+                    SX1278PADac::PA_DAC_HIGH_POWER,
+                )),
+                _ => Err(anyhow!(
+                    "tx_power {} dBm not available on PA_BOOST (2 to 20 dBm)",
+                    tx_power
+                )),
+            },
+        }
+    }
+}
+
+/*
+ * Over-current protection trim, Imax = 45 + 5 * OcpTrim up to 120 mA,
+ * Imax = -30 + 10 * OcpTrim from 130 to 240 mA
+ */
+pub fn ocp_trim(current_ma: u8) -> Result<u8> {
+    match current_ma {
+        45..=120 if current_ma.is_multiple_of(5) => Ok((current_ma - 45) / 5),
+        130..=240 if current_ma.is_multiple_of(10) => Ok(((current_ma as u16 + 30) / 10) as u8),
+        _ => Err(anyhow!(
+            "ocp_trim_ma {} not available, should be 45-120 mA in 5 mA steps or 130-240 mA in 10 mA steps",
+            current_ma
+        )),
+    }
 }
 
 /*
@@ -65,17 +340,40 @@ pub enum SX1278PAConfiguration {
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy)]
 pub enum SX1278IRQMask {
+    IRQ_CAD_DETECTED_MASK = 0x01,
+    IRQ_FHSS_CHANGE_CHANNEL_MASK = 0x02,
+    IRQ_CAD_DONE_MASK = 0x04,
     IRQ_TX_DONE_MASK = 0x08,
     IRQ_RX_DONE_MASK = 0x40,
     IRQ_PAYLOAD_CRC_ERROR = 0x20,
-    PA_OUTPUT_RFO_PIN = 0,
-    PA_OUTPUT_PA_BOOST_PIN = 1,
+    IRQ_RX_TIMEOUT_MASK = 0x80,
 }
 
 impl SX1278IRQMask {
     pub const IRQ_PAYLOAD_CRC_ERROR_MASK: SX1278IRQMask = SX1278IRQMask::IRQ_PAYLOAD_CRC_ERROR;
 }
 
+/*
+ * DIO0 mappings, bits 7-6 of DIO_MAPPING_1
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278Dio0Mapping {
+    RX_DONE = 0b00,
+    TX_DONE = 0b01,
+    CAD_DONE = 0b10,
+}
+
+/*
+ * DIO1 mappings, bits 5-4 of DIO_MAPPING_1
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX1278Dio1Mapping {
+    RX_TIMEOUT = 0b00,
+    FHSS_CHANGE_CHANNEL = 0b01,
+}
+
 /*
  * Lora delays
  */
@@ -113,6 +411,60 @@ pub enum Bandwidth {
     bandwidth_500kHz = 9,
 }
 
+impl Bandwidth {
+    /*
+     * SX126x SetModulationParams bandwidth codes, not ordered like the SX127x ones
+     */
+    pub fn sx126x_value(&self) -> u8 {
+        match self {
+            Self::bandwidth_7_8kHz => 0x00,
+            Self::bandwidth_10_4kHz => 0x08,
+            Self::bandwidth_15_6kHz => 0x01,
+            Self::bandwidth_20_8kHz => 0x09,
+            Self::bandwidth_31_25kHz => 0x02,
+            Self::bandwidth_41_7kHz => 0x0A,
+            Self::bandwidth_62_5kHz => 0x03,
+            Self::bandwidth_125kHz => 0x04,
+            Self::bandwidth_250kHz => 0x05,
+            Self::bandwidth_500kHz => 0x06,
+        }
+    }
+
+    pub fn hz(&self) -> u32 {
+        match self {
+            Self::bandwidth_7_8kHz => 7_800,
+            Self::bandwidth_10_4kHz => 10_400,
+            Self::bandwidth_15_6kHz => 15_600,
+            Self::bandwidth_20_8kHz => 20_800,
+            Self::bandwidth_31_25kHz => 31_250,
+            Self::bandwidth_41_7kHz => 41_700,
+            Self::bandwidth_62_5kHz => 62_500,
+            Self::bandwidth_125kHz => 125_000,
+            Self::bandwidth_250kHz => 250_000,
+            Self::bandwidth_500kHz => 500_000,
+        }
+    }
+
+    /*
+     * Inverse of the MODEM_CONFIG_1 bits 7-4 encoding
+     */
+    pub fn from_register(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::bandwidth_7_8kHz),
+            1 => Ok(Self::bandwidth_10_4kHz),
+            2 => Ok(Self::bandwidth_15_6kHz),
+            3 => Ok(Self::bandwidth_20_8kHz),
+            4 => Ok(Self::bandwidth_31_25kHz),
+            5 => Ok(Self::bandwidth_41_7kHz),
+            6 => Ok(Self::bandwidth_62_5kHz),
+            7 => Ok(Self::bandwidth_125kHz),
+            8 => Ok(Self::bandwidth_250kHz),
+            9 => Ok(Self::bandwidth_500kHz),
+            _ => Err(anyhow!("Invalid bandwidth register value {}", value)),
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
@@ -127,7 +479,8 @@ pub enum CodingRate {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub enum SpreadingFactor {
-    // _64   = 6, //TODO implement later, related issue [RB-11]
+    /// Requires implicit header mode
+    spreading_factor_64 = 6,
     spreading_factor_128 = 7,
     spreading_factor_256 = 8,
     spreading_factor_512 = 9,
@@ -136,9 +489,199 @@ pub enum SpreadingFactor {
     spreading_factor_4096 = 12,
 }
 
-#[allow(non_camel_case_types)]
+/*
+ * LNA gain, used only when AGC is disabled
+ */
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum LnaGain {
+    /// Maximum gain
+    G1 = 1,
+    G2 = 2,
+    G3 = 3,
+    G4 = 4,
+    G5 = 5,
+    /// Minimum gain
+    G6 = 6,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Chip {
+    SX1276,
+    SX1277,
     SX1278,
+    SX1279,
+    /// HopeRF module built around the SX1276, only the HF port is routed
+    RFM95,
+    SX1262,
+}
+
+/*
+ * Chips sharing a driver, the SX127x family is register based,
+ * the SX126x family is command based with a BUSY pin
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChipFamily {
+    SX127x,
+    SX126x,
+}
+
+impl Chip {
+    pub fn family(&self) -> ChipFamily {
+        match self {
+            Self::SX1276 | Self::SX1277 | Self::SX1278 | Self::SX1279 | Self::RFM95 => ChipFamily::SX127x,
+            Self::SX1262 => ChipFamily::SX126x,
+        }
+    }
+
+    /*
+     * Expected value of the VERSION register, the SX126x family has none
+     */
+    pub fn silicon_version(&self) -> Option<u8> {
+        match self.family() {
+            ChipFamily::SX127x => Some(0x12),
+            ChipFamily::SX126x => None,
+        }
+    }
+
+    /*
+     * Frequency ranges in Hz the chip (or module) is specified for
+     */
+    pub fn frequency_ranges(&self) -> &'static [(u64, u64)] {
+        match self {
+            Self::SX1276 | Self::SX1277 => &[(137_000_000, 525_000_000), (862_000_000, 1_020_000_000)],
+            Self::SX1278 => &[(137_000_000, 525_000_000)],
+            Self::SX1279 => &[(137_000_000, 525_000_000), (862_000_000, 960_000_000)],
+            Self::RFM95 => &[(862_000_000, 1_020_000_000)],
+            Self::SX1262 => &[(150_000_000, 960_000_000)],
+        }
+    }
+
+    pub fn check_frequency(&self, frequency: u64) -> Result<()> {
+        let ranges = self.frequency_ranges();
+        if ranges.iter().any(|(low, high)| (*low..=*high).contains(&frequency)) {
+            return Ok(());
+        }
+        let ranges: Vec<String> = ranges
+            .iter()
+            .map(|(low, high)| format!("{}-{} MHz", low / 1_000_000, high / 1_000_000))
+            .collect();
+        Err(anyhow!(
+            "Frequency {} Hz is not supported by {:?} ({})",
+            frequency,
+            self,
+            ranges.join(", ")
+        ))
+    }
+
+    pub fn max_spreading_factor(&self) -> SpreadingFactor {
+        match self {
+            Self::SX1277 => SpreadingFactor::spreading_factor_512,
+            _ => SpreadingFactor::spreading_factor_4096,
+        }
+    }
+}
+
+/*
+ * SX126x SPI commands, the opcode is followed by the command parameters
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX126xCommand {
+    SET_SLEEP = 0x84,
+    SET_STANDBY = 0x80,
+    SET_TX = 0x83,
+    SET_RX = 0x82,
+    SET_REGULATOR_MODE = 0x96,
+    CALIBRATE = 0x89,
+    CALIBRATE_IMAGE = 0x98,
+    SET_PA_CONFIG = 0x95,
+    WRITE_REGISTER = 0x0D,
+    READ_REGISTER = 0x1D,
+    WRITE_BUFFER = 0x0E,
+    READ_BUFFER = 0x1E,
+    SET_DIO_IRQ_PARAMS = 0x08,
+    GET_IRQ_STATUS = 0x12,
+    CLEAR_IRQ_STATUS = 0x02,
+    SET_DIO2_AS_RF_SWITCH_CTRL = 0x9D,
+    SET_RF_FREQUENCY = 0x86,
+    SET_PACKET_TYPE = 0x8A,
+    SET_TX_PARAMS = 0x8E,
+    SET_MODULATION_PARAMS = 0x8B,
+    SET_PACKET_PARAMS = 0x8C,
+    SET_BUFFER_BASE_ADDRESS = 0x8F,
+    GET_STATUS = 0xC0,
+    GET_RX_BUFFER_STATUS = 0x13,
+    GET_PACKET_STATUS = 0x14,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX126xRegister {
+    /// 16 byte ASCII version string, e.g. "SX1261 V2D 2D02"
+    VERSION_STRING = 0x0320,
+    LORA_SYNC_WORD_MSB = 0x0740,
+    RX_GAIN = 0x08AC,
+    OCP = 0x08E7,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX126xConfig {
+    STANDBY_RC = 0x00,
+    PACKET_TYPE_LORA = 0x01,
+    /// Sleep keeping the configuration
+    SLEEP_WARM_START = 0x04,
+    CALIBRATE_ALL = 0x7F,
+    RX_GAIN_POWER_SAVING = 0x94,
+    RX_GAIN_BOOSTED = 0x96,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX126xPowerConfig {
+    REGULATOR_DC_DC = 0x01,
+    /// 200 us PA ramp
+    RAMP_200_US = 0x04,
+}
+
+/*
+ * SX126x IRQ status bits
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum SX126xIrq {
+    TX_DONE = 0x0001,
+    RX_DONE = 0x0002,
+    HEADER_ERR = 0x0020,
+    CRC_ERR = 0x0040,
+    TIMEOUT = 0x0200,
+}
+
+/*
+ * Chip mode field (bits 6:4) of the status byte
+ */
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SX126xChipMode {
+    STBY_RC = 0x2,
+    STBY_XOSC = 0x3,
+    FS = 0x4,
+    RX = 0x5,
+    TX = 0x6,
+}
+
+impl SX126xChipMode {
+    pub fn from_status(status: u8) -> Result<Self> {
+        match (status >> 4) & 0x07 {
+            0x2 => Ok(Self::STBY_RC),
+            0x3 => Ok(Self::STBY_XOSC),
+            0x4 => Ok(Self::FS),
+            0x5 => Ok(Self::RX),
+            0x6 => Ok(Self::TX),
+            _ => Err(anyhow!("Invalid chip mode in status 0x{:02X}", status)),
+        }
+    }
 }
@@ -0,0 +1,394 @@
+use crate::config::EncryptionConfig;
+use crate::packet::{DataType, META_DATA_SIZE, PACKET_DATA_TYPE_IDX, PACKET_ID_IDX};
+use anyhow::{anyhow, Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+/*
+ * Authenticated encryption of the packet body with ChaCha20-Poly1305. A sealed frame is
+ * the packet header in cleartext, the frame counter of the sender and the encrypted body
+ * followed by the tag. The header is authenticated along with the body, the nonce is
+ * the header followed by the frame counter, so it never repeats for a key as long as
+ * the frame counter doesn't
+ */
+
+pub const FRAME_COUNTER_SIZE: usize = 4;
+pub const TAG_SIZE: usize = 16;
+/// Bytes a sealed frame is longer than the packet
+pub const ENCRYPTION_OVERHEAD: usize = FRAME_COUNTER_SIZE + TAG_SIZE;
+/// Frame counters handed out between two writes of the frame counter file
+const FRAME_COUNTER_BLOCK: u32 = 64;
+
+#[derive(Deserialize)]
+struct KeyFile {
+    device: Vec<DeviceKey>,
+}
+
+#[derive(Deserialize)]
+struct DeviceKey {
+    id: u8,
+    /// 256 bits, hex encoded
+    key: String,
+}
+
+pub struct Encryption {
+    /// Ciphers by device id
+    keys: HashMap<u8, ChaCha20Poly1305>,
+    frame_counter: u32,
+    /// The frame counter file holds this value, it is moved ahead before the counter reaches it
+    reserved_until: u32,
+    frame_counter_file: String,
+    /// Last frame counter accepted by (device id, ACK), packets and the ACKs
+    /// for them come from different senders. The received counter file holds the same values
+    last_received: HashMap<(u8, bool), u32>,
+    received_counter_file: String,
+}
+
+impl Encryption {
+    pub fn from_config(config: &EncryptionConfig) -> Result<Self> {
+        let key_file = fs::read_to_string(&config.key_file)
+            .with_context(|| format!("Failed to read {}", config.key_file))
+            .context("Encryption::from_config")?;
+        let key_file: KeyFile = toml::from_str(&key_file).context("Encryption::from_config")?;
+
+        let mut keys = HashMap::new();
+        for device in key_file.device {
+            let key = parse_key(&device.key)
+                .with_context(|| format!("Invalid key of device {}", device.id))
+                .context("Encryption::from_config")?;
+            keys.insert(device.id, ChaCha20Poly1305::new(&key));
+        }
+
+        // a missing file means nothing was sent with these keys yet
+        let frame_counter = match fs::read_to_string(&config.frame_counter_file) {
+            Ok(value) => value
+                .trim()
+                .parse::<u32>()
+                .with_context(|| format!("Invalid frame counter in {}", config.frame_counter_file))
+                .context("Encryption::from_config")?,
+            Err(_) => 0,
+        };
+
+        // a missing file means nothing was received with these keys yet
+        let last_received = match fs::read_to_string(&config.received_counter_file) {
+            Ok(value) => parse_received_counters(&value)
+                .with_context(|| format!("Invalid received counters in {}", config.received_counter_file))
+                .context("Encryption::from_config")?,
+            Err(_) => HashMap::new(),
+        };
+
+        let mut encryption = Self {
+            keys,
+            frame_counter,
+            reserved_until: frame_counter,
+            frame_counter_file: config.frame_counter_file.clone(),
+            last_received,
+            received_counter_file: config.received_counter_file.clone(),
+        };
+        encryption.reserve_frame_counters().context("Encryption::from_config")?;
+        Ok(encryption)
+    }
+
+    /*
+     * Writes the end of the next block of frame counters to the file before any of them is used,
+     * so after a restart the counter continues above every value that could have been sent
+     */
+    fn reserve_frame_counters(&mut self) -> Result<()> {
+        self.reserved_until = self.frame_counter.saturating_add(FRAME_COUNTER_BLOCK);
+        fs::write(&self.frame_counter_file, self.reserved_until.to_string())
+            .with_context(|| format!("Failed to write {}", self.frame_counter_file))
+            .context("Encryption::reserve_frame_counters")
+    }
+
+    /*
+     * Writes the frame counter accepted from `sender` to the file before the frame is accepted,
+     * so after a restart every frame received before is rejected and every later one accepted
+     */
+    fn save_received_counter(&mut self, sender: (u8, bool), frame_counter: u32) -> Result<()> {
+        self.last_received.insert(sender, frame_counter);
+
+        let mut senders: Vec<_> = self.last_received.iter().collect();
+        senders.sort();
+        let contents: String = senders
+            .iter()
+            .map(|((id, ack), last)| format!("{} {} {}\n", id, if *ack { "ack" } else { "packet" }, last))
+            .collect();
+        fs::write(&self.received_counter_file, contents)
+            .with_context(|| format!("Failed to write {}", self.received_counter_file))
+            .context("Encryption::save_received_counter")
+    }
+
+    fn cipher(&self, id: u8) -> Result<&ChaCha20Poly1305> {
+        self.keys
+            .get(&id)
+            .ok_or_else(|| anyhow!("No key for device {}", id))
+            .context("Encryption::cipher")
+    }
+
+    /*
+     * Encrypts the body of a serialized packet with the key of its device id
+     */
+    pub fn seal(&mut self, bytes: &[u8]) -> Result<Vec<u8>> {
+        if bytes.len() < META_DATA_SIZE {
+            return Err(anyhow!("Incorrect length, was {}", bytes.len())).context("Encryption::seal");
+        }
+        if self.frame_counter == u32::MAX {
+            return Err(anyhow!("Frame counter exhausted, new keys are needed")).context("Encryption::seal");
+        }
+        if self.frame_counter >= self.reserved_until {
+            self.reserve_frame_counters().context("Encryption::seal")?;
+        }
+        let frame_counter = self.frame_counter;
+        self.frame_counter += 1;
+
+        let (header, body) = bytes.split_at(META_DATA_SIZE);
+        let cipher = self.cipher(bytes[PACKET_ID_IDX]).context("Encryption::seal")?;
+        let ciphertext = cipher
+            .encrypt(&nonce(header, frame_counter), Payload { msg: body, aad: header })
+            .map_err(|_| anyhow!("Encryption failed"))
+            .context("Encryption::seal")?;
+
+        let mut frame = header.to_vec();
+        frame.extend(frame_counter.to_le_bytes());
+        frame.extend(ciphertext);
+        Ok(frame)
+    }
+
+    /*
+     * Authenticates and decrypts a received frame, returns the serialized packet.
+     * Frames with a frame counter not above the last one from the same sender are replays
+     */
+    pub fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>> {
+        if frame.len() < META_DATA_SIZE + ENCRYPTION_OVERHEAD {
+            return Err(anyhow!(
+                "Incorrect length, was {}, should be at least {}",
+                frame.len(),
+                META_DATA_SIZE + ENCRYPTION_OVERHEAD
+            ))
+            .context("Encryption::open");
+        }
+
+        let id = frame[PACKET_ID_IDX];
+        let (header, rest) = frame.split_at(META_DATA_SIZE);
+        let (frame_counter, ciphertext) = rest.split_at(FRAME_COUNTER_SIZE);
+        let frame_counter = u32::from_le_bytes(frame_counter.try_into().context("Encryption::open")?);
+
+        let cipher = self.cipher(id).context("Encryption::open")?;
+        let body = cipher
+            .decrypt(&nonce(header, frame_counter), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| anyhow!("Frame from device {} failed authentication", id))
+            .context("Encryption::open")?;
+
+        // checked only after authentication, forged frames can't move the counter ahead
+        let sender = (id, frame[PACKET_DATA_TYPE_IDX] == DataType::Ack.to_byte());
+        if let Some(&last) = self.last_received.get(&sender) {
+            if frame_counter <= last {
+                return Err(anyhow!(
+                    "Replayed frame from device {}, frame counter {} is not above {}",
+                    id,
+                    frame_counter,
+                    last
+                ))
+                .context("Encryption::open");
+            }
+        }
+        self.save_received_counter(sender, frame_counter)
+            .context("Encryption::open")?;
+
+        let mut bytes = header.to_vec();
+        bytes.extend(body);
+        Ok(bytes)
+    }
+}
+
+/*
+ * Parses the received counter file, a line "<device id> <packet|ack> <last frame counter>" per sender
+ */
+fn parse_received_counters(contents: &str) -> Result<HashMap<(u8, bool), u32>> {
+    let mut received_counters = HashMap::new();
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [id, kind, last] = fields[..] else {
+            return Err(anyhow!("Incorrect line \"{}\"", line)).context("parse_received_counters");
+        };
+        let ack = match kind {
+            "packet" => false,
+            "ack" => true,
+            _ => return Err(anyhow!("Incorrect sender kind \"{}\"", kind)).context("parse_received_counters"),
+        };
+        received_counters.insert(
+            (id.parse::<u8>().context("parse_received_counters")?, ack),
+            last.parse::<u32>().context("parse_received_counters")?,
+        );
+    }
+    Ok(received_counters)
+}
+
+fn nonce(header: &[u8], frame_counter: u32) -> Nonce {
+    let mut nonce = [0x00; 12];
+    nonce[..META_DATA_SIZE].copy_from_slice(header);
+    nonce[META_DATA_SIZE..META_DATA_SIZE + FRAME_COUNTER_SIZE].copy_from_slice(&frame_counter.to_le_bytes());
+    Nonce::from(nonce)
+}
+
+pub fn parse_key(hex: &str) -> Result<Key> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(anyhow!("Key should be 64 hex digits")).context("parse_key");
+    }
+
+    let mut key = [0x00; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).context("parse_key")?;
+    }
+    Ok(Key::from(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{Data, Packet, BME280};
+
+    fn encryption_config(frame_counter_file: &str) -> EncryptionConfig {
+        EncryptionConfig {
+            key_file: "./tests/configs/keys.toml".to_string(),
+            frame_counter_file: frame_counter_file.to_string(),
+            received_counter_file: format!("{}_received", frame_counter_file),
+        }
+    }
+
+    fn encryption(frame_counter_file: &str) -> Encryption {
+        let config = encryption_config(frame_counter_file);
+        let _ = fs::remove_file(&config.frame_counter_file);
+        let _ = fs::remove_file(&config.received_counter_file);
+        Encryption::from_config(&config).unwrap()
+    }
+
+    fn bme280_bytes(id: u8) -> Vec<u8> {
+        Packet {
+            version: 0x01,
+            id,
+            msg_id: 0x11,
+            msg_count: 1,
+            data_type: DataType::BME280,
+            data: Data::Bme280(BME280 {
+                temperature: 23,
+                humidity: 45,
+                pressure: 67,
+            }),
+        }
+        .to_bytes()
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_key_correct() {
+        let key = parse_key("000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F").unwrap();
+        assert_eq!(0x00, key[0]);
+        assert_eq!(0x1A, key[26]);
+        assert_eq!(0x1F, key[31]);
+
+        assert!(parse_key("000102").is_err());
+        assert!(parse_key("zz0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f").is_err());
+    }
+
+    #[test]
+    fn seal_open_correct() {
+        let mut sender = encryption("/tmp/rusty_beagle_seal_open_counter");
+        let mut receiver = encryption("/tmp/rusty_beagle_seal_open_receiver_counter");
+        let bytes = bme280_bytes(7);
+
+        let frame = sender.seal(&bytes).unwrap();
+        assert_eq!(bytes.len() + ENCRYPTION_OVERHEAD, frame.len());
+        // the header stays readable, the body doesn't
+        assert_eq!(bytes[..META_DATA_SIZE], frame[..META_DATA_SIZE]);
+        assert_eq!([0x00, 0x00, 0x00, 0x00], frame[META_DATA_SIZE..META_DATA_SIZE + FRAME_COUNTER_SIZE]);
+        assert_ne!(bytes[META_DATA_SIZE..], frame[META_DATA_SIZE + FRAME_COUNTER_SIZE..bytes.len() + FRAME_COUNTER_SIZE]);
+        assert_eq!(bytes, receiver.open(&frame).unwrap());
+
+        // the same packet is sealed differently every time
+        let next_frame = sender.seal(&bytes).unwrap();
+        assert_ne!(frame, next_frame);
+        assert_eq!(bytes, receiver.open(&next_frame).unwrap());
+    }
+
+    #[test]
+    fn open_rejects_forged_and_replayed() {
+        let mut sender = encryption("/tmp/rusty_beagle_forged_counter");
+        let mut receiver = encryption("/tmp/rusty_beagle_forged_receiver_counter");
+
+        let frame = sender.seal(&bme280_bytes(7)).unwrap();
+        // a flipped bit in the body or the header
+        let mut forged = frame.clone();
+        forged[META_DATA_SIZE + FRAME_COUNTER_SIZE] ^= 0x01;
+        assert!(receiver.open(&forged).is_err());
+        let mut forged = frame.clone();
+        forged[PACKET_DATA_TYPE_IDX] = DataType::Gps.to_byte();
+        assert!(receiver.open(&forged).is_err());
+
+        assert!(receiver.open(&frame).is_ok());
+        assert!(receiver.open(&frame).is_err());
+
+        // no key for the device
+        assert!(sender.seal(&bme280_bytes(8)).is_err());
+    }
+
+    #[test]
+    fn frame_counter_persisted() {
+        let frame_counter_file = "/tmp/rusty_beagle_persisted_counter";
+        let mut sender = encryption(frame_counter_file);
+        for _ in 0..FRAME_COUNTER_BLOCK + 1 {
+            sender.seal(&bme280_bytes(7)).unwrap();
+        }
+        assert_eq!(
+            (2 * FRAME_COUNTER_BLOCK).to_string(),
+            fs::read_to_string(frame_counter_file).unwrap()
+        );
+
+        // after a restart the counter continues above everything sent before
+        let mut sender = Encryption::from_config(&encryption_config(frame_counter_file)).unwrap();
+        let frame = sender.seal(&bme280_bytes(7)).unwrap();
+        assert_eq!(
+            (2 * FRAME_COUNTER_BLOCK).to_le_bytes(),
+            frame[META_DATA_SIZE..META_DATA_SIZE + FRAME_COUNTER_SIZE]
+        );
+    }
+
+    #[test]
+    fn received_counter_persisted() {
+        let receiver_counter_file = "/tmp/rusty_beagle_persisted_receiver_counter";
+        let mut sender = encryption("/tmp/rusty_beagle_persisted_sender_counter");
+        let mut receiver = encryption(receiver_counter_file);
+
+        let frames: Vec<Vec<u8>> = (0..FRAME_COUNTER_BLOCK + 2)
+            .map(|_| sender.seal(&bme280_bytes(7)).unwrap())
+            .collect();
+        assert!(receiver.open(&frames[0]).is_ok());
+        assert!(receiver.open(&frames[1]).is_ok());
+        assert_eq!(
+            "7 packet 1\n",
+            fs::read_to_string(format!("{}_received", receiver_counter_file)).unwrap()
+        );
+
+        // after a restart the frames received before are rejected, the later ones accepted
+        let mut receiver = Encryption::from_config(&encryption_config(receiver_counter_file)).unwrap();
+        assert!(receiver.open(&frames[0]).is_err());
+        assert!(receiver.open(&frames[1]).is_err());
+        assert!(receiver.open(&frames[FRAME_COUNTER_BLOCK as usize]).is_ok());
+        assert!(receiver.open(&frames[2]).is_err());
+        assert!(receiver.open(&frames[FRAME_COUNTER_BLOCK as usize + 1]).is_ok());
+    }
+
+    #[test]
+    fn parse_received_counters_correct() {
+        let received_counters = parse_received_counters("7 packet 64\n7 ack 128\n\n").unwrap();
+        assert_eq!(Some(&64), received_counters.get(&(7, false)));
+        assert_eq!(Some(&128), received_counters.get(&(7, true)));
+
+        assert!(parse_received_counters("7 packet").is_err());
+        assert!(parse_received_counters("7 reply 64").is_err());
+        assert!(parse_received_counters("256 packet 64").is_err());
+    }
+}
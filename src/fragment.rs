@@ -0,0 +1,307 @@
+use crate::custom_data::CustomDataTypes;
+use crate::packet::{Data, DataType, Fragment, Packet, META_DATA_SIZE, PACKET_SIZE};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/*
+ * Messages longer than PACKET_SIZE are sent as up to MAX_PARTS packets of the Fragment data type
+ * with the same msg_id, msg_count holds the index of the part in the high nibble and the number
+ * of parts in the low one, the first data byte is the data type of the whole message.
+ * The msg_count of other packets is left to the sender
+ */
+
+/// The number of parts has to fit in the low nibble of msg_count
+pub const MAX_PARTS: usize = 15;
+
+/// The header of a part, the data type of the whole message follows the metadata
+pub const FRAGMENT_HEADER_SIZE: usize = META_DATA_SIZE + 1;
+
+pub fn part_index(msg_count: u8) -> u8 {
+    msg_count >> 4
+}
+
+pub fn part_count(msg_count: u8) -> u8 {
+    msg_count & 0x0F
+}
+
+/*
+ * Splits a packet longer than max_packet_size into parts of at most max_packet_size bytes,
+ * shorter packets are returned as they are. max_packet_size is at most PACKET_SIZE,
+ * less where a frame holds fewer bytes
+ */
+pub fn fragment(packet: &Packet, max_packet_size: usize) -> Result<Vec<Packet>> {
+    if max_packet_size <= FRAGMENT_HEADER_SIZE || max_packet_size > PACKET_SIZE {
+        return Err(anyhow!(
+            "Maximum packet size {} out of range, should be within {}-{}",
+            max_packet_size,
+            FRAGMENT_HEADER_SIZE + 1,
+            PACKET_SIZE
+        ))
+        .context("fragment::fragment");
+    }
+    let bytes = packet.to_bytes().context("fragment::fragment")?;
+    if bytes.len() <= max_packet_size {
+        return Ok(vec![packet.clone()]);
+    }
+
+    let chunks: Vec<&[u8]> = bytes[META_DATA_SIZE..]
+        .chunks(max_packet_size - FRAGMENT_HEADER_SIZE)
+        .collect();
+    if chunks.len() > MAX_PARTS {
+        return Err(anyhow!(
+            "Packet of {} bytes needs {} parts, at most {} are allowed",
+            bytes.len(),
+            chunks.len(),
+            MAX_PARTS
+        ))
+        .context("fragment::fragment");
+    }
+
+    let count = chunks.len() as u8;
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| Packet {
+            version: packet.version,
+            id: packet.id,
+            msg_id: packet.msg_id,
+            msg_count: ((index as u8) << 4) | count,
+            data_type: DataType::Fragment,
+            data: Data::Fragment(Fragment {
+                data_type: packet.data_type,
+                data: chunk.to_vec(),
+            }),
+        })
+        .collect())
+}
+
+struct PartialMessage {
+    started: Instant,
+    version: u8,
+    data_type: DataType,
+    parts: Vec<Option<Vec<u8>>>,
+}
+
+pub struct Reassembly {
+    timeout: Duration,
+    /// Messages still missing parts, by (device id, msg_id)
+    messages: HashMap<(u8, u8), PartialMessage>,
+    custom_data_types: CustomDataTypes,
+}
+
+impl Reassembly {
+    pub fn new(timeout: Duration, custom_data_types: CustomDataTypes) -> Self {
+        Self {
+            timeout,
+            messages: HashMap::new(),
+            custom_data_types,
+        }
+    }
+
+    /*
+     * Collects the parts of multi-part messages, returns the whole message once its last part
+     * comes in. Packets sent whole are returned right away. Messages that don't complete
+     * within the timeout are dropped
+     */
+    pub fn add(&mut self, packet: Packet) -> Result<Option<Packet>> {
+        self.drop_expired();
+
+        let Data::Fragment(fragment) = packet.data else {
+            return Ok(Some(packet));
+        };
+
+        let count = part_count(packet.msg_count);
+        let key = (packet.id, packet.msg_id);
+        let message = self.messages.entry(key).or_insert_with(|| PartialMessage {
+            started: Instant::now(),
+            version: packet.version,
+            data_type: fragment.data_type,
+            parts: vec![None; count as usize],
+        });
+        // the msg_id was reused for another message
+        if message.parts.len() != count as usize || message.data_type != fragment.data_type {
+            *message = PartialMessage {
+                started: Instant::now(),
+                version: packet.version,
+                data_type: fragment.data_type,
+                parts: vec![None; count as usize],
+            };
+        }
+        message.parts[part_index(packet.msg_count) as usize] = Some(fragment.data);
+
+        if message.parts.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+        let message = self.messages.remove(&key).unwrap();
+
+        let mut bytes = vec![message.version, packet.id, packet.msg_id, count, message.data_type.to_byte()];
+        for part in message.parts.into_iter().flatten() {
+            bytes.extend(part);
+        }
+        let data = Data::from_bytes(&bytes, &self.custom_data_types).context("Reassembly::add")?;
+
+        Ok(Some(Packet {
+            version: message.version,
+            id: packet.id,
+            msg_id: packet.msg_id,
+            msg_count: count,
+            data_type: message.data_type,
+            data,
+        }))
+    }
+
+    fn drop_expired(&mut self) {
+        let timeout = self.timeout;
+        self.messages.retain(|(id, msg_id), message| {
+            if message.started.elapsed() < timeout {
+                return true;
+            }
+            let received = message.parts.iter().filter(|part| part.is_some()).count();
+            println!(
+                "Message msg_id {} from device {} timed out with {} of {} parts, dropped",
+                msg_id, id, received, message.parts.len()
+            );
+            info!(
+                "Message msg_id {} from device {} timed out with {} of {} parts, dropped",
+                msg_id, id, received, message.parts.len()
+            );
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::BME280;
+
+    fn sms_packet(text: &str) -> Packet {
+        Packet {
+            version: 0,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 1,
+            data_type: DataType::Sms,
+            data: Data::Sms(text.to_string()),
+        }
+    }
+
+    fn long_text() -> String {
+        // 163 bytes in 3 parts, a multi-byte character spans the first part boundary
+        format!(">{}", "zażółć gęślą jaźń ".repeat(6))
+    }
+
+    #[test]
+    fn fragment_correct() {
+        let text = long_text();
+        let parts = fragment(&sms_packet(&text), PACKET_SIZE).unwrap();
+        assert_eq!(3, parts.len());
+
+        let mut data = Vec::new();
+        for (index, part) in parts.iter().enumerate() {
+            assert_eq!(0x22, part.id);
+            assert_eq!(0x11, part.msg_id);
+            assert_eq!(((index as u8) << 4) | 3, part.msg_count);
+            assert_eq!(DataType::Fragment, part.data_type);
+
+            // every part is a valid packet on its own
+            let bytes = part.to_bytes().unwrap();
+            assert!(bytes.len() <= PACKET_SIZE);
+            let Data::Fragment(fragment) = Packet::new(&bytes).unwrap().data else {
+                panic!("Expected a fragment");
+            };
+            data.extend(fragment.data);
+        }
+        assert_eq!(text.as_bytes(), data);
+    }
+
+    #[test]
+    fn fragment_short_packet() {
+        let parts = fragment(&sms_packet("Buzzverse"), PACKET_SIZE).unwrap();
+        assert_eq!(1, parts.len());
+        assert_eq!(1, parts[0].msg_count);
+        assert!(matches!(&parts[0].data, Data::Sms(text) if text == "Buzzverse"));
+    }
+
+    #[test]
+    fn fragment_smaller_frames() {
+        let text = long_text();
+        // FSK/OOK frames hold 63 bytes, 43 of them are left for the packet with encryption
+        for max_packet_size in [63, 43] {
+            let parts = fragment(&sms_packet(&text), max_packet_size).unwrap();
+            assert_eq!(text.len().div_ceil(max_packet_size - FRAGMENT_HEADER_SIZE), parts.len());
+            assert!(parts.iter().all(|part| part.to_bytes().unwrap().len() <= max_packet_size));
+
+            let mut reassembly = Reassembly::new(Duration::from_secs(30), CustomDataTypes::default());
+            let mut received = None;
+            for part in parts {
+                received = reassembly.add(part).unwrap();
+            }
+            assert!(matches!(received.unwrap().data, Data::Sms(received) if received == text));
+        }
+
+        // a 60-byte packet fits in a LoRa frame but not in an encrypted FSK/OOK one
+        let text = "a".repeat(60 - META_DATA_SIZE);
+        assert_eq!(1, fragment(&sms_packet(&text), PACKET_SIZE).unwrap().len());
+        assert_eq!(2, fragment(&sms_packet(&text), 43).unwrap().len());
+
+        assert!(fragment(&sms_packet(&text), FRAGMENT_HEADER_SIZE).is_err());
+        assert!(fragment(&sms_packet(&text), PACKET_SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn fragment_too_long() {
+        let text = "a".repeat(MAX_PARTS * (PACKET_SIZE - FRAGMENT_HEADER_SIZE) + 1);
+        assert!(fragment(&sms_packet(&text), PACKET_SIZE).is_err());
+        let text = "a".repeat(MAX_PARTS * (PACKET_SIZE - FRAGMENT_HEADER_SIZE));
+        assert_eq!(MAX_PARTS, fragment(&sms_packet(&text), PACKET_SIZE).unwrap().len());
+    }
+
+    #[test]
+    fn reassembly_correct() {
+        let text = long_text();
+        let parts = fragment(&sms_packet(&text), PACKET_SIZE).unwrap();
+        let mut reassembly = Reassembly::new(Duration::from_secs(30), CustomDataTypes::default());
+
+        // parts may come in any order and more than once
+        assert!(reassembly.add(parts[2].clone()).unwrap().is_none());
+        assert!(reassembly.add(parts[0].clone()).unwrap().is_none());
+        assert!(reassembly.add(parts[2].clone()).unwrap().is_none());
+        let packet = reassembly.add(parts[1].clone()).unwrap().unwrap();
+        assert_eq!(0x22, packet.id);
+        assert_eq!(0x11, packet.msg_id);
+        assert_eq!(3, packet.msg_count);
+        assert!(matches!(&packet.data, Data::Sms(received) if *received == text));
+
+        // packets sent whole pass right through
+        let packet = Packet {
+            version: 0,
+            id: 0x22,
+            msg_id: 0x12,
+            msg_count: 1,
+            data_type: DataType::BME280,
+            data: Data::Bme280(BME280 {
+                temperature: 23,
+                humidity: 45,
+                pressure: 67,
+            }),
+        };
+        assert!(matches!(reassembly.add(packet).unwrap(), Some(Packet { msg_id: 0x12, .. })));
+    }
+
+    #[test]
+    fn reassembly_timeout() {
+        let parts = fragment(&sms_packet(&long_text()), PACKET_SIZE).unwrap();
+        let mut reassembly = Reassembly::new(Duration::from_millis(50), CustomDataTypes::default());
+
+        assert!(reassembly.add(parts[0].clone()).unwrap().is_none());
+        assert!(reassembly.add(parts[1].clone()).unwrap().is_none());
+        std::thread::sleep(Duration::from_millis(100));
+
+        // the first parts were dropped, the message can't complete anymore
+        assert!(reassembly.add(parts[2].clone()).unwrap().is_none());
+        assert!(reassembly.add(parts[1].clone()).unwrap().is_none());
+    }
+}
@@ -3,12 +3,50 @@ use signal_hook::{
     consts::{SIGINT, SIGQUIT, SIGTERM},
     iterator::Signals,
 };
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::time::Duration;
 use std::thread;
 
 use crate::config::config_output_pin;
-use crate::GPIOPinNumber;
+use crate::sx1278::symbol_duration_us;
+use crate::{GPIOPinNumber, LoRaConfig};
+
+/// Added to the receive timeout when waiting for the LoRa thread to stop
+pub const SHUTDOWN_GRACE_MS: u64 = 1000;
+
+/// Set once a signal asks the daemon to stop, every clone shares the flag. The receive loops
+/// return at their next receive timeout, the transmit loops before their next packet
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn request_shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn shutdown_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/*
+ * The longest a receive loop may take to notice a shutdown, None if it receives without a timeout
+ */
+pub fn receive_timeout(lora_config: &LoRaConfig) -> Option<Duration> {
+    let radio_config = &lora_config.radio_config;
+    match (radio_config.symbol_timeout, radio_config.rx_timeout_ms) {
+        (Some(symbol_timeout), _) => Some(Duration::from_micros(
+            symbol_timeout as u64
+                * symbol_duration_us(radio_config.bandwidth, radio_config.spreading_factor) as u64,
+        )),
+        (None, Some(rx_timeout_ms)) => Some(Duration::from_millis(rx_timeout_ms)),
+        (None, None) => None,
+    }
+}
 
 pub fn run_signal_handler(signal_sender: Sender<i32>) -> Result<()> {
     let mut signals = Signals::new([SIGINT, SIGQUIT, SIGTERM])?;
@@ -0,0 +1,162 @@
+use crate::config::{config_input_pin, config_output_pin, SPIConfig};
+use crate::GPIOPinNumber;
+use anyhow::{Context, Result};
+use embedded_hal::digital::{self, ErrorKind, ErrorType, InputPin, OutputPin};
+use gpio_cdev::LineEventHandle;
+use gpiod::{Lines, Output};
+use linux_embedded_hal::SpidevDevice;
+use spidev::{SpiModeFlags, SpidevOptions};
+use std::fmt;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/*
+ * Hardware backend: spidev for SPI, gpiod lines for the reset pin and gpio-cdev lines with
+ * edge events for the DIO and BUSY pins, wrapped in the embedded-hal traits the drivers are generic over
+ */
+
+#[derive(Debug)]
+pub struct GpioError(io::Error);
+
+impl fmt::Display for GpioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GPIO error: {}", self.0)
+    }
+}
+
+impl std::error::Error for GpioError {}
+
+impl digital::Error for GpioError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+pub struct GpiodOutputPin(Lines<Output>);
+
+impl GpiodOutputPin {
+    pub fn new(pin_number: GPIOPinNumber) -> Result<Self> {
+        let line = config_output_pin(pin_number).context("GpiodOutputPin::new")?;
+        Ok(Self(line))
+    }
+}
+
+impl ErrorType for GpiodOutputPin {
+    type Error = GpioError;
+}
+
+impl OutputPin for GpiodOutputPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_values(0x00_u8).map_err(GpioError)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_values(0x01_u8).map_err(GpioError)
+    }
+}
+
+/*
+ * Waiting for DIO interrupts, the drivers are generic over it next to InputPin.
+ * DIO lines stay high until the IRQ flags are cleared, so a line that went high
+ * before the wait started is seen as well
+ */
+pub trait WaitForHigh: InputPin + Sized {
+    /*
+     * Blocks until one of the pins is high and returns its index.
+     * Returns None if the timeout passes first, without a timeout this blocks
+     */
+    fn wait_for_high(pins: &mut [&mut Self], timeout: Option<Duration>) -> Result<Option<usize>, Self::Error>;
+}
+
+pub struct CdevInputPin(LineEventHandle);
+
+impl CdevInputPin {
+    pub fn new(pin_number: GPIOPinNumber) -> Result<Self> {
+        let line = config_input_pin(pin_number).context("CdevInputPin::new")?;
+        Ok(Self(line))
+    }
+}
+
+impl ErrorType for CdevInputPin {
+    type Error = GpioError;
+}
+
+impl InputPin for CdevInputPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        let value = self.0.get_value().map_err(|e| GpioError(io::Error::other(e)))?;
+        Ok(value != 0)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl WaitForHigh for CdevInputPin {
+    /*
+     * Sleeps in poll(2) on the line fds until a rising edge is queued on one of them,
+     * the level is checked before every poll and after every edge
+     */
+    fn wait_for_high(pins: &mut [&mut Self], timeout: Option<Duration>) -> Result<Option<usize>, Self::Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            for (index, pin) in pins.iter_mut().enumerate() {
+                if pin.is_high()? {
+                    return Ok(Some(index));
+                }
+            }
+
+            let poll_timeout_ms = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Ok(None);
+                    }
+                    // rounded up, poll would return early and spin through the last millisecond
+                    remaining.as_micros().div_ceil(1000).min(i32::MAX as u128) as libc::c_int
+                }
+                None => -1,
+            };
+
+            let mut fds: Vec<libc::pollfd> = pins
+                .iter()
+                .map(|pin| libc::pollfd {
+                    fd: pin.0.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+            // SAFETY: fds is a valid array of fds.len() pollfd structs for the duration of the call
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, poll_timeout_ms) };
+            if ready < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(GpioError(error));
+            }
+
+            // the edges are consumed, so the next poll blocks until a new one
+            for (pin, fd) in pins.iter_mut().zip(&fds) {
+                if fd.revents & libc::POLLIN != 0 {
+                    pin.0.get_event().map_err(|e| GpioError(io::Error::other(e)))?;
+                }
+            }
+        }
+    }
+}
+
+pub fn open_spidev(spi_config: &SPIConfig) -> Result<SpidevDevice> {
+    let mut spidev = SpidevDevice::open(&spi_config.spidev_path).context("hal::open_spidev")?;
+
+    let spi_options = SpidevOptions::new()
+        .bits_per_word(spi_config.bits_per_word)
+        .max_speed_hz(spi_config.max_speed_hz)
+        .mode(SpiModeFlags::from_bits_truncate(spi_config.spi_mode as u32))
+        .build();
+    spidev.0.configure(&spi_options).context("hal::open_spidev")?;
+
+    Ok(spidev)
+}
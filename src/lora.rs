@@ -1,24 +1,34 @@
 use crate::mqtt::MQTTMessage;
 use crate::sx1278::SX1278;
-use crate::{config::RadioConfig, Mode};
+use crate::sx126x::SX126x;
+use crate::{config::RadioConfig, Backend, Mode};
 use crate::csv_writer::CSVPacketWrapper;
+use crate::graceful_shutdown::Shutdown;
+use crate::tx_queue::TxQueue;
 use crate::{defines::*, LoRaConfig};
 use anyhow::{Context, Result};
 use std::sync::mpsc::Sender;
 
 pub trait LoRa: Send {
     fn get_mode(&self) -> Mode;
+    /// Stops the receive, transmit and transceive loops once requested
+    fn shutdown(&self) -> Shutdown;
     fn display_parameters(&mut self, radio_config: &RadioConfig) -> Result<()>;
     fn configure_lora(&mut self, radio_config: &RadioConfig) -> Result<()>;
     fn receive(&mut self, option_sender: Option<Sender<MQTTMessage>>) -> Result<()>;
-    fn transmit(&mut self) -> Result<()>;
+    fn transmit(&mut self, tx_queue: TxQueue) -> Result<()>;
     fn rt_receive(&mut self, csv_sender: Sender<CSVPacketWrapper>) -> Result<()>;
-    fn rt_transmit(&mut self, csv_sender: Sender<CSVPacketWrapper>) -> Result<()>;
+    fn rt_transmit(&mut self, tx_queue: TxQueue, csv_sender: Sender<CSVPacketWrapper>) -> Result<()>;
+    fn transceive(&mut self, option_sender: Option<Sender<MQTTMessage>>, tx_queue: TxQueue) -> Result<()>;
 }
 
 pub fn lora_from_config(lora_config: &LoRaConfig) -> Result<Box<dyn LoRa>> {
-    let lora: Box<dyn LoRa> = match lora_config.chip {
-        Chip::SX1278 => Box::new(SX1278::from_config(lora_config)?),
+    // the SX1278 driver covers the whole SX127x family
+    let lora: Box<dyn LoRa> = match (lora_config.chip.family(), lora_config.backend) {
+        (ChipFamily::SX127x, Backend::Hardware) => Box::new(SX1278::from_config(lora_config)?),
+        (ChipFamily::SX127x, Backend::Simulator) => Box::new(SX1278::simulated(lora_config)?),
+        (ChipFamily::SX126x, Backend::Hardware) => Box::new(SX126x::from_config(lora_config)?),
+        (ChipFamily::SX126x, Backend::Simulator) => Box::new(SX126x::simulated(lora_config)?),
     };
     Ok(lora)
 }
@@ -28,6 +38,7 @@ pub fn start_lora(
     radio_config: &RadioConfig,
     option_sender: Option<Sender<MQTTMessage>>,
     csv_sender: Option<Sender<CSVPacketWrapper>>,
+    tx_queue: Option<TxQueue>,
 ) -> Result<()> {
     lora.configure_lora(radio_config)
         .context("lora::start_lora")?;
@@ -35,9 +46,16 @@ pub fn start_lora(
         .context("lora::start_lora")?;
     match lora.get_mode() {
         Mode::RX => lora.receive(option_sender),
-        Mode::TX => lora.transmit(),
+        Mode::TX => lora.transmit(Option::expect(tx_queue, "TX queue not found - required for transmitting.")),
         Mode::RX_RANGE_TEST => lora.rt_receive(Option::expect(csv_sender, "CSV sender not found - required for range tests.")),
-        Mode::TX_RANGE_TEST => lora.rt_transmit(Option::expect(csv_sender, "CSV sender not found - required for range tests.")),
+        Mode::TX_RANGE_TEST => lora.rt_transmit(
+            Option::expect(tx_queue, "TX queue not found - required for transmitting."),
+            Option::expect(csv_sender, "CSV sender not found - required for range tests."),
+        ),
+        Mode::TRX => lora.transceive(
+            option_sender,
+            Option::expect(tx_queue, "TX queue not found - required for transmitting."),
+        ),
     }
     .context("lora::start_lora")?;
     Ok(())
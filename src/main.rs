@@ -1,15 +1,25 @@
 mod bme280;
+mod cayenne_lpp;
 mod config;
 mod conversions;
 mod csv_writer;
+mod custom_data;
 mod defines;
+mod encryption;
+mod fragment;
 mod graceful_shutdown;
+mod hal;
 mod logging;
 mod lora;
 mod mqtt;
 mod packet;
 mod post;
+mod reliable;
+mod simulator;
 mod sx1278;
+mod sx126x;
+mod tx_queue;
+#[allow(dead_code)]
 mod version_tag;
 
 extern crate log;
@@ -23,6 +33,7 @@ pub use crate::post::post;
 use bme280::BME280Sensor;
 use graceful_shutdown::emergency_reset;
 use graceful_shutdown::run_signal_handler;
+use graceful_shutdown::{receive_timeout, Shutdown, SHUTDOWN_GRACE_MS};
 use log::{error, info};
 use lora::{lora_from_config, start_lora};
 use mqtt::{MQTTMessage, Mqtt};
@@ -30,6 +41,8 @@ use packet::Status;
 use std::env;
 use std::sync::mpsc::channel;
 use std::thread;
+use std::time::Duration;
+use tx_queue::{TxMessage, TxQueue};
 
 macro_rules! handle_error_exit {
     ($func:expr) => {
@@ -108,8 +121,39 @@ fn main() {
         option_device_id = None;
     }
 
+    // In the TX and TRX modes readings and packets from the other threads are queued for the LoRa thread
+    let option_tx_sender;
+    let option_tx_queue;
+
+    match (&option_lora_config, mod_state.lora) {
+        (Some(lora_config @ LoRaConfig { mode: Mode::TX | Mode::TX_RANGE_TEST | Mode::TRX, .. }), true) => {
+            let tx_config = lora_config
+                .tx_config
+                .as_ref()
+                .expect("tx_config shouldn't be none in the TX and TRX modes");
+            let (tx_sender, tx_receiver) = channel::<TxMessage>();
+
+            // the node announces its status once at startup; a transmitting node then sends it
+            // every transmit interval, also without any sensor
+            let mut status = Status::from_mod_info(&mod_state, tx_config.device_id);
+            status.version = tx_config.packet_version;
+            if let Mode::TX | Mode::TX_RANGE_TEST = lora_config.mode {
+                log_error!(tx_sender.send(TxMessage::Reading(status.data.clone())));
+            }
+            log_error!(tx_sender.send(TxMessage::Packet(status)));
+
+            option_tx_queue = Some(TxQueue::new(tx_config, tx_receiver));
+            option_tx_sender = Some(tx_sender);
+        }
+        _ => {
+            option_tx_queue = None;
+            option_tx_sender = None;
+        }
+    }
+
     if let (Some(bme280_config), true) = (option_bme_config, mod_state.bme280) {
         let option_sender = option_sender.clone();
+        let option_tx_sender = option_tx_sender.clone();
         threads.push(thread::spawn(move || {
             let mut bme280 = handle_error_exit!(BME280Sensor::new(bme280_config.clone()));
             bme280.thread_run(
@@ -117,10 +161,15 @@ fn main() {
                 mod_state.mqtt,
                 option_device_id,
                 option_sender,
+                option_tx_sender,
             );
         }));
     }
 
+    // start_lora() returns once a shutdown is requested, the LoRa thread reports it here
+    let (lora_done_sender, lora_done_receiver) = channel();
+    let mut option_shutdown: Option<Shutdown> = None;
+
     if let (Some(lora_config), true) = (option_lora_config.clone(), mod_state.lora) {
         let radio_config = lora_config.radio_config.clone();
         let mut lora = match lora_from_config(&lora_config) {
@@ -135,6 +184,8 @@ fn main() {
             }
         };
 
+        option_shutdown = Some(lora.shutdown());
+
         match lora_config.mode {
             Mode::RX_RANGE_TEST | Mode::TX_RANGE_TEST => {
                 // Use CSV sender & receiver channel only in RT (Range Test) modes, else csv_sender = None
@@ -147,12 +198,14 @@ fn main() {
                 }));
 
                 threads.push(thread::spawn(move || {
-                    handle_error_exit!(start_lora(&mut lora, &radio_config, option_sender, Some(csv_sender)));
+                    handle_error_exit!(start_lora(&mut lora, &radio_config, option_sender, Some(csv_sender), option_tx_queue));
+                    let _ = lora_done_sender.send(());
                 }));
             }
             _ => {
                 threads.push(thread::spawn(move || {
-                    handle_error_exit!(start_lora(&mut lora, &radio_config, option_sender, None));
+                    handle_error_exit!(start_lora(&mut lora, &radio_config, option_sender, None, option_tx_queue));
+                    let _ = lora_done_sender.send(());
                 }));
             }
         }
@@ -168,12 +221,21 @@ fn main() {
     // The program exits after receiving to signal_receiver.
     match signal_receiver.recv() {
         Ok(_signal) => {
+            // Let the LoRa loop stop (receiving at its next receive timeout) before the radio is reset
+            if let (Some(lora_config), Some(shutdown)) = (option_lora_config.clone(), option_shutdown) {
+                shutdown.request_shutdown();
+                let timeout = receive_timeout(&lora_config).unwrap_or_default();
+                let _ = lora_done_receiver.recv_timeout(timeout + Duration::from_millis(SHUTDOWN_GRACE_MS));
+            }
+
             // drop() takes ownership of threads, and thus drops it after finishing
             drop(threads);
 
             // Get reset_pin from config, initialize and use it to reset LoRa
             if let Some(lora_config) = option_lora_config.clone() {
-                handle_error_exit!(emergency_reset(lora_config.reset_gpio));
+                if lora_config.backend == Backend::Hardware {
+                    handle_error_exit!(emergency_reset(lora_config.reset_gpio));
+                }
             }
         },
         Err(error) => {
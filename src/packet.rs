@@ -1,5 +1,11 @@
 use core::fmt;
-use crate::{conversions::*, post::ModulesState};
+use crate::{
+    cayenne_lpp::CayenneLpp,
+    conversions::*,
+    custom_data::{CustomData, CustomDataTypes},
+    fragment::{part_count, part_index},
+    post::ModulesState,
+};
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::hash::Hash;
@@ -14,15 +20,48 @@ pub const PACKET_MSG_ID_IDX: usize = 2;
 pub const PACKET_MSG_COUNT_IDX: usize = 3;
 pub const PACKET_DATA_TYPE_IDX: usize = 4;
 
+/// Layouts of the data by the version byte. 0 and 1 share the original layout,
+/// 2 widens the GPS altitude to i32 (negative altitudes) and the Status sleep time to u32
 #[repr(u8)]
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PacketVersion {
+    V0 = 0,
+    V1 = 1,
+    V2 = 2,
+}
+
+impl PacketVersion {
+    pub fn new(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(Self::V0),
+            1 => Ok(Self::V1),
+            2 => Ok(Self::V2),
+            _ => Err(anyhow!("Unknown packet version {}", byte)).context("PacketVersion::new"),
+        }
+    }
+
+    fn has_wide_fields(self) -> bool {
+        self >= Self::V2
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DataType {
     BME280 = 1,
     BMA400 = 2,
     MQ2 = 3,
     Gps = 4,
     Status = 5,
+    Ack = 6,
+    BME280HighRes = 7,
+    /// Cayenne Low Power Payload, see cayenne_lpp.rs
+    CayenneLpp = 8,
     Sms = 32,
+    /// One part of a message split over several packets, see fragment.rs
+    Fragment = 33,
+    /// Declared in the config, see custom_data.rs
+    Custom(u8),
 }
 
 impl DataType {
@@ -33,55 +72,132 @@ impl DataType {
             3 => Ok(Self::MQ2),
             4 => Ok(Self::Gps),
             5 => Ok(Self::Status),
+            6 => Ok(Self::Ack),
+            7 => Ok(Self::BME280HighRes),
+            8 => Ok(Self::CayenneLpp),
             32 => Ok(Self::Sms),
+            33 => Ok(Self::Fragment),
             _ => Err(anyhow!("Invalid data type")).context("DataType::new"),
         }
     }
+
+    /*
+     * Like DataType::new, the custom data types come before the built-in ones but
+     * the config doesn't allow the ids to overlap
+     */
+    pub fn with_custom_types(byte: u8, custom_data_types: &CustomDataTypes) -> Result<Self> {
+        if custom_data_types.contains(byte) {
+            return Ok(Self::Custom(byte));
+        }
+        Self::new(byte).context("DataType::with_custom_types")
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::BME280 => 1,
+            Self::BMA400 => 2,
+            Self::MQ2 => 3,
+            Self::Gps => 4,
+            Self::Status => 5,
+            Self::Ack => 6,
+            Self::BME280HighRes => 7,
+            Self::CayenneLpp => 8,
+            Self::Sms => 32,
+            Self::Fragment => 33,
+            Self::Custom(byte) => byte,
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize, Hash)]
+#[derive(Deserialize, Serialize, Clone, Hash)]
 pub enum Data {
     Bme280(BME280),
     Bma400(BMA400),
     Mq2(MQ2),
     Gps(Gps),
     Status(Status),
+    /// Acknowledges the packet with the same device id, msg_id and msg_count, carries no data
+    Ack,
+    Bme280HighRes(BME280HighRes),
+    CayenneLpp(CayenneLpp),
     Sms(String),
+    /// One part of a message split over several packets, see fragment.rs
+    Fragment(Fragment),
+    Custom(CustomData),
 }
 
-#[derive(Debug, Deserialize, Serialize, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, Hash)]
 pub struct BME280 {
     pub temperature: u8,
     pub humidity: u8,
     pub pressure: u8,
 }
 
-#[derive(Debug, Deserialize, Serialize, Hash)]
+/// BME280 reading without the rounding of the compact BME280 type
+#[derive(Debug, Deserialize, Serialize, Clone, Hash)]
+pub struct BME280HighRes {
+    /// 0.01 °C
+    pub temperature: i16,
+    /// 0.01 %
+    pub humidity: u16,
+    /// Pa
+    pub pressure: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Hash)]
 pub struct BMA400 {
     pub x: u64,
     pub y: u64,
     pub z: u64,
 }
 
-#[derive(Debug, Deserialize, Serialize, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, Hash)]
 pub struct MQ2 {
     pub gas_type: u8,
     pub value: u128,
 }
 
-#[derive(Debug, Deserialize, Serialize, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, Hash)]
 pub struct Gps {
     pub status: u8,
-    pub altitude: u16,
+    /// Meters, at least 0 up to packet version 1
+    pub altitude: i32,
     pub latitude: i32,
     pub longitude: i32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Hash)]
+impl Gps {
+    fn to_bytes(&self, version: PacketVersion) -> Result<Vec<u8>> {
+        let mut bytes = vec![self.status];
+        if version.has_wide_fields() {
+            bytes.extend(self.altitude.to_le_bytes());
+        } else {
+            let altitude = u16::try_from(self.altitude)
+                .map_err(|_| anyhow!("Altitude {} needs packet version 2", self.altitude))
+                .context("Gps::to_bytes")?;
+            bytes.extend(altitude.to_le_bytes());
+        }
+        bytes.extend(self.latitude.to_le_bytes());
+        bytes.extend(self.longitude.to_le_bytes());
+        Ok(bytes)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Hash)]
+pub struct Fragment {
+    /// Data type of the whole message
+    pub data_type: DataType,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Hash)]
 pub struct Status {
     pub status: u8,
     pub battery: u16,
-    pub sleep: u16,
+    /// At most 65535 up to packet version 1
+    pub sleep: u32,
+    /// Silicon version of the LoRa chip, 0 if unknown, only sent from packet version 2
+    pub chip_version: u8,
 }
 
 impl Status {
@@ -98,11 +214,28 @@ impl Status {
             status,
             battery: 0,
             sleep: 0,
+            chip_version: mod_info.lora_version.unwrap_or(0),
         });
 
         Packet { version, id, msg_id, msg_count, data_type, data }
     }
 
+    fn to_bytes(&self, version: PacketVersion) -> Result<Vec<u8>> {
+        let mut bytes = vec![self.status];
+        bytes.extend(self.battery.to_le_bytes());
+        if version.has_wide_fields() {
+            bytes.extend(self.sleep.to_le_bytes());
+            bytes.push(self.chip_version);
+        } else {
+            // the original layout stays 10 bytes long, older receivers reject anything else
+            let sleep = u16::try_from(self.sleep)
+                .map_err(|_| anyhow!("Sleep time {} needs packet version 2", self.sleep))
+                .context("Status::to_bytes")?;
+            bytes.extend(sleep.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
     fn set_lora_status(status: u8, lora_status: bool) -> u8 {
         const LORA_STATUS_BIT: u8 = 1 << 4;
 
@@ -138,9 +271,42 @@ impl Status {
 
 
 impl Data {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        let data_type = DataType::new(bytes[PACKET_DATA_TYPE_IDX]).unwrap();
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Data::Bme280(_) => DataType::BME280,
+            Data::Bma400(_) => DataType::BMA400,
+            Data::Mq2(_) => DataType::MQ2,
+            Data::Gps(_) => DataType::Gps,
+            Data::Status(_) => DataType::Status,
+            Data::Ack => DataType::Ack,
+            Data::Bme280HighRes(_) => DataType::BME280HighRes,
+            Data::CayenneLpp(_) => DataType::CayenneLpp,
+            Data::Sms(_) => DataType::Sms,
+            Data::Fragment(_) => DataType::Fragment,
+            Data::Custom(data) => DataType::Custom(data.type_id),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8], custom_data_types: &CustomDataTypes) -> Result<Self> {
+        let version = PacketVersion::new(bytes[PACKET_VERSION_IDX]).context("Data::from_bytes")?;
+        let data_type = DataType::with_custom_types(bytes[PACKET_DATA_TYPE_IDX], custom_data_types)
+            .context("Data::from_bytes")?;
         match data_type {
+            DataType::Gps if version.has_wide_fields() => {
+                if bytes.len() != 18 {
+                    return Err(anyhow!(
+                        "Incorrect length, was {}, should be 18",
+                        bytes.len()
+                    ))
+                    .context("Data::from_bytes");
+                }
+                Ok(Data::Gps(Gps {
+                    status: bytes[META_DATA_SIZE],
+                    altitude: vec_to_i32(bytes, META_DATA_SIZE + 1).context("Data::from_bytes")?,
+                    latitude: vec_to_i32(bytes, META_DATA_SIZE + 5).context("Data::from_bytes")?,
+                    longitude: vec_to_i32(bytes, META_DATA_SIZE + 9).context("Data::from_bytes")?,
+                }))
+            }
             DataType::BME280 => {
                 if bytes.len() != 8 {
                     return Err(anyhow!(
@@ -192,15 +358,31 @@ impl Data {
                 }
                 Ok(Data::Gps(Gps {
                     status: bytes[META_DATA_SIZE],
-                    altitude: vec_to_u16(bytes, META_DATA_SIZE + 1).context("Data::from_bytes")?,
+                    altitude: vec_to_u16(bytes, META_DATA_SIZE + 1).context("Data::from_bytes")? as i32,
                     latitude: vec_to_i32(bytes, META_DATA_SIZE + 3).context("Data::from_bytes")?,
                     longitude: vec_to_i32(bytes, META_DATA_SIZE + 7).context("Data::from_bytes")?,
                 }))
             }
+            DataType::Status if version.has_wide_fields() => {
+                if bytes.len() != 13 {
+                    return Err(anyhow!(
+                        "Incorrect length, was {}, should be 13",
+                        bytes.len()
+                    ))
+                    .context("Data::from_bytes");
+                }
+                Ok(Data::Status(Status {
+                    status: bytes[META_DATA_SIZE],
+                    battery: vec_to_u16(bytes, META_DATA_SIZE + 1).context("Data::from_bytes")?,
+                    sleep: vec_to_u32(bytes, META_DATA_SIZE + 3).context("Data::from_bytes")?,
+                    chip_version: bytes[META_DATA_SIZE + 7],
+                }))
+            }
             DataType::Status => {
-                if bytes.len() != 10 {
+                // chip_version is only sent from version 2, some nodes appended it to this layout
+                if bytes.len() != 10 && bytes.len() != 11 {
                     return Err(anyhow!(
-                        "Incorrect length, was {}, should be 10",
+                        "Incorrect length, was {}, should be 10 or 11",
                         bytes.len()
                     ))
                     .context("Data::from_bytes");
@@ -208,9 +390,38 @@ impl Data {
                 Ok(Data::Status(Status {
                     status: bytes[META_DATA_SIZE],
                     battery: vec_to_u16(bytes, META_DATA_SIZE + 1).context("Data::from_bytes")?,
-                    sleep: vec_to_u16(bytes, META_DATA_SIZE + 3).context("Data::from_bytes")?,
+                    sleep: vec_to_u16(bytes, META_DATA_SIZE + 3).context("Data::from_bytes")? as u32,
+                    chip_version: bytes.get(META_DATA_SIZE + 5).copied().unwrap_or(0),
                 }))
             }
+            DataType::Ack => {
+                if bytes.len() != META_DATA_SIZE {
+                    return Err(anyhow!(
+                        "Incorrect length, was {}, should be {}",
+                        bytes.len(),
+                        META_DATA_SIZE
+                    ))
+                    .context("Data::from_bytes");
+                }
+                Ok(Data::Ack)
+            }
+            DataType::BME280HighRes => {
+                if bytes.len() != 13 {
+                    return Err(anyhow!(
+                        "Incorrect length, was {}, should be 13",
+                        bytes.len()
+                    ))
+                    .context("Data::from_bytes");
+                }
+                Ok(Data::Bme280HighRes(BME280HighRes {
+                    temperature: vec_to_i16(bytes, META_DATA_SIZE).context("Data::from_bytes")?,
+                    humidity: vec_to_u16(bytes, META_DATA_SIZE + 2).context("Data::from_bytes")?,
+                    pressure: vec_to_u32(bytes, META_DATA_SIZE + 4).context("Data::from_bytes")?,
+                }))
+            }
+            DataType::CayenneLpp => Ok(Data::CayenneLpp(
+                CayenneLpp::from_bytes(&bytes[META_DATA_SIZE..]).context("Data::from_bytes")?,
+            )),
             DataType::Sms => {
                 if bytes.len() < 6 {
                     return Err(anyhow!(
@@ -224,6 +435,39 @@ impl Data {
                         .context("Data::from_bytes")?,
                 ))
             }
+            DataType::Fragment => {
+                if bytes.len() < META_DATA_SIZE + 2 {
+                    return Err(anyhow!(
+                        "Incorrect length, was {}, should be at least {}",
+                        bytes.len(),
+                        META_DATA_SIZE + 2
+                    ))
+                    .context("Data::from_bytes");
+                }
+                let msg_count = bytes[PACKET_MSG_COUNT_IDX];
+                if part_count(msg_count) < 2 || part_index(msg_count) >= part_count(msg_count) {
+                    return Err(anyhow!(
+                        "Invalid part {} of {}",
+                        part_index(msg_count),
+                        part_count(msg_count)
+                    ))
+                    .context("Data::from_bytes");
+                }
+                let data_type = DataType::with_custom_types(bytes[META_DATA_SIZE], custom_data_types)
+                    .context("Data::from_bytes")?;
+                if data_type == DataType::Fragment {
+                    return Err(anyhow!("Fragment of a fragment")).context("Data::from_bytes");
+                }
+                Ok(Data::Fragment(Fragment {
+                    data_type,
+                    data: bytes[META_DATA_SIZE + 1..].to_vec(),
+                }))
+            }
+            DataType::Custom(id) => Ok(Data::Custom(
+                custom_data_types
+                    .decode(id, &bytes[META_DATA_SIZE..])
+                    .context("Data::from_bytes")?,
+            )),
         }
     }
 }
@@ -253,15 +497,35 @@ impl fmt::Debug for Data {
             ),
             Data::Status(data) => write!(
                 f, 
-                "{{ status: {}, battery: {}, sleep: {} }}",
-                data.status, data.battery, data.sleep
+                "{{ status: {}, battery: {}, sleep: {}, chip_version: {} }}",
+                data.status, data.battery, data.sleep, data.chip_version
             ),
+            Data::Ack => write!(f, "ACK"),
+            Data::Bme280HighRes(data) => write!(
+                f,
+                "{{ temperature: {}, humidity: {}, pressure: {} }}",
+                data.temperature as f64 / 100.0, data.humidity as f64 / 100.0, data.pressure as f64 / 100.0
+            ),
+            Data::CayenneLpp(data) => write!(f, "{:?}", data),
             Data::Sms(data) => write!(f, "\"{}\"", *data),
+            Data::Fragment(data) => write!(
+                f,
+                "{{ part of {:?}: {:02X?} }}",
+                data.data_type, data.data
+            ),
+            Data::Custom(data) => {
+                let values: Vec<String> = data
+                    .values
+                    .iter()
+                    .map(|value| format!("{}: {} {}", value.name, value.value, value.unit).trim_end().to_string())
+                    .collect();
+                write!(f, "{} {{ {} }}", data.name, values.join(", "))
+            }
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Hash)]
+#[derive(Debug, Deserialize, Serialize, Clone, Hash)]
 pub struct Packet {
     pub version: u8,
     pub id: u8,
@@ -273,15 +537,24 @@ pub struct Packet {
 
 impl Packet {
     pub fn new(bytes: &[u8]) -> Result<Self> {
+        Self::with_custom_types(bytes, &CustomDataTypes::default())
+    }
+
+    /*
+     * Like Packet::new, also accepts the data types declared in the config
+     */
+    pub fn with_custom_types(bytes: &[u8], custom_data_types: &CustomDataTypes) -> Result<Self> {
         if bytes.len() < META_DATA_SIZE || bytes.len() > PACKET_SIZE {
             return Err(anyhow!("Incorrect length, was {}", bytes.len()));
         }
-        let version = bytes[PACKET_VERSION_IDX];
+        // an unknown version would be decoded with the wrong layout
+        let version = PacketVersion::new(bytes[PACKET_VERSION_IDX]).context("Packet::new")? as u8;
         let id = bytes[PACKET_ID_IDX];
         let msg_id = bytes[PACKET_MSG_ID_IDX];
         let msg_count = bytes[PACKET_MSG_COUNT_IDX];
-        let data_type = DataType::new(bytes[PACKET_DATA_TYPE_IDX]).context("Packet::new")?;
-        let data = Data::from_bytes(bytes).context("Packet::new")?;
+        let data_type = DataType::with_custom_types(bytes[PACKET_DATA_TYPE_IDX], custom_data_types)
+            .context("Packet::new")?;
+        let data = Data::from_bytes(bytes, custom_data_types).context("Packet::new")?;
 
         Ok(Self {
             version,
@@ -293,20 +566,30 @@ impl Packet {
         })
     }
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let version = PacketVersion::new(self.version).context("Packet::to_bytes")?;
         let mut packet = vec![
             self.version,
             self.id,
             self.msg_id,
             self.msg_count,
-            self.data_type as u8,
+            self.data_type.to_byte(),
         ];
         let mut data = match &self.data {
             Data::Bme280(data) => bincode::serialize(data).context("Packet::to_bytes")?,
             Data::Bma400(data) => bincode::serialize(data).context("Packet::to_bytes")?,
             Data::Mq2(data) => bincode::serialize(data).context("Packet::to_bytes")?,
-            Data::Gps(data) => bincode::serialize(data).context("Packet::to_bytes")?,
-            Data::Status(data) => bincode::serialize(data).context("Packet::to_bytes")?,
+            Data::Gps(data) => data.to_bytes(version).context("Packet::to_bytes")?,
+            Data::Status(data) => data.to_bytes(version).context("Packet::to_bytes")?,
+            Data::Ack => Vec::new(),
+            Data::Bme280HighRes(data) => bincode::serialize(data).context("Packet::to_bytes")?,
+            Data::CayenneLpp(data) => data.to_bytes().context("Packet::to_bytes")?,
             Data::Sms(data) => data.as_bytes().to_vec(),
+            Data::Fragment(data) => {
+                let mut bytes = vec![data.data_type.to_byte()];
+                bytes.extend(&data.data);
+                bytes
+            }
+            Data::Custom(data) => data.data.clone(),
         };
 
         packet.append(&mut data);
@@ -334,27 +617,61 @@ impl Packet {
                 (data.longitude as f64) / 10_000_000f64
             )),
             Data::Status(data) => Ok(format!(
-                r#""STATUS": {{ "status": {}, "battery": {}, "sleep": {} }}"#,
-                data.status, data.battery, data.sleep
+                r#""STATUS": {{ "status": {}, "battery": {}, "sleep": {}, "chip_version": {} }}"#,
+                data.status, data.battery, data.sleep, data.chip_version
+            )),
+            Data::Ack => Ok(r#""ACK": { }"#.to_string()),
+            // same key and units as the compact type, only finer
+            Data::Bme280HighRes(data) => Ok(format!(
+                r#""BME280": {{ "temperature": {}, "humidity": {}, "pressure": {} }}"#,
+                data.temperature as f64 / 100.0, data.humidity as f64 / 100.0, data.pressure as f64 / 100.0
             )),
+            Data::CayenneLpp(data) => Ok(format!(r#""CAYENNE_LPP": {}"#, data.to_json())),
             Data::Sms(data) => Ok(format!(
                 r#""SMS": {{ "text": "{}" }}"#,
                 *data
             )),
+            Data::Fragment(data) => Ok(format!(
+                r#""FRAGMENT": {{ "data_type": "{:?}", "data": {:?} }}"#,
+                data.data_type, data.data
+            )),
+            Data::Custom(data) => {
+                let values: Vec<String> = data
+                    .values
+                    .iter()
+                    .map(|value| format!(r#""{}": {}"#, value.name, value.value))
+                    .collect();
+                let units: Vec<String> = data
+                    .values
+                    .iter()
+                    .map(|value| format!(r#""{}": "{}""#, value.name, value.unit))
+                    .collect();
+                Ok(format!(
+                    r#""{}": {{ {}, "units": {{ {} }} }}"#,
+                    data.name,
+                    values.join(", "),
+                    units.join(", ")
+                ))
+            }
         }
     }
 }
 
+#[derive(Debug)]
 pub struct Metadata {
-    pub snr: u8,
+    /// dB, 0.25 dB resolution
+    pub snr: f32,
+    /// dBm
     pub rssi: i16,
+    /// Hz, carrier offset of the transmitter relative to the RX frequency
+    pub frequency_error: i32,
 }
 
 impl Metadata {
     pub fn to_json(&self) -> Result<String> {
         Ok(format!(
-                r#""META": {{ "snr": {}, "rssi": {} }}"#,
-                self.snr, self.rssi
+                r#""META": {{ "snr": {}, "rssi": {}, "frequency_error": {} }}"#,
+                self.snr, self.rssi, self.frequency_error
         ))
     }
 }
@@ -362,6 +679,7 @@ impl Metadata {
 /// A struct to wrap a LoRa packet with additional data about
 /// SNR (signal to noise ratio)
 /// and RSSI (received signal strength indicator)
+#[derive(Debug)]
 pub struct PacketWrapper {
     pub packet: Packet,
     pub metadata: Metadata,
@@ -380,6 +698,7 @@ impl PacketWrapper {
 mod tests {
     use super::*;
     use std::hash::{DefaultHasher, Hasher};
+    use crate::config::Config;
     use crate::post::ModulesState;
 
     fn calculate_hash<T: Hash>(t: &T) -> u64 {
@@ -391,7 +710,7 @@ mod tests {
     #[test]
     fn serialize_bme280_correct() {
         let packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -403,7 +722,7 @@ mod tests {
             }),
         };
 
-        let expected_data: Vec<u8> = vec![0x33, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43];
+        let expected_data: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43];
         let serialized_packet = packet.to_bytes().unwrap();
 
         assert_eq!(serialized_packet, expected_data);
@@ -411,10 +730,10 @@ mod tests {
 
     #[test]
     fn deserialize_bme280_correct() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43];
 
         let expected_packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -436,26 +755,126 @@ mod tests {
 
     #[test]
     fn deserialize_bme280_data_too_short() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D];
         assert!(Packet::new(&bytes).is_err());
     }
 
     #[test]
     fn deserialize_bme280_packet_too_long() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43, 0xFF];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43, 0xFF];
         assert!(Packet::new(&bytes).is_err());
     }
 
     #[test]
     fn deserialize_bme280_packet_too_short() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11];
+        assert!(Packet::new(&bytes).is_err());
+    }
+
+    #[test]
+    fn deserialize_cayenne_lpp_correct() {
+        // temperature 27.2 °C on channel 3, humidity 32 % on channel 5
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x01, 0x08, 0x03, 0x67, 0x01, 0x10, 0x05, 0x68, 0x40];
+        let packet = Packet::new(&bytes).unwrap();
+        assert_eq!(DataType::CayenneLpp, packet.data_type);
+        assert_eq!(
+            r#""CAYENNE_LPP": { "temperature_3": 27.2, "relative_humidity_5": 32 }"#,
+            packet.to_json().unwrap()
+        );
+        assert_eq!(
+            "{ temperature_3: 27.2, relative_humidity_5: 32 }",
+            format!("{:?}", packet.data)
+        );
+        assert_eq!(bytes, packet.to_bytes().unwrap());
+
+        // a record cut off by the end of the packet
+        assert!(Packet::new(&bytes[..11]).is_err());
+    }
+
+    #[test]
+    fn deserialize_custom_data_type_correct() {
+        let config = Config::from_file("./tests/configs/custom_data_types_conf.toml".to_string()).unwrap();
+        let custom_data_types = CustomDataTypes::new(&config.lora_config.unwrap().custom_data_types);
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x01, 0xC8, 0x9F, 0x01, 0xFB, 0xE6, 0x2D];
+
+        let packet = Packet::with_custom_types(&bytes, &custom_data_types).unwrap();
+        assert_eq!(DataType::Custom(200), packet.data_type);
+        assert_eq!(DataType::Custom(200), packet.data.data_type());
+        assert_eq!(
+            r#""SOIL": { "moisture": 41.5, "temperature": -10.5, "battery": 2900, "units": { "moisture": "%", "temperature": "°C", "battery": "mV" } }"#,
+            packet.to_json().unwrap()
+        );
+        assert_eq!(
+            "SOIL { moisture: 41.5 %, temperature: -10.5 °C, battery: 2900 mV }",
+            format!("{:?}", packet.data)
+        );
+        // sent again as received
+        assert_eq!(bytes, packet.to_bytes().unwrap());
+
+        // unscaled 64-bit fields are exact
+        let mut counter_bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x01, 0xC9, 0xFE, 0xFF, 0xFF, 0xFF];
+        counter_bytes.extend((u64::MAX - 1).to_be_bytes());
+        assert_eq!(
+            r#""COUNTER": { "delta": -2, "total": 18446744073709551614, "units": { "delta": "", "total": "" } }"#,
+            Packet::with_custom_types(&counter_bytes, &custom_data_types).unwrap().to_json().unwrap()
+        );
+
+        // undeclared, or declared with another length
+        assert!(Packet::new(&bytes).is_err());
+        assert!(Packet::with_custom_types(&bytes[..9], &custom_data_types).is_err());
+        // the built-in types are unaffected
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43];
+        assert!(matches!(
+            Packet::with_custom_types(&bytes, &custom_data_types).unwrap().data,
+            Data::Bme280(_)
+        ));
+    }
+
+    #[test]
+    fn serialize_deserialize_bme280_high_res_correct() {
+        let packet = Packet {
+            version: 0x01,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 0x00,
+            data_type: DataType::BME280HighRes,
+            data: Data::Bme280HighRes(BME280HighRes {
+                temperature: -1050,
+                humidity: 4567,
+                pressure: 101_325,
+            }),
+        };
+
+        let expected_data: Vec<u8> = vec![
+            0x01, 0x22, 0x11, 0x00, 0x07, 0xE6, 0xFB, 0xD7, 0x11, 0xCD, 0x8B, 0x01, 0x00,
+        ];
+        let serialized_packet = packet.to_bytes().unwrap();
+        assert_eq!(serialized_packet, expected_data);
+
+        let deserialized_data = Packet::new(&expected_data).unwrap();
+        assert_eq!(
+            calculate_hash(&deserialized_data),
+            calculate_hash(&packet)
+        );
+        assert_eq!(
+            r#""BME280": { "temperature": -10.5, "humidity": 45.67, "pressure": 1013.25 }"#,
+            deserialized_data.to_json().unwrap()
+        );
+    }
+
+    #[test]
+    fn deserialize_bme280_high_res_incorrect_length() {
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x07, 0xE6, 0xFB, 0xD7, 0x11, 0xCD, 0x8B, 0x01];
+        assert!(Packet::new(&bytes).is_err());
+        // a compact BME280 body under the high-resolution type
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x07, 0x17, 0x2D, 0x43];
         assert!(Packet::new(&bytes).is_err());
     }
 
     #[test]
     fn serialize_bma400_correct() {
         let packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -468,7 +887,7 @@ mod tests {
         };
 
         let expected_data: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x22, 0x11, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00,
         ];
@@ -480,13 +899,13 @@ mod tests {
     #[test]
     fn deserialize_bma400_correct() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x22, 0x11, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00,
         ];
 
         let expected_packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -509,7 +928,7 @@ mod tests {
     #[test]
     fn deserialize_bma400_data_too_short() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x22, 0x11, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
         ];
         assert!(Packet::new(&bytes).is_err());
@@ -518,7 +937,7 @@ mod tests {
     #[test]
     fn deserialize_bma400_packet_too_long() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x22, 0x11, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0xFF,
         ];
@@ -527,14 +946,14 @@ mod tests {
 
     #[test]
     fn deserialize_bme400_packet_too_short() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11];
         assert!(Packet::new(&bytes).is_err());
     }
 
     #[test]
     fn serialize_mq2_correct() {
         let packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -546,7 +965,7 @@ mod tests {
         };
 
         let expected_data: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x03, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x03, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
             0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
         ];
         let serialized_packet = packet.to_bytes().unwrap();
@@ -557,12 +976,12 @@ mod tests {
     #[test]
     fn deserialize_mq2_correct() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x03, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x03, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
             0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
         ];
 
         let expected_packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -584,7 +1003,7 @@ mod tests {
     #[test]
     fn deserialize_mq2_data_too_short() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x03, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x03, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
             0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
         ];
         assert!(Packet::new(&bytes).is_err());
@@ -593,7 +1012,7 @@ mod tests {
     #[test]
     fn deserialize_mq2_packet_too_long() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x03, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x03, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
             0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
         ];
         assert!(Packet::new(&bytes).is_err());
@@ -601,28 +1020,28 @@ mod tests {
 
     #[test]
     fn deserialize_mq2_packet_too_short() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11];
         assert!(Packet::new(&bytes).is_err());
     }
 
     #[test]
     fn serialize_gps_correct() {
         let packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
             data_type: DataType::Gps,
             data: Data::Gps(Gps {
                 status: u8::MAX,
-                altitude: u16::MAX,
+                altitude: u16::MAX as i32,
                 latitude: i32::MAX,
                 longitude: i32::MAX,
             }),
         };
 
         let expected_data: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF,
             0xFF, 0x7F,
         ];
         let serialized_packet = packet.to_bytes().unwrap();
@@ -633,19 +1052,19 @@ mod tests {
     #[test]
     fn deserialize_gps_correct() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF,
             0xFF, 0x7F,
         ];
 
         let expected_packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
             data_type: DataType::Gps,
             data: Data::Gps(Gps {
                 status: u8::MAX,
-                altitude: u16::MAX,
+                altitude: u16::MAX as i32,
                 latitude: i32::MAX,
                 longitude: i32::MAX,
             }),
@@ -662,7 +1081,7 @@ mod tests {
     #[test]
     fn deserialize_gps_data_too_short() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF,
             0xFF,
         ];
         assert!(Packet::new(&bytes).is_err());
@@ -671,7 +1090,7 @@ mod tests {
     #[test]
     fn deserialize_gps_packet_too_long() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF,
             0xFF, 0x7F, 0xFF,
         ];
         assert!(Packet::new(&bytes).is_err());
@@ -679,27 +1098,108 @@ mod tests {
 
     #[test]
     fn deserialize_gps_packet_too_short() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11];
         assert!(Packet::new(&bytes).is_err());
     }
 
     #[test]
-    fn serialize_status_correct() {
+    fn serialize_gps_v2_correct() {
+        let packet = Packet {
+            version: 0x02,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 0x00,
+            data_type: DataType::Gps,
+            data: Data::Gps(Gps {
+                status: u8::MAX,
+                altitude: -420,
+                latitude: i32::MAX,
+                longitude: i32::MAX,
+            }),
+        };
+
+        let expected_data: Vec<u8> = vec![
+            0x02, 0x22, 0x11, 0x00, 0x04, 0xFF, 0x5C, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F,
+            0xFF, 0xFF, 0xFF, 0x7F,
+        ];
+        let serialized_packet = packet.to_bytes().unwrap();
+
+        assert_eq!(serialized_packet, expected_data);
+
+        // a negative altitude doesn't fit the v1 layout
+        let packet = Packet { version: 0x01, ..packet };
+        assert!(packet.to_bytes().is_err());
+    }
+
+    #[test]
+    fn deserialize_gps_v2_correct() {
+        let bytes: Vec<u8> = vec![
+            0x02, 0x22, 0x11, 0x00, 0x04, 0xFF, 0x5C, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F,
+            0xFF, 0xFF, 0xFF, 0x7F,
+        ];
+
+        let expected_packet = Packet {
+            version: 0x02,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 0x00,
+            data_type: DataType::Gps,
+            data: Data::Gps(Gps {
+                status: u8::MAX,
+                altitude: -420,
+                latitude: i32::MAX,
+                longitude: i32::MAX,
+            }),
+        };
+
+        let deserialized_data = Packet::new(&bytes).unwrap();
+
+        assert_eq!(
+            calculate_hash(&deserialized_data),
+            calculate_hash(&expected_packet)
+        );
+
+        // the v1 layout is 2 bytes shorter
+        let mut bytes = bytes;
+        bytes[PACKET_VERSION_IDX] = 0x01;
+        assert!(Packet::new(&bytes).is_err());
+    }
+
+    #[test]
+    fn deserialize_unknown_version() {
+        let bytes: Vec<u8> = vec![0x03, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43];
+        assert!(Packet::new(&bytes).is_err());
+
         let packet = Packet {
             version: 0x33,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
+            data_type: DataType::Ack,
+            data: Data::Ack,
+        };
+        assert!(packet.to_bytes().is_err());
+    }
+
+    #[test]
+    fn serialize_status_correct() {
+        let packet = Packet {
+            version: 0x01,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 0x00,
             data_type: DataType::Status,
             data: Data::Status(Status {
                 status: u8::MAX,
                 battery: u16::MAX,
-                sleep: u16::MAX,
+                sleep: u16::MAX as u32,
+                chip_version: 0x12,
             }),
         };
 
+        // no chip_version in the original layout
         let expected_data: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
         ];
         let serialized_packet = packet.to_bytes().unwrap();
 
@@ -709,11 +1209,11 @@ mod tests {
     #[test]
     fn deserialize_status_correct() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x12,
         ];
 
         let expected_packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -721,7 +1221,8 @@ mod tests {
             data: Data::Status(Status {
                 status: u8::MAX,
                 battery: u16::MAX,
-                sleep: u16::MAX,
+                sleep: u16::MAX as u32,
+                chip_version: 0x12,
             }),
         };
 
@@ -733,10 +1234,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn deserialize_status_without_chip_version() {
+        let bytes: Vec<u8> = vec![
+            0x01, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+
+        let deserialized_data = Packet::new(&bytes).unwrap();
+        if let Data::Status(data) = deserialized_data.data {
+            assert_eq!((u8::MAX, 0u8), (data.status, data.chip_version));
+        } else {
+            panic!("Expected a Status packet");
+        }
+    }
+
     #[test]
     fn deserialize_status_data_too_short() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF,
         ];
         assert!(Packet::new(&bytes).is_err());
     }
@@ -744,7 +1259,7 @@ mod tests {
     #[test]
     fn deserialize_status_packet_too_long() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x01, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
             0xFF, 0xFF, 0xFF,
         ];
         assert!(Packet::new(&bytes).is_err());
@@ -752,94 +1267,142 @@ mod tests {
 
     #[test]
     fn deserialize_status_packet_too_short() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11];
         assert!(Packet::new(&bytes).is_err());
     }
 
+    #[test]
+    fn serialize_deserialize_status_v2_correct() {
+        let packet = Packet {
+            version: 0x02,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 0x00,
+            data_type: DataType::Status,
+            data: Data::Status(Status {
+                status: u8::MAX,
+                battery: u16::MAX,
+                sleep: 86_400,
+                chip_version: 0x12,
+            }),
+        };
+
+        let expected_data: Vec<u8> = vec![
+            0x02, 0x22, 0x11, 0x00, 0x05, 0xFF, 0xFF, 0xFF, 0x80, 0x51, 0x01, 0x00, 0x12,
+        ];
+        let serialized_packet = packet.to_bytes().unwrap();
+        assert_eq!(serialized_packet, expected_data);
+
+        let deserialized_data = Packet::new(&expected_data).unwrap();
+        assert_eq!(
+            calculate_hash(&deserialized_data),
+            calculate_hash(&packet)
+        );
+
+        // a day doesn't fit the v1 layout
+        let packet = Packet { version: 0x01, ..packet };
+        assert!(packet.to_bytes().is_err());
+    }
+
     #[test]
     fn from_mod_info_all_true() {
-        let mod_info = ModulesState { lora: true, mqtt: true, bme280: true };
+        let mod_info = ModulesState { lora: true, lora_version: Some(0x12), mqtt: true, bme280: true };
+
+        let result = Status::from_mod_info(&mod_info, 255);
+        if let Data::Status(data) = result.data {
+            assert_eq!((0u8, 0u16, 0u32), (data.status, data.battery, data.sleep));
+        }
+    }
+
+    #[test]
+    fn from_mod_info_chip_version() {
+        let mod_info = ModulesState { lora: true, lora_version: Some(0x12), mqtt: true, bme280: true };
+        let result = Status::from_mod_info(&mod_info, 255);
+        if let Data::Status(data) = result.data {
+            assert_eq!(0x12, data.chip_version);
+        }
 
+        let mod_info = ModulesState { lora: false, lora_version: None, mqtt: true, bme280: true };
         let result = Status::from_mod_info(&mod_info, 255);
         if let Data::Status(data) = result.data {
-            assert_eq!((0u8, 0u16, 0u16), (data.status, data.battery, data.sleep));
+            assert_eq!(0, data.chip_version);
         }
     }
 
     #[test]
     fn from_mod_info_bme_false() {
-        let mod_info = ModulesState { lora: true, mqtt: true, bme280: false };
+        let mod_info = ModulesState { lora: true, lora_version: Some(0x12), mqtt: true, bme280: false };
 
         let result = Status::from_mod_info(&mod_info, 255);
         if let Data::Status(data) = result.data {
-            assert_eq!((160u8, 0u16, 0u16), (data.status, data.battery, data.sleep));
+            assert_eq!((160u8, 0u16, 0u32), (data.status, data.battery, data.sleep));
         }
     }
 
     #[test]
     fn from_mod_info_mqtt_false() {
-        let mod_info = ModulesState { lora: true, mqtt: false, bme280: true };
+        let mod_info = ModulesState { lora: true, lora_version: Some(0x12), mqtt: false, bme280: true };
 
         let result = Status::from_mod_info(&mod_info, 255);
         if let Data::Status(data) = result.data {
-            assert_eq!((4u8, 0u16, 0u16), (data.status, data.battery, data.sleep));
+            assert_eq!((4u8, 0u16, 0u32), (data.status, data.battery, data.sleep));
         }
     }
 
     #[test]
     fn from_mod_info_lora_false() {
-        let mod_info = ModulesState { lora: false, mqtt: true, bme280: true };
+        let mod_info = ModulesState { lora: false, lora_version: None, mqtt: true, bme280: true };
 
         let result = Status::from_mod_info(&mod_info, 255);
         if let Data::Status(data) = result.data {
-            assert_eq!((16u8, 0u16, 0u16), (data.status, data.battery, data.sleep));
+            assert_eq!((16u8, 0u16, 0u32), (data.status, data.battery, data.sleep));
         }
     }
 
     #[test]
     fn from_mod_info_only_mqtt_true() {
-        let mod_info = ModulesState { lora: false, mqtt: true, bme280: false };
+        let mod_info = ModulesState { lora: false, lora_version: None, mqtt: true, bme280: false };
 
         let result = Status::from_mod_info(&mod_info, 255);
         if let Data::Status(data) = result.data {
-            assert_eq!((176u8, 0u16, 0u16), (data.status, data.battery, data.sleep));
+            assert_eq!((176u8, 0u16, 0u32), (data.status, data.battery, data.sleep));
         }
     }
 
     #[test]
     fn from_mod_info_only_lora_true() {
-        let mod_info = ModulesState { lora: true, mqtt: false, bme280: false };
+        let mod_info = ModulesState { lora: true, lora_version: Some(0x12), mqtt: false, bme280: false };
 
         let result = Status::from_mod_info(&mod_info, 255);
         if let Data::Status(data) = result.data {
-            assert_eq!((164u8, 0u16, 0u16), (data.status, data.battery, data.sleep));
+            assert_eq!((164u8, 0u16, 0u32), (data.status, data.battery, data.sleep));
         }
     }
 
     #[test]
     fn from_mod_info_only_bme_true() {
-        let mod_info = ModulesState { lora: false, mqtt: false, bme280: true };
+        let mod_info = ModulesState { lora: false, lora_version: None, mqtt: false, bme280: true };
 
         let result = Status::from_mod_info(&mod_info, 255);
         if let Data::Status(data) = result.data {
-            assert_eq!((20u8, 0u16, 0u16), (data.status, data.battery, data.sleep));
+            assert_eq!((20u8, 0u16, 0u32), (data.status, data.battery, data.sleep));
         }
     }
 
     #[test]
     fn from_mod_info_all_false() {
-        let mod_info = ModulesState { lora: false, mqtt: false, bme280: false };
+        let mod_info = ModulesState { lora: false, lora_version: None, mqtt: false, bme280: false };
 
         let result = Status::from_mod_info(&mod_info, 255);
         if let Data::Status(data) = result.data {
-            assert_eq!((180u8, 0u16, 0u16), (data.status, data.battery, data.sleep));
+            assert_eq!((180u8, 0u16, 0u32), (data.status, data.battery, data.sleep));
         }
     }
 
     #[test]
     fn serialize_sms_correct() {
         let packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -847,7 +1410,7 @@ mod tests {
             data: Data::Sms(String::from("AB")),
         };
 
-        let expected_data: Vec<u8> = vec![0x33, 0x22, 0x11, 0x00, 0x20, 0x41, 0x42];
+        let expected_data: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x20, 0x41, 0x42];
         let serialized_packet = packet.to_bytes().unwrap();
 
         assert_eq!(serialized_packet, expected_data);
@@ -855,10 +1418,10 @@ mod tests {
 
     #[test]
     fn deserialize_sms_correct() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11, 0x00, 0x20, 0x41, 0x42];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x20, 0x41, 0x42];
 
         let expected_packet = Packet {
-            version: 0x33,
+            version: 0x01,
             id: 0x22,
             msg_id: 0x11,
             msg_count: 0x00,
@@ -876,14 +1439,14 @@ mod tests {
 
     #[test]
     fn deserialize_sms_data_too_short() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11, 0x00, 0x20]; // 0 byte string
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x20]; // 0 byte string
         assert!(Packet::new(&bytes).is_err());
     }
 
     #[test]
     fn deserialize_sms_packet_too_long() {
         let bytes: Vec<u8> = vec![
-            0x33, 0x22, 0x11, 0x00, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
+            0x01, 0x22, 0x11, 0x00, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
             0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
             0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
             0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
@@ -894,7 +1457,113 @@ mod tests {
 
     #[test]
     fn deserialize_sms_packet_too_short() {
-        let bytes: Vec<u8> = vec![0x33, 0x22, 0x11];
+        let bytes: Vec<u8> = vec![0x01, 0x22, 0x11];
         assert!(Packet::new(&bytes).is_err());
     }
+
+    #[test]
+    fn serialize_ack_correct() {
+        let packet = Packet {
+            version: 0x00,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 0x01,
+            data_type: DataType::Ack,
+            data: Data::Ack,
+        };
+
+        let expected_data: Vec<u8> = vec![0x00, 0x22, 0x11, 0x01, 0x06];
+        assert_eq!(packet.to_bytes().unwrap(), expected_data);
+        assert_eq!(r#""ACK": { }"#, packet.to_json().unwrap());
+    }
+
+    #[test]
+    fn deserialize_ack_correct() {
+        let bytes: Vec<u8> = vec![0x00, 0x22, 0x11, 0x01, 0x06];
+
+        let expected_packet = Packet {
+            version: 0x00,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 0x01,
+            data_type: DataType::Ack,
+            data: Data::Ack,
+        };
+
+        let deserialized_data = Packet::new(&bytes).unwrap();
+
+        assert_eq!(
+            calculate_hash(&deserialized_data),
+            calculate_hash(&expected_packet)
+        );
+    }
+
+    #[test]
+    fn deserialize_ack_with_data() {
+        let bytes: Vec<u8> = vec![0x00, 0x22, 0x11, 0x01, 0x06, 0x00];
+        assert!(Packet::new(&bytes).is_err());
+    }
+
+    #[test]
+    fn deserialize_fragment_correct() {
+        // part 1 of 3 of an SMS, not decoded until the message is reassembled
+        let bytes: Vec<u8> = vec![0x00, 0x22, 0x11, 0x13, 0x21, 0x20, 0xE2, 0x82];
+
+        let expected_packet = Packet {
+            version: 0x00,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 0x13,
+            data_type: DataType::Fragment,
+            data: Data::Fragment(Fragment {
+                data_type: DataType::Sms,
+                data: vec![0xE2, 0x82],
+            }),
+        };
+
+        let deserialized_data = Packet::new(&bytes).unwrap();
+
+        assert_eq!(
+            calculate_hash(&deserialized_data),
+            calculate_hash(&expected_packet)
+        );
+        assert_eq!(bytes, deserialized_data.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn deserialize_fragment_invalid_part() {
+        // part 3 of 3
+        let bytes: Vec<u8> = vec![0x00, 0x22, 0x11, 0x33, 0x21, 0x20, 0x41];
+        assert!(Packet::new(&bytes).is_err());
+
+        // part 0 of 1
+        let bytes: Vec<u8> = vec![0x00, 0x22, 0x11, 0x01, 0x21, 0x20, 0x41];
+        assert!(Packet::new(&bytes).is_err());
+
+        // a part without data
+        let bytes: Vec<u8> = vec![0x00, 0x22, 0x11, 0x13, 0x21, 0x20];
+        assert!(Packet::new(&bytes).is_err());
+
+        // a part of a part
+        let bytes: Vec<u8> = vec![0x00, 0x22, 0x11, 0x13, 0x21, 0x21, 0x41];
+        assert!(Packet::new(&bytes).is_err());
+    }
+
+    #[test]
+    fn deserialize_msg_count_not_fragment() {
+        // legacy senders set msg_count freely, only the Fragment data type makes a packet a part
+        let bytes: Vec<u8> = vec![0x00, 0x22, 0x11, 0x12, 0x20, 0x41];
+        let packet = Packet::new(&bytes).unwrap();
+        assert_eq!(0x12, packet.msg_count);
+        assert!(matches!(&packet.data, Data::Sms(text) if text == "A"));
+    }
+
+    #[test]
+    fn metadata_to_json_correct() {
+        let metadata = Metadata { snr: -6.25, rssi: -121, frequency_error: -1530 };
+        assert_eq!(
+            r#""META": { "snr": -6.25, "rssi": -121, "frequency_error": -1530 }"#,
+            metadata.to_json().unwrap()
+        );
+    }
 }
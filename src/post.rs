@@ -1,7 +1,11 @@
 use crate::defines::*;
+use crate::hal::WaitForHigh;
 use crate::sx1278::SX1278;
-use crate::{bme280::BME280Sensor, BME280Config, Config, LoRaConfig};
+use crate::sx126x::SX126x;
+use crate::{bme280::BME280Sensor, BME280Config, Backend, Config, LoRaConfig};
 use anyhow::{anyhow, Result};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiDevice;
 use log::{error, info};
 use ping::ping;
 use std::net::Ipv4Addr;
@@ -24,17 +28,23 @@ pub fn post(config: &Config) -> Result<ModulesState> {
     println!("--------------------------------------------------------------------------------");
 
     let lora;
+    let lora_version;
     let mqtt;
     let bme280;
 
     if let Some(lora_config) = &config.lora_config {
-        lora = error_log!(post_lora(lora_config), "LoRa");
+        let result = post_lora(lora_config);
+        lora_version = result.as_ref().ok().copied().flatten();
+        lora = error_log!(result, "LoRa");
     } else {
         lora = false;
+        lora_version = None;
         println!("[ OFF ] SPI POST");
         println!("[ OFF ] GPIO POST");
+        println!("[ OFF ] CHIP POST");
         info!("[ OFF ] SPI POST");
         info!("[ OFF ] GPIO POST");
+        info!("[ OFF ] CHIP POST");
     }
 
     if let Some(bme_config) = &config.bme_config {
@@ -56,11 +66,14 @@ pub fn post(config: &Config) -> Result<ModulesState> {
     println!("--------------------------------------------------------------------------------");
     println!();
 
-    Ok(ModulesState { lora, mqtt, bme280 })
+    Ok(ModulesState { lora, lora_version, mqtt, bme280 })
 }
 
-fn post_lora(lora_config: &LoRaConfig) -> Result<()> {
-    if !Path::new(&lora_config.spi_config.spidev_path).exists() {
+/*
+ * Returns the silicon version read from the chip, the SX126x family has none
+ */
+fn post_lora(lora_config: &LoRaConfig) -> Result<Option<u8>> {
+    if lora_config.backend == Backend::Hardware && !Path::new(&lora_config.spi_config.spidev_path).exists() {
         eprintln!("[ ERR ] SPI POST");
         error!("[ ERR ] SPI POST");
         return Err(anyhow!(
@@ -68,32 +81,12 @@ fn post_lora(lora_config: &LoRaConfig) -> Result<()> {
             &lora_config.spi_config.spidev_path
         ));
     }
-    match lora_config.chip {
-        Chip::SX1278 => {
-            let mut lora = SX1278::from_config(lora_config)?;
-            let mut mode = 0;
-            lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode)?;
-            if mode == 0 {
-                eprintln!("[ ERR ] SPI POST");
-                error!("[ ERR ] SPI POST");
-                return Err(anyhow!("Unable to IO via SPI"));
-            }
-
-            lora.standby_mode()?;
-            lora.reset()?;
-            lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode)?;
-
-            if mode == 0 {
-                eprintln!("[ ERR ] SPI POST");
-                error!("[ ERR ] SPI POST");
-                return Err(anyhow!("Unable to IO via SPI"));
-            } else if mode != 9 {
-                eprintln!("[ ERR ] GPIO POST");
-                error!("[ ERR ] GPIO POST");
-                return Err(anyhow!("Unable to IO via GPIO"));
-            }
-        }
-    }
+    let chip_id = match (lora_config.chip.family(), lora_config.backend) {
+        (ChipFamily::SX127x, Backend::Hardware) => post_sx127x(SX1278::from_config(lora_config)?)?,
+        (ChipFamily::SX127x, Backend::Simulator) => post_sx127x(SX1278::simulated(lora_config)?)?,
+        (ChipFamily::SX126x, Backend::Hardware) => post_sx126x(SX126x::from_config(lora_config)?)?,
+        (ChipFamily::SX126x, Backend::Simulator) => post_sx126x(SX126x::simulated(lora_config)?)?,
+    };
 
     println!("[ OK ] SPI POST");
     info!("[ OK ] SPI POST");
@@ -101,6 +94,107 @@ fn post_lora(lora_config: &LoRaConfig) -> Result<()> {
     println!("[ OK ] GPIO POST");
     info!("[ OK ] GPIO POST");
 
+    match chip_id {
+        ChipId::Version(version) => {
+            check_chip_version(&lora_config.chip, version)?;
+
+            println!("[ OK ] CHIP POST: {:?} version 0x{:02X}", lora_config.chip, version);
+            info!("[ OK ] CHIP POST: {:?} version 0x{:02X}", lora_config.chip, version);
+
+            Ok(Some(version))
+        }
+        ChipId::VersionString(version_string) => {
+            check_chip_version_string(&lora_config.chip, &version_string)?;
+
+            println!("[ OK ] CHIP POST: {:?} {}", lora_config.chip, version_string);
+            info!("[ OK ] CHIP POST: {:?} {}", lora_config.chip, version_string);
+
+            Ok(None)
+        }
+    }
+}
+
+/*
+ * SX127x chips report a VERSION register, SX126x chips a version string
+ */
+enum ChipId {
+    Version(u8),
+    VersionString(String),
+}
+
+fn post_sx127x<SPI: SpiDevice, OUT: OutputPin, IN: WaitForHigh>(mut lora: SX1278<SPI, OUT, IN>) -> Result<ChipId> {
+    let mut mode = 0;
+    lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode)?;
+    if mode == 0 {
+        eprintln!("[ ERR ] SPI POST");
+        error!("[ ERR ] SPI POST");
+        return Err(anyhow!("Unable to IO via SPI"));
+    }
+
+    lora.standby_mode()?;
+    lora.reset()?;
+    lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode)?;
+
+    if mode == 0 {
+        eprintln!("[ ERR ] SPI POST");
+        error!("[ ERR ] SPI POST");
+        return Err(anyhow!("Unable to IO via SPI"));
+    } else if mode != 9 {
+        eprintln!("[ ERR ] GPIO POST");
+        error!("[ ERR ] GPIO POST");
+        return Err(anyhow!("Unable to IO via GPIO"));
+    }
+
+    Ok(ChipId::Version(lora.get_version()?))
+}
+
+fn post_sx126x<SPI: SpiDevice, OUT: OutputPin, IN: WaitForHigh>(mut lora: SX126x<SPI, OUT, IN>) -> Result<ChipId> {
+    // BUSY stuck high after reset times out
+    let status = match lora.reset().and_then(|_| lora.get_status()) {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("[ ERR ] GPIO POST");
+            error!("[ ERR ] GPIO POST");
+            return Err(e);
+        }
+    };
+    // a missing chip answers with 0x00 or 0xFF
+    if SX126xChipMode::from_status(status).is_err() {
+        eprintln!("[ ERR ] SPI POST");
+        error!("[ ERR ] SPI POST");
+        return Err(anyhow!("Unable to IO via SPI"));
+    }
+
+    Ok(ChipId::VersionString(lora.get_version_string()?))
+}
+
+fn check_chip_version_string(chip: &Chip, version_string: &str) -> Result<()> {
+    if !version_string.starts_with("SX126") {
+        eprintln!("[ ERR ] CHIP POST");
+        error!("[ ERR ] CHIP POST");
+        return Err(anyhow!(
+            "Chip mismatch: configured {:?}, found version string {:?}",
+            chip,
+            version_string
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_chip_version(chip: &Chip, version: u8) -> Result<()> {
+    let expected = chip.silicon_version().unwrap_or_default();
+    if version != expected {
+        eprintln!("[ ERR ] CHIP POST");
+        error!("[ ERR ] CHIP POST");
+        return Err(anyhow!(
+            "Chip mismatch: configured {:?} should report version 0x{:02X}, found 0x{:02X}",
+            chip,
+            expected,
+            version
+        ));
+    }
+
     Ok(())
 }
 
@@ -160,6 +254,44 @@ fn post_mqtt() -> Result<()> {
 
 pub struct ModulesState {
     pub lora: bool,
+    /// Silicon version read during POST
+    pub lora_version: Option<u8>,
     pub mqtt: bool,
     pub bme280: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_chip_version_correct() {
+        assert!(check_chip_version(&Chip::SX1278, 0x12).is_ok());
+        assert!(check_chip_version(&Chip::RFM95, 0x12).is_ok());
+    }
+
+    #[test]
+    fn check_chip_version_mismatch() {
+        // SX1261/2 and SX1272 report other versions, 0x00 and 0xFF mean nothing answered
+        for version in [0x00, 0x22, 0xFF] {
+            assert!(check_chip_version(&Chip::SX1278, version).is_err());
+        }
+    }
+
+    #[test]
+    fn check_chip_version_string_correct() {
+        assert!(check_chip_version_string(&Chip::SX1262, "SX1261 V2D 2D02").is_ok());
+        assert!(check_chip_version_string(&Chip::SX1262, "").is_err());
+    }
+
+    #[test]
+    fn post_lora_simulator() {
+        // the spidev path doesn't exist, the simulated chip is used instead
+        let config = Config::from_file("./tests/configs/simulator_conf.toml".to_string()).unwrap();
+        let mut lora_config = config.lora_config.unwrap();
+        assert_eq!(Some(0x12), post_lora(&lora_config).unwrap());
+
+        lora_config.backend = Backend::Hardware;
+        assert!(post_lora(&lora_config).is_err());
+    }
+}
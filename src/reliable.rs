@@ -0,0 +1,213 @@
+use crate::config::ReliableDeliveryConfig;
+use crate::packet::{Data, DataType, Packet};
+use anyhow::{anyhow, Context, Result};
+use log::{error, info};
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+ * Acknowledged delivery: every packet is answered with an ACK keyed by (device id, msg_id, msg_count),
+ * the sender retransmits with exponential backoff until it gets one. msg_count tells apart
+ * the parts of a multi-part message, each is acknowledged on its own
+ */
+
+pub struct ReliableDelivery {
+    config: ReliableDeliveryConfig,
+    /// (device id, msg_id, msg_count) of the packets received within the duplicate window
+    received: HashMap<(u8, u8, u8), Instant>,
+}
+
+impl ReliableDelivery {
+    pub fn new(config: &ReliableDeliveryConfig) -> Self {
+        Self {
+            config: config.clone(),
+            received: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &ReliableDeliveryConfig {
+        &self.config
+    }
+
+    /*
+     * Records the packet, returns true if the same device id, msg_id and msg_count was received
+     * within the duplicate window
+     */
+    pub fn is_duplicate(&mut self, packet: &Packet) -> bool {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.duplicate_window);
+        self.received
+            .retain(|_, received| now.duration_since(*received) < window);
+
+        self.received
+            .insert((packet.id, packet.msg_id, packet.msg_count), now)
+            .is_some()
+    }
+}
+
+pub fn ack_packet(packet: &Packet) -> Packet {
+    Packet {
+        version: 0,
+        id: packet.id,
+        msg_id: packet.msg_id,
+        msg_count: packet.msg_count,
+        data_type: DataType::Ack,
+        data: Data::Ack,
+    }
+}
+
+pub fn is_ack_for(ack: &Packet, packet: &Packet) -> bool {
+    ack.data_type == DataType::Ack
+        && ack.id == packet.id
+        && ack.msg_id == packet.msg_id
+        && ack.msg_count == packet.msg_count
+}
+
+/*
+ * Backoff before the given retransmission, counted from 1
+ */
+pub fn retransmission_backoff(config: &ReliableDeliveryConfig, retransmission: u32) -> Duration {
+    let factor = 1u64 << retransmission.saturating_sub(1).min(16);
+    Duration::from_millis(config.initial_backoff_ms.saturating_mul(factor))
+}
+
+/*
+ * Calls `transmission` until it returns true (the packet was acknowledged) or
+ * max_retransmissions runs out. `transmission` gets the ACK timeout, a failed transmission
+ * (e.g. busy channel) counts as a lost one
+ */
+pub fn retransmit_until_acked<F>(config: &ReliableDeliveryConfig, packet: &Packet, mut transmission: F) -> Result<()>
+where
+    F: FnMut(Duration) -> Result<bool>,
+{
+    let ack_timeout = Duration::from_millis(config.ack_timeout_ms);
+
+    for retransmission in 0..=config.max_retransmissions {
+        if retransmission > 0 {
+            let backoff = retransmission_backoff(config, retransmission);
+            println!("No ACK for msg_id {}, retransmitting in {} ms", packet.msg_id, backoff.as_millis());
+            info!("No ACK for msg_id {}, retransmitting in {} ms", packet.msg_id, backoff.as_millis());
+            thread::sleep(backoff);
+        }
+
+        match transmission(ack_timeout) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("{:?}", e);
+                error!("{:?}", e);
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "No ACK for msg_id {} after {} retransmissions",
+        packet.msg_id,
+        config.max_retransmissions
+    ))
+    .context("reliable::retransmit_until_acked")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::BME280;
+
+    fn reliable_delivery_config() -> ReliableDeliveryConfig {
+        ReliableDeliveryConfig {
+            ack_timeout_ms: 200,
+            max_retransmissions: 2,
+            initial_backoff_ms: 10,
+            duplicate_window: 60,
+        }
+    }
+
+    fn bme280_packet(id: u8, msg_id: u8) -> Packet {
+        Packet {
+            version: 0,
+            id,
+            msg_id,
+            msg_count: 1,
+            data_type: DataType::BME280,
+            data: Data::Bme280(BME280 {
+                temperature: 23,
+                humidity: 45,
+                pressure: 67,
+            }),
+        }
+    }
+
+    #[test]
+    fn is_ack_for_correct() {
+        let packet = bme280_packet(0x22, 0x11);
+        let ack = ack_packet(&packet);
+        assert!(is_ack_for(&ack, &packet));
+        assert!(!is_ack_for(&ack, &bme280_packet(0x22, 0x12)));
+        assert!(!is_ack_for(&ack, &bme280_packet(0x23, 0x11)));
+        // a packet is never its own ACK
+        assert!(!is_ack_for(&packet, &packet));
+
+        // the parts of a multi-part message are acknowledged one by one
+        let mut first_part = bme280_packet(0x22, 0x11);
+        first_part.msg_count = 0x02;
+        let mut second_part = bme280_packet(0x22, 0x11);
+        second_part.msg_count = 0x12;
+        let ack = Packet::new(&ack_packet(&second_part).to_bytes().unwrap()).unwrap();
+        assert!(is_ack_for(&ack, &second_part));
+        assert!(!is_ack_for(&ack, &first_part));
+    }
+
+    #[test]
+    fn is_duplicate_correct() {
+        let mut reliable_delivery = ReliableDelivery::new(&reliable_delivery_config());
+        assert!(!reliable_delivery.is_duplicate(&bme280_packet(0x22, 0x11)));
+        assert!(reliable_delivery.is_duplicate(&bme280_packet(0x22, 0x11)));
+        assert!(!reliable_delivery.is_duplicate(&bme280_packet(0x22, 0x12)));
+        assert!(!reliable_delivery.is_duplicate(&bme280_packet(0x23, 0x11)));
+
+        // repeats outside of the window are new packets
+        let mut config = reliable_delivery_config();
+        config.duplicate_window = 0;
+        let mut reliable_delivery = ReliableDelivery::new(&config);
+        assert!(!reliable_delivery.is_duplicate(&bme280_packet(0x22, 0x11)));
+        assert!(!reliable_delivery.is_duplicate(&bme280_packet(0x22, 0x11)));
+    }
+
+    #[test]
+    fn retransmission_backoff_correct() {
+        let config = reliable_delivery_config();
+        assert_eq!(Duration::from_millis(10), retransmission_backoff(&config, 1));
+        assert_eq!(Duration::from_millis(20), retransmission_backoff(&config, 2));
+        assert_eq!(Duration::from_millis(40), retransmission_backoff(&config, 3));
+    }
+
+    #[test]
+    fn retransmit_until_acked_correct() {
+        let config = reliable_delivery_config();
+        let packet = bme280_packet(0x22, 0x11);
+
+        // acknowledged on the second retransmission, a failed transmission counts as lost
+        let mut transmissions = 0;
+        let result = retransmit_until_acked(&config, &packet, |ack_timeout| {
+            assert_eq!(Duration::from_millis(200), ack_timeout);
+            transmissions += 1;
+            match transmissions {
+                1 => Err(anyhow!("Channel busy")),
+                2 => Ok(false),
+                _ => Ok(true),
+            }
+        });
+        assert!(result.is_ok());
+        assert_eq!(3, transmissions);
+
+        // never acknowledged, the first transmission and max_retransmissions
+        let mut transmissions = 0;
+        let result = retransmit_until_acked(&config, &packet, |_| {
+            transmissions += 1;
+            Ok(false)
+        });
+        assert!(result.is_err());
+        assert_eq!(3, transmissions);
+    }
+}
@@ -0,0 +1,1563 @@
+use crate::defines::*;
+use crate::hal::WaitForHigh;
+use crate::sx126x::RX_CONTINUOUS;
+use embedded_hal::digital::{self, InputPin, OutputPin};
+use embedded_hal::spi::{self, Operation, SpiDevice};
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+ * Simulator backend: an in-memory chip behind the same embedded-hal traits
+ * as the hardware backend, so the drivers run unchanged on any host
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulatedPinKind {
+    Reset,
+    Dio0,
+    Dio1,
+    Busy,
+}
+
+pub trait SimulatedDevice: Send {
+    /*
+     * One SPI transaction with chip select held low, full duplex
+     */
+    fn transfer(&mut self, write: &[u8], read: &mut [u8]);
+    fn reset(&mut self);
+    fn pin_level(&mut self, pin: SimulatedPinKind) -> bool;
+}
+
+pub struct SimulatedSpi<D> {
+    device: Arc<Mutex<D>>,
+}
+
+impl<D: SimulatedDevice> SimulatedSpi<D> {
+    pub fn new(device: Arc<Mutex<D>>) -> Self {
+        Self { device }
+    }
+
+    pub fn device(&self) -> MutexGuard<'_, D> {
+        self.device.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<D> spi::ErrorType for SimulatedSpi<D> {
+    type Error = Infallible;
+}
+
+impl<D: SimulatedDevice> SpiDevice for SimulatedSpi<D> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let mut write = Vec::new();
+        for operation in operations.iter() {
+            match operation {
+                Operation::Read(read) => write.resize(write.len() + read.len(), 0x00),
+                Operation::Write(data) => write.extend_from_slice(data),
+                Operation::Transfer(read, data) => {
+                    let end = write.len() + read.len().max(data.len());
+                    write.extend_from_slice(data);
+                    write.resize(end, 0x00);
+                }
+                Operation::TransferInPlace(data) => write.extend_from_slice(data),
+                Operation::DelayNs(_) => {}
+            }
+        }
+
+        let mut read = vec![0x00; write.len()];
+        self.device().transfer(&write, &mut read);
+
+        let mut offset = 0;
+        for operation in operations.iter_mut() {
+            match operation {
+                Operation::Read(buffer) | Operation::TransferInPlace(buffer) => {
+                    buffer.copy_from_slice(&read[offset..offset + buffer.len()]);
+                    offset += buffer.len();
+                }
+                Operation::Transfer(buffer, data) => {
+                    buffer.copy_from_slice(&read[offset..offset + buffer.len()]);
+                    offset += buffer.len().max(data.len());
+                }
+                Operation::Write(data) => offset += data.len(),
+                Operation::DelayNs(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Reset is an output resetting the chip on its rising edge, the other pins are inputs
+ */
+pub struct SimulatedPin<D> {
+    device: Arc<Mutex<D>>,
+    kind: SimulatedPinKind,
+    low: bool,
+}
+
+impl<D: SimulatedDevice> SimulatedPin<D> {
+    pub fn new(device: Arc<Mutex<D>>, kind: SimulatedPinKind) -> Self {
+        Self { device, kind, low: false }
+    }
+
+    pub fn device(&self) -> MutexGuard<'_, D> {
+        self.device.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<D> digital::ErrorType for SimulatedPin<D> {
+    type Error = Infallible;
+}
+
+impl<D: SimulatedDevice> OutputPin for SimulatedPin<D> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.low = true;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        if self.low && self.kind == SimulatedPinKind::Reset {
+            self.device().reset();
+        }
+        self.low = false;
+        Ok(())
+    }
+}
+
+impl<D: SimulatedDevice> InputPin for SimulatedPin<D> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.device().pin_level(self.kind))
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+/// Interval between level checks of the simulated pins, there is no line to block on
+const SIMULATED_PIN_POLL_INTERVAL_MS: u64 = 1;
+
+impl<D: SimulatedDevice> WaitForHigh for SimulatedPin<D> {
+    // the simulated chip catches up with time whenever a pin is read, so the levels are polled
+    fn wait_for_high(pins: &mut [&mut Self], timeout: Option<Duration>) -> Result<Option<usize>, Self::Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            for (index, pin) in pins.iter_mut().enumerate() {
+                if pin.is_high()? {
+                    return Ok(Some(index));
+                }
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+            }
+            thread::sleep(Duration::from_millis(SIMULATED_PIN_POLL_INTERVAL_MS));
+        }
+    }
+}
+
+/*
+ * Radio conditions of the simulated air, drawn for every receiver of every packet
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AirConditions {
+    /// Probability (0-1) that a receiver misses a packet
+    pub loss: f64,
+    /// Probability (0-1) that a packet arrives corrupted, caught by the receiver's CRC if enabled
+    pub crc_corruption: f64,
+    pub snr_db: f32,
+    pub rssi_dbm: i16,
+}
+
+impl Default for AirConditions {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            crc_corruption: 0.0,
+            snr_db: 9.0,
+            rssi_dbm: -60,
+        }
+    }
+}
+
+/*
+ * A receiver hears a packet only on the same frequency and modem settings
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    LoRa { frf: u32, bandwidth: u8, spreading_factor: u8 },
+    Fsk { frf: u32, modulation_type: u8 },
+}
+
+struct Transmission {
+    source: usize,
+    channel: Channel,
+    payload: Vec<u8>,
+    /// End of the transmission, receivers get the packet after it
+    end: Instant,
+}
+
+/// A packet as heard by one receiver
+struct Reception {
+    payload: Vec<u8>,
+    corrupted: bool,
+    snr_db: f32,
+    rssi_dbm: i16,
+}
+
+const AIR_LOG_LENGTH: usize = 256;
+
+#[derive(Default)]
+struct AirState {
+    conditions: AirConditions,
+    /// The last AIR_LOG_LENGTH transmissions, the oldest one has the position `first`
+    transmissions: VecDeque<Transmission>,
+    first: u64,
+    devices: usize,
+}
+
+/*
+ * In-process "air" shared by simulated chips. Transmissions are kept in a log and every chip
+ * keeps its own position in it, so a packet waits for the next RX mode of each receiver
+ */
+#[derive(Default)]
+pub struct SimulatedAir {
+    state: Mutex<AirState>,
+}
+
+impl SimulatedAir {
+    pub fn new(conditions: AirConditions) -> Arc<Self> {
+        let air = Self::default();
+        air.state().conditions = conditions;
+        Arc::new(air)
+    }
+
+    fn state(&self) -> MutexGuard<'_, AirState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /*
+     * Returns the id of a new chip and its position in the log, it hears only later packets
+     */
+    fn attach(&self) -> (usize, u64) {
+        let mut state = self.state();
+        state.devices += 1;
+        (state.devices, state.first + state.transmissions.len() as u64)
+    }
+
+    fn transmit(&self, source: usize, channel: Channel, payload: Vec<u8>, end: Instant) {
+        let mut state = self.state();
+        state.transmissions.push_back(Transmission { source, channel, payload, end });
+        if state.transmissions.len() > AIR_LOG_LENGTH {
+            state.transmissions.pop_front();
+            state.first += 1;
+        }
+    }
+
+    /*
+     * Whether another chip is transmitting on the channel right now
+     */
+    fn is_busy(&self, device: usize, channel: Channel) -> bool {
+        let now = Instant::now();
+        self.state().transmissions.iter().any(|transmission| {
+            transmission.source != device && transmission.channel == channel && transmission.end > now
+        })
+    }
+
+    /*
+     * Returns the first packet after `position` that reaches the receiver and moves `position` past it.
+     * Packets on other channels are skipped, the receiver wasn't tuned to them,
+     * a packet still on the air stops the search
+     */
+    fn receive(&self, device: usize, channel: Channel, position: &mut u64) -> Option<Reception> {
+        let state = self.state();
+        let conditions = state.conditions;
+        let mut rng = rand::thread_rng();
+
+        *position = (*position).max(state.first);
+        while let Some(transmission) = state.transmissions.get((*position - state.first) as usize) {
+            if transmission.channel == channel && transmission.source != device && transmission.end > Instant::now() {
+                return None;
+            }
+            *position += 1;
+            if transmission.source == device
+                || transmission.channel != channel
+                || rng.gen_bool(conditions.loss.clamp(0.0, 1.0))
+            {
+                continue;
+            }
+
+            let mut payload = transmission.payload.clone();
+            let corrupted = rng.gen_bool(conditions.crc_corruption.clamp(0.0, 1.0));
+            if corrupted && !payload.is_empty() {
+                let index = rng.gen_range(0..payload.len());
+                payload[index] ^= rng.gen_range(1..=0xFF);
+            }
+            return Some(Reception {
+                payload,
+                corrupted,
+                snr_db: conditions.snr_db,
+                rssi_dbm: conditions.rssi_dbm,
+            });
+        }
+        None
+    }
+}
+
+/// Registers 0x0D-0x3F are paged, the LoRa and FSK/OOK modems each have their own
+const PAGED_REGISTERS: RangeInclusive<usize> = 0x0D..=0x3F;
+
+/*
+ * SX127x chip with its register pages, FIFOs, operating modes and IRQ flags.
+ * DIO levels follow the IRQ flags through the DIO mapping,
+ * TX puts the packet on the air and RX takes the next one off it
+ */
+pub struct SimulatedSX1278 {
+    /// Common registers and the LoRa page
+    pub registers: [u8; 112],
+    /// FSK/OOK page, indexed by address
+    pub fsk_registers: [u8; 0x40],
+    /// LoRa FIFO, accessed through FIFO_ADDR_PTR
+    fifo: [u8; 256],
+    /// FSK/OOK FIFO, a plain queue without FIFO_ADDR_PTR
+    fsk_fifo: VecDeque<u8>,
+    /// Where the LoRa modem writes the next received packet
+    rx_byte_addr: u8,
+    /// RX_SINGLE gives up at this time without a packet
+    rx_single_deadline: Option<Instant>,
+    /// End of the packet being sent in TX
+    tx_end: Option<Instant>,
+    air: Arc<SimulatedAir>,
+    id: usize,
+    /// Position in the transmission log of the air
+    air_position: u64,
+    version: u8,
+}
+
+impl SimulatedSX1278 {
+    pub fn new(chip: Chip, air: Arc<SimulatedAir>) -> Self {
+        let version = chip.silicon_version().unwrap_or(0);
+        let (id, air_position) = air.attach();
+        Self {
+            registers: Self::reset_registers(version),
+            fsk_registers: Self::reset_fsk_registers(),
+            fifo: [0; 256],
+            fsk_fifo: VecDeque::new(),
+            rx_byte_addr: 0,
+            rx_single_deadline: None,
+            tx_end: None,
+            air,
+            id,
+            air_position,
+            version,
+        }
+    }
+
+    /*
+     * Reset values from the datasheet register table
+     */
+    fn reset_registers(version: u8) -> [u8; 112] {
+        let mut registers = [0; 112];
+        for (register, value) in [
+            // FSK/OOK standby
+            (SX1278LoRaRegister::OP_MODE as usize, 0x09),
+            (SX1278FskRegister::BITRATE_MSB as usize, 0x1A),
+            (SX1278FskRegister::BITRATE_LSB as usize, 0x0B),
+            (SX1278FskRegister::FDEV_LSB as usize, 0x52),
+            (SX1278LoRaRegister::FRF_MSB as usize, 0x6C),
+            (SX1278LoRaRegister::FRF_MID as usize, 0x80),
+            (SX1278LoRaRegister::PA_CONFIG as usize, 0x4F),
+            (SX1278FskRegister::PA_RAMP as usize, 0x09),
+            (SX1278LoRaRegister::OCP as usize, 0x2B),
+            (SX1278LoRaRegister::LNA as usize, 0x20),
+            (SX1278LoRaRegister::FIFO_TX_BASE_ADDR as usize, 0x80),
+            (SX1278LoRaRegister::MODEM_CONFIG_1 as usize, 0x72),
+            (SX1278LoRaRegister::MODEM_CONFIG_2 as usize, 0x70),
+            (SX1278LoRaRegister::SYMB_TIMEOUT_LSB as usize, 0x64),
+            (SX1278LoRaRegister::PREAMBLE_LSB as usize, 0x08),
+            (SX1278LoRaRegister::PAYLOAD_LENGTH as usize, 0x01),
+            (SX1278LoRaRegister::MODEM_CONFIG_3 as usize, 0x04),
+            (SX1278LoRaRegister::DETECTION_OPTIMIZE as usize, 0xC3),
+            (SX1278LoRaRegister::DETECTION_THRESHOLD as usize, 0x0A),
+            (SX1278LoRaRegister::SYNC_WORD as usize, 0x12),
+            (SX1278LoRaRegister::VERSION as usize, version),
+            (SX1278LoRaRegister::PA_DAC as usize, 0x84),
+        ] {
+            registers[register] = value;
+        }
+        registers
+    }
+
+    fn reset_fsk_registers() -> [u8; 0x40] {
+        let mut registers = [0; 0x40];
+        for (register, value) in [
+            (SX1278FskRegister::RX_CONFIG, 0x0E),
+            (SX1278FskRegister::RX_BW, 0x15),
+            (SX1278FskRegister::AFC_BW, 0x0B),
+            (SX1278FskRegister::PREAMBLE_DETECT, 0xAA),
+            (SX1278FskRegister::PREAMBLE_LSB, 0x03),
+            (SX1278FskRegister::SYNC_CONFIG, 0x93),
+            (SX1278FskRegister::SYNC_VALUE_1, 0x01),
+            (SX1278FskRegister::PACKET_CONFIG_1, 0x90),
+            (SX1278FskRegister::PACKET_CONFIG_2, 0x40),
+            (SX1278FskRegister::PAYLOAD_LENGTH, 0x40),
+            (SX1278FskRegister::FIFO_THRESH, 0x0F),
+            // ModeReady
+            (SX1278FskRegister::IRQ_FLAGS_1, 0x80),
+        ] {
+            registers[register as usize] = value;
+        }
+        registers
+    }
+
+    fn long_range_mode(&self) -> bool {
+        self.registers[SX1278LoRaRegister::OP_MODE as usize] & SX1278LoRaMode::LONG_RANGE as u8 != 0
+    }
+
+    fn op_mode(&self) -> u8 {
+        self.registers[SX1278LoRaRegister::OP_MODE as usize] & 0x07
+    }
+
+    fn register(&self, address: usize) -> u8 {
+        if PAGED_REGISTERS.contains(&address) && !self.long_range_mode() {
+            return self.fsk_registers[address];
+        }
+        *self.registers.get(address).unwrap_or(&0)
+    }
+
+    fn register_mut(&mut self, address: usize) -> Option<&mut u8> {
+        if PAGED_REGISTERS.contains(&address) && !self.long_range_mode() {
+            return self.fsk_registers.get_mut(address);
+        }
+        self.registers.get_mut(address)
+    }
+
+    fn read(&mut self, address: usize) -> u8 {
+        if address == SX1278LoRaRegister::FIFO as usize && !self.long_range_mode() {
+            let value = self.fsk_fifo.pop_front().unwrap_or(0);
+            // PayloadReady and CrcOk are cleared once the FIFO is empty
+            if self.fsk_fifo.is_empty() {
+                self.fsk_registers[SX1278FskRegister::IRQ_FLAGS_2 as usize] &= !(SX1278FskIRQMask::IRQ_PAYLOAD_READY_MASK
+                    as u8
+                    | SX1278FskIRQMask::IRQ_CRC_OK_MASK as u8);
+            }
+            return value;
+        }
+        if address == SX1278LoRaRegister::FIFO as usize {
+            let pointer = SX1278LoRaRegister::FIFO_ADDR_PTR as usize;
+            let value = self.fifo[self.registers[pointer] as usize];
+            self.registers[pointer] = self.registers[pointer].wrapping_add(1);
+            return value;
+        }
+
+        self.register(address)
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        if address == SX1278LoRaRegister::FIFO as usize && !self.long_range_mode() {
+            self.fsk_fifo.push_back(value);
+            return;
+        }
+        if address == SX1278LoRaRegister::FIFO as usize {
+            let pointer = SX1278LoRaRegister::FIFO_ADDR_PTR as usize;
+            self.fifo[self.registers[pointer] as usize] = value;
+            self.registers[pointer] = self.registers[pointer].wrapping_add(1);
+            return;
+        }
+        if address == SX1278LoRaRegister::OP_MODE as usize {
+            self.set_op_mode(value);
+            return;
+        }
+
+        // IRQ flags are cleared by writing 1s, in FSK/OOK mode only the latched ones
+        let irq_flags_mask = match self.long_range_mode() {
+            true if address == SX1278LoRaRegister::IRQ_FLAGS as usize => Some(0xFF),
+            false if address == SX1278FskRegister::IRQ_FLAGS_1 as usize => Some(0x0B),
+            false if address == SX1278FskRegister::IRQ_FLAGS_2 as usize => Some(0x11),
+            _ => None,
+        };
+        match (self.register_mut(address), irq_flags_mask) {
+            (Some(register), Some(mask)) => *register &= !(value & mask),
+            (Some(register), None) => *register = value,
+            (None, _) => {}
+        }
+    }
+
+    /*
+     * Mode transitions: the FIFO is cleared in SLEEP, TX sends the packet for its time on air,
+     * RX starts writing packets at FIFO_RX_BASE_ADDR and CAD is done right away
+     */
+    fn set_op_mode(&mut self, value: u8) {
+        let previous_mode = self.op_mode();
+        self.registers[SX1278LoRaRegister::OP_MODE as usize] = value;
+        let mode = self.op_mode();
+
+        if mode == SX1278LoRaMode::SLEEP as u8 {
+            self.fifo = [0; 256];
+            self.fsk_fifo.clear();
+        }
+        // PacketSent is cleared when leaving TX
+        if previous_mode == SX1278LoRaMode::TX as u8 && mode != SX1278LoRaMode::TX as u8 {
+            self.fsk_registers[SX1278FskRegister::IRQ_FLAGS_2 as usize] &= !(SX1278FskIRQMask::IRQ_PACKET_SENT_MASK as u8);
+        }
+        if mode != previous_mode && (mode == SX1278LoRaMode::RX_CONTINUOUS as u8 || mode == SX1278LoRaMode::RX_SINGLE as u8) {
+            self.rx_byte_addr = self.registers[SX1278LoRaRegister::FIFO_RX_BASE_ADDR as usize];
+        }
+        self.rx_single_deadline = None;
+        if mode != SX1278LoRaMode::TX as u8 {
+            self.tx_end = None;
+        }
+
+        match mode {
+            m if m == SX1278LoRaMode::TX as u8 && mode != previous_mode => self.transmit(),
+            m if m == SX1278LoRaMode::RX_SINGLE as u8 && self.long_range_mode() => {
+                self.rx_single_deadline = Some(Instant::now() + self.symbol_timeout());
+            }
+            m if m == SX1278LoRaMode::CAD as u8 && self.long_range_mode() => {
+                let mut irq_flags = SX1278IRQMask::IRQ_CAD_DONE_MASK as u8;
+                if self.air.is_busy(self.id, self.channel()) {
+                    irq_flags |= SX1278IRQMask::IRQ_CAD_DETECTED_MASK as u8;
+                }
+                self.registers[SX1278LoRaRegister::IRQ_FLAGS as usize] |= irq_flags;
+                self.return_to_standby();
+            }
+            _ => {}
+        }
+        self.update();
+    }
+
+    fn return_to_standby(&mut self) {
+        let op_mode = &mut self.registers[SX1278LoRaRegister::OP_MODE as usize];
+        *op_mode = (*op_mode & !0x07) | SX1278LoRaMode::STDBY as u8;
+        self.rx_single_deadline = None;
+    }
+
+    /*
+     * LoRa symbol time 2^SF / BW in microseconds
+     */
+    fn symbol_us(&self) -> f64 {
+        let bandwidth = Bandwidth::from_register(self.registers[SX1278LoRaRegister::MODEM_CONFIG_1 as usize] >> 4)
+            .map_or(125_000, |bandwidth| bandwidth.hz());
+        let spreading_factor = self.registers[SX1278LoRaRegister::MODEM_CONFIG_2 as usize] >> 4;
+        (1u64 << spreading_factor) as f64 * 1_000_000.0 / bandwidth as f64
+    }
+
+    fn symbol_timeout(&self) -> Duration {
+        let symbols = ((self.registers[SX1278LoRaRegister::MODEM_CONFIG_2 as usize] as u64 & 0x03) << 8)
+            | self.registers[SX1278LoRaRegister::SYMB_TIMEOUT_LSB as usize] as u64;
+        Duration::from_micros((symbols as f64 * self.symbol_us()) as u64)
+    }
+
+    /*
+     * LoRa time on air from the datasheet:
+     * (preamble + 4.25 + 8 + max(ceil((8PL - 4SF + 28 + 16CRC - 20IH) / 4(SF - 2DE)) * (CR + 4), 0)) symbols,
+     * FSK/OOK sends preamble, sync word, length byte, payload and CRC at the bitrate
+     */
+    fn time_on_air(&self, payload_length: usize) -> Duration {
+        if !self.long_range_mode() {
+            let bitrate_register = u16::from_be_bytes([
+                self.registers[SX1278FskRegister::BITRATE_MSB as usize],
+                self.registers[SX1278FskRegister::BITRATE_LSB as usize],
+            ]);
+            let bitrate = FXOSC as f64 / bitrate_register.max(1) as f64;
+            let preamble = u16::from_be_bytes([
+                self.fsk_registers[SX1278FskRegister::PREAMBLE_MSB as usize],
+                self.fsk_registers[SX1278FskRegister::PREAMBLE_LSB as usize],
+            ]) as usize;
+            let sync_word = (self.fsk_registers[SX1278FskRegister::SYNC_CONFIG as usize] & 0x07) as usize + 1;
+            let crc = match self.fsk_registers[SX1278FskRegister::PACKET_CONFIG_1 as usize]
+                & SX1278FskPacketConfig1::CRC_ON as u8
+            {
+                0 => 0,
+                _ => 2,
+            };
+            let bits = (preamble + sync_word + 1 + payload_length + crc) * 8;
+            return Duration::from_micros((bits as f64 * 1_000_000.0 / bitrate) as u64);
+        }
+
+        let modem_config_1 = self.registers[SX1278LoRaRegister::MODEM_CONFIG_1 as usize];
+        let modem_config_2 = self.registers[SX1278LoRaRegister::MODEM_CONFIG_2 as usize];
+        let modem_config_3 = self.registers[SX1278LoRaRegister::MODEM_CONFIG_3 as usize];
+        let spreading_factor = (modem_config_2 >> 4) as f64;
+        let coding_rate = ((modem_config_1 >> 1) & 0x07) as f64;
+        let implicit_header = (modem_config_1 & 0x01) as f64;
+        let crc = ((modem_config_2 >> 2) & 0x01) as f64;
+        let low_data_rate_optimize = ((modem_config_3 >> 3) & 0x01) as f64;
+        let preamble = u16::from_be_bytes([
+            self.registers[SX1278LoRaRegister::PREAMBLE_MSB as usize],
+            self.registers[SX1278LoRaRegister::PREAMBLE_LSB as usize],
+        ]) as f64;
+
+        let payload_symbols = ((8.0 * payload_length as f64 - 4.0 * spreading_factor + 28.0 + 16.0 * crc
+            - 20.0 * implicit_header)
+            / (4.0 * (spreading_factor - 2.0 * low_data_rate_optimize)))
+            .ceil()
+            * (coding_rate + 4.0);
+        let symbols = preamble + 4.25 + 8.0 + payload_symbols.max(0.0);
+        Duration::from_micros((symbols * self.symbol_us()) as u64)
+    }
+
+    fn channel(&self) -> Channel {
+        let frf = u32::from_be_bytes([
+            0,
+            self.registers[SX1278LoRaRegister::FRF_MSB as usize],
+            self.registers[SX1278LoRaRegister::FRF_MID as usize],
+            self.registers[SX1278LoRaRegister::FRF_LSB as usize],
+        ]);
+        match self.long_range_mode() {
+            true => Channel::LoRa {
+                frf,
+                bandwidth: self.registers[SX1278LoRaRegister::MODEM_CONFIG_1 as usize] >> 4,
+                spreading_factor: self.registers[SX1278LoRaRegister::MODEM_CONFIG_2 as usize] >> 4,
+            },
+            false => Channel::Fsk {
+                frf,
+                modulation_type: self.registers[SX1278LoRaRegister::OP_MODE as usize] & 0x60,
+            },
+        }
+    }
+
+    /*
+     * LoRa sends PAYLOAD_LENGTH bytes from FIFO_TX_BASE_ADDR,
+     * FSK/OOK sends a variable length packet from the FIFO
+     */
+    fn transmit(&mut self) {
+        let payload: Vec<u8> = match self.long_range_mode() {
+            true => {
+                let base = self.registers[SX1278LoRaRegister::FIFO_TX_BASE_ADDR as usize];
+                let length = self.registers[SX1278LoRaRegister::PAYLOAD_LENGTH as usize];
+                (0..length).map(|i| self.fifo[base.wrapping_add(i) as usize]).collect()
+            }
+            false => {
+                let Some(length) = self.fsk_fifo.pop_front() else {
+                    return;
+                };
+                let length = (length as usize).min(self.fsk_fifo.len());
+                self.fsk_fifo.drain(..length).collect()
+            }
+        };
+
+        let end = Instant::now() + self.time_on_air(payload.len());
+        self.air.transmit(self.id, self.channel(), payload, end);
+        self.tx_end = Some(end);
+    }
+
+    /*
+     * LoRa raises TxDone and returns to STDBY, FSK/OOK raises PacketSent and stays in TX
+     */
+    fn transmit_done(&mut self) {
+        self.tx_end = None;
+        if self.long_range_mode() {
+            self.registers[SX1278LoRaRegister::IRQ_FLAGS as usize] |= SX1278IRQMask::IRQ_TX_DONE_MASK as u8;
+            self.return_to_standby();
+        } else {
+            self.fsk_registers[SX1278FskRegister::IRQ_FLAGS_2 as usize] |= SX1278FskIRQMask::IRQ_PACKET_SENT_MASK as u8;
+        }
+    }
+
+    /*
+     * Ends the transmission after its time on air. In RX mode takes the next packet
+     * off the air once the last one is handled, RX_SINGLE times out after SYMB_TIMEOUT symbols
+     */
+    fn update(&mut self) {
+        if self.tx_end.is_some_and(|end| Instant::now() >= end) {
+            self.transmit_done();
+        }
+
+        let mode = self.op_mode();
+        let receiving = mode == SX1278LoRaMode::RX_CONTINUOUS as u8
+            || (mode == SX1278LoRaMode::RX_SINGLE as u8 && self.long_range_mode());
+        if !receiving {
+            return;
+        }
+
+        let pending = match self.long_range_mode() {
+            true => self.registers[SX1278LoRaRegister::IRQ_FLAGS as usize] & SX1278IRQMask::IRQ_RX_DONE_MASK as u8,
+            false => {
+                self.fsk_registers[SX1278FskRegister::IRQ_FLAGS_2 as usize]
+                    & SX1278FskIRQMask::IRQ_PAYLOAD_READY_MASK as u8
+            }
+        };
+        if pending != 0 {
+            return;
+        }
+
+        match self.air.receive(self.id, self.channel(), &mut self.air_position) {
+            Some(reception) if self.long_range_mode() => self.receive_lora(reception),
+            Some(reception) => self.receive_fsk(reception),
+            None => {
+                if self.rx_single_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    self.registers[SX1278LoRaRegister::IRQ_FLAGS as usize] |=
+                        SX1278IRQMask::IRQ_RX_TIMEOUT_MASK as u8;
+                    self.return_to_standby();
+                }
+            }
+        }
+    }
+
+    fn receive_lora(&mut self, reception: Reception) {
+        let start = self.rx_byte_addr;
+        for byte in &reception.payload {
+            self.fifo[self.rx_byte_addr as usize] = *byte;
+            self.rx_byte_addr = self.rx_byte_addr.wrapping_add(1);
+        }
+        self.registers[SX1278LoRaRegister::FIFO_RX_CURRENT_ADDR as usize] = start;
+        self.registers[SX1278LoRaRegister::RX_NB_BYTES as usize] = reception.payload.len() as u8;
+
+        // inverse of packet_snr_db and packet_rssi_dbm
+        let snr = (reception.snr_db * 4.0).round().clamp(-128.0, 127.0) as i8;
+        let offset = match self.registers[SX1278LoRaRegister::OP_MODE as usize] & SX1278LoRaMode::LOW_FREQUENCY_MODE_ON as u8 {
+            0 => -157.0,
+            _ => -164.0,
+        };
+        let rssi = reception.rssi_dbm as f32 - offset;
+        let rssi = match reception.snr_db < 0.0 {
+            true => rssi - snr as f32 / 4.0,
+            false => rssi * 15.0 / 16.0,
+        };
+        self.registers[SX1278LoRaRegister::PKT_SNR_VALUE as usize] = snr as u8;
+        self.registers[SX1278LoRaRegister::PKT_RSSI_VALUE as usize] = rssi.round().clamp(0.0, 255.0) as u8;
+
+        let crc_on = self.registers[SX1278LoRaRegister::MODEM_CONFIG_2 as usize] & 0x04 != 0;
+        let mut irq_flags = SX1278IRQMask::IRQ_RX_DONE_MASK as u8;
+        if crc_on && reception.corrupted {
+            irq_flags |= SX1278IRQMask::IRQ_PAYLOAD_CRC_ERROR as u8;
+        }
+        self.registers[SX1278LoRaRegister::IRQ_FLAGS as usize] |= irq_flags;
+
+        if self.op_mode() == SX1278LoRaMode::RX_SINGLE as u8 {
+            self.return_to_standby();
+        }
+    }
+
+    /*
+     * Variable length packets are preceded by their length in the FIFO,
+     * packets failing the CRC are dropped unless CrcAutoClearOff is set
+     */
+    fn receive_fsk(&mut self, reception: Reception) {
+        let packet_config = self.fsk_registers[SX1278FskRegister::PACKET_CONFIG_1 as usize];
+        let crc_on = packet_config & SX1278FskPacketConfig1::CRC_ON as u8 != 0;
+        let crc_auto_clear_off = packet_config & SX1278FskPacketConfig1::CRC_AUTO_CLEAR_OFF as u8 != 0;
+        if crc_on && reception.corrupted && !crc_auto_clear_off {
+            return;
+        }
+
+        self.fsk_fifo.push_back(reception.payload.len() as u8);
+        self.fsk_fifo.extend(&reception.payload);
+        self.fsk_registers[SX1278FskRegister::RSSI_VALUE as usize] =
+            (-(reception.rssi_dbm as i32) * 2).clamp(0, 255) as u8;
+
+        let mut irq_flags = SX1278FskIRQMask::IRQ_PAYLOAD_READY_MASK as u8;
+        if crc_on && !reception.corrupted {
+            irq_flags |= SX1278FskIRQMask::IRQ_CRC_OK_MASK as u8;
+        }
+        self.fsk_registers[SX1278FskRegister::IRQ_FLAGS_2 as usize] |= irq_flags;
+    }
+
+    /*
+     * LoRa DIO mappings 00-10: DIO0 RxDone, TxDone, CadDone and DIO1 RxTimeout, FhssChangeChannel, CadDetected.
+     * FSK/OOK DIO0 mapping 00 is PacketSent in TX and PayloadReady otherwise, 01 is CrcOk
+     */
+    fn dio_level(&self, pin: SimulatedPinKind) -> bool {
+        let dio_mapping = self.registers[SX1278LoRaRegister::DIO_MAPPING_1 as usize];
+        if !self.long_range_mode() {
+            let irq_flags = self.fsk_registers[SX1278FskRegister::IRQ_FLAGS_2 as usize];
+            let mask = match (pin, dio_mapping >> 6) {
+                (SimulatedPinKind::Dio0, 0b00) if self.op_mode() == SX1278LoRaMode::TX as u8 => {
+                    SX1278FskIRQMask::IRQ_PACKET_SENT_MASK as u8
+                }
+                (SimulatedPinKind::Dio0, 0b00) => SX1278FskIRQMask::IRQ_PAYLOAD_READY_MASK as u8,
+                (SimulatedPinKind::Dio0, 0b01) => SX1278FskIRQMask::IRQ_CRC_OK_MASK as u8,
+                _ => 0,
+            };
+            return irq_flags & mask != 0;
+        }
+
+        let irq_flags = self.registers[SX1278LoRaRegister::IRQ_FLAGS as usize];
+        let mask = match (pin, dio_mapping >> 6, (dio_mapping >> 4) & 0x03) {
+            (SimulatedPinKind::Dio0, 0b00, _) => SX1278IRQMask::IRQ_RX_DONE_MASK,
+            (SimulatedPinKind::Dio0, 0b01, _) => SX1278IRQMask::IRQ_TX_DONE_MASK,
+            (SimulatedPinKind::Dio0, 0b10, _) => SX1278IRQMask::IRQ_CAD_DONE_MASK,
+            (SimulatedPinKind::Dio1, _, 0b00) => SX1278IRQMask::IRQ_RX_TIMEOUT_MASK,
+            (SimulatedPinKind::Dio1, _, 0b01) => SX1278IRQMask::IRQ_FHSS_CHANGE_CHANNEL_MASK,
+            (SimulatedPinKind::Dio1, _, 0b10) => SX1278IRQMask::IRQ_CAD_DETECTED_MASK,
+            _ => return false,
+        };
+        irq_flags & mask as u8 != 0
+    }
+}
+
+impl SimulatedDevice for SimulatedSX1278 {
+    /*
+     * The first byte is the address with the write bit, the address is incremented
+     * after each data byte, except for the FIFO
+     */
+    fn transfer(&mut self, write: &[u8], read: &mut [u8]) {
+        self.update();
+        let Some(first) = write.first() else {
+            return;
+        };
+        let mut address = (first & !(SPIIO::SPI_WRITE as u8)) as usize;
+        let is_write = first & SPIIO::SPI_WRITE as u8 != 0;
+        for (i, value) in write.iter().enumerate().skip(1) {
+            if is_write {
+                self.write(address, *value);
+            } else {
+                read[i] = self.read(address);
+            }
+            if address != SX1278LoRaRegister::FIFO as usize {
+                address += 1;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.registers = Self::reset_registers(self.version);
+        self.fsk_registers = Self::reset_fsk_registers();
+        self.fifo = [0; 256];
+        self.fsk_fifo.clear();
+        self.rx_single_deadline = None;
+        self.tx_end = None;
+    }
+
+    fn pin_level(&mut self, pin: SimulatedPinKind) -> bool {
+        self.update();
+        self.dio_level(pin)
+    }
+}
+
+const SX126X_COMMANDS: [SX126xCommand; 25] = [
+    SX126xCommand::SET_SLEEP,
+    SX126xCommand::SET_STANDBY,
+    SX126xCommand::SET_TX,
+    SX126xCommand::SET_RX,
+    SX126xCommand::SET_REGULATOR_MODE,
+    SX126xCommand::CALIBRATE,
+    SX126xCommand::CALIBRATE_IMAGE,
+    SX126xCommand::SET_PA_CONFIG,
+    SX126xCommand::WRITE_REGISTER,
+    SX126xCommand::READ_REGISTER,
+    SX126xCommand::WRITE_BUFFER,
+    SX126xCommand::READ_BUFFER,
+    SX126xCommand::SET_DIO_IRQ_PARAMS,
+    SX126xCommand::GET_IRQ_STATUS,
+    SX126xCommand::CLEAR_IRQ_STATUS,
+    SX126xCommand::SET_DIO2_AS_RF_SWITCH_CTRL,
+    SX126xCommand::SET_RF_FREQUENCY,
+    SX126xCommand::SET_PACKET_TYPE,
+    SX126xCommand::SET_TX_PARAMS,
+    SX126xCommand::SET_MODULATION_PARAMS,
+    SX126xCommand::SET_PACKET_PARAMS,
+    SX126xCommand::SET_BUFFER_BASE_ADDRESS,
+    SX126xCommand::GET_STATUS,
+    SX126xCommand::GET_RX_BUFFER_STATUS,
+    SX126xCommand::GET_PACKET_STATUS,
+];
+
+/*
+ * SX126x command interface. A packet sent is looped back to the next receive,
+ * a receive with a timeout and nothing to receive times out. BUSY is never high
+ */
+pub struct SimulatedSX126x {
+    pub registers: HashMap<u16, u8>,
+    pub buffer: [u8; 256],
+    pub irq_status: u16,
+    dio1_mask: u16,
+    chip_mode: SX126xChipMode,
+    packet_params: [u8; 6],
+    /// Length of the last packet sent, received back by the next SetRx
+    tx_length: Option<u8>,
+    rx_length: u8,
+}
+
+impl SimulatedSX126x {
+    pub fn new() -> Self {
+        Self {
+            registers: Self::reset_registers(),
+            buffer: [0; 256],
+            irq_status: 0,
+            dio1_mask: 0,
+            chip_mode: SX126xChipMode::STBY_RC,
+            packet_params: [0; 6],
+            tx_length: None,
+            rx_length: 0,
+        }
+    }
+
+    fn reset_registers() -> HashMap<u16, u8> {
+        let mut registers = HashMap::new();
+        let version_string = b"SX1261 V2D 2D02\0";
+        for (i, value) in version_string.iter().enumerate() {
+            registers.insert(SX126xRegister::VERSION_STRING as u16 + i as u16, *value);
+        }
+        registers.insert(SX126xRegister::RX_GAIN as u16, SX126xConfig::RX_GAIN_POWER_SAVING as u8);
+        registers
+    }
+
+    /*
+     * Read commands have a fixed number of parameters before the status byte,
+     * every byte of the other commands is a parameter
+     */
+    fn parameter_count(command: SX126xCommand, length: usize) -> usize {
+        match command {
+            SX126xCommand::READ_REGISTER => 2,
+            SX126xCommand::READ_BUFFER => 1,
+            SX126xCommand::GET_STATUS
+            | SX126xCommand::GET_IRQ_STATUS
+            | SX126xCommand::GET_RX_BUFFER_STATUS
+            | SX126xCommand::GET_PACKET_STATUS => 0,
+            _ => length,
+        }
+    }
+
+    /*
+     * The response starts with the status byte, followed by the data of read commands
+     */
+    fn command(&mut self, command: SX126xCommand, params: &[u8], response: &mut [u8]) {
+        match command {
+            SX126xCommand::SET_SLEEP | SX126xCommand::SET_STANDBY => {
+                self.chip_mode = match (command, params) {
+                    (SX126xCommand::SET_STANDBY, [0x01]) => SX126xChipMode::STBY_XOSC,
+                    _ => SX126xChipMode::STBY_RC,
+                };
+            }
+            SX126xCommand::SET_TX => {
+                self.tx_length = Some(self.packet_params[3]);
+                self.irq_status |= SX126xIrq::TX_DONE as u16;
+                self.chip_mode = SX126xChipMode::STBY_RC;
+            }
+            SX126xCommand::SET_RX => {
+                let timeout = u32::from_be_bytes([0, params[0], params[1], params[2]]);
+                match self.tx_length.take() {
+                    Some(length) => {
+                        self.rx_length = length;
+                        self.irq_status |= SX126xIrq::RX_DONE as u16;
+                    }
+                    None if timeout != RX_CONTINUOUS => {
+                        self.irq_status |= SX126xIrq::TIMEOUT as u16;
+                    }
+                    None => {}
+                }
+                self.chip_mode = SX126xChipMode::RX;
+            }
+            SX126xCommand::WRITE_REGISTER => {
+                let address = u16::from_be_bytes([params[0], params[1]]);
+                for (i, value) in params[2..].iter().enumerate() {
+                    self.registers.insert(address + i as u16, *value);
+                }
+            }
+            SX126xCommand::READ_REGISTER => {
+                let address = u16::from_be_bytes([params[0], params[1]]);
+                for (i, value) in response.iter_mut().skip(1).enumerate() {
+                    *value = *self.registers.get(&(address + i as u16)).unwrap_or(&0);
+                }
+            }
+            SX126xCommand::WRITE_BUFFER => {
+                for (i, value) in params[1..].iter().enumerate() {
+                    self.buffer[params[0].wrapping_add(i as u8) as usize] = *value;
+                }
+            }
+            SX126xCommand::READ_BUFFER => {
+                for (i, value) in response.iter_mut().skip(1).enumerate() {
+                    *value = self.buffer[params[0].wrapping_add(i as u8) as usize];
+                }
+            }
+            SX126xCommand::SET_DIO_IRQ_PARAMS => {
+                self.dio1_mask = u16::from_be_bytes([params[2], params[3]]);
+            }
+            SX126xCommand::GET_IRQ_STATUS => {
+                response[1..3].copy_from_slice(&self.irq_status.to_be_bytes());
+            }
+            SX126xCommand::CLEAR_IRQ_STATUS => {
+                self.irq_status &= !u16::from_be_bytes([params[0], params[1]]);
+            }
+            SX126xCommand::SET_PACKET_PARAMS => {
+                self.packet_params.copy_from_slice(params);
+            }
+            SX126xCommand::GET_RX_BUFFER_STATUS => {
+                response[1] = self.rx_length;
+                response[2] = 0x00;
+            }
+            SX126xCommand::GET_PACKET_STATUS => {
+                // -64 dBm, 10 dB SNR
+                response[1..4].copy_from_slice(&[0x80, 0x28, 0x80]);
+            }
+            _ => {}
+        }
+        if let Some(status) = response.first_mut() {
+            *status = (self.chip_mode as u8) << 4;
+        }
+    }
+}
+
+impl SimulatedDevice for SimulatedSX126x {
+    fn transfer(&mut self, write: &[u8], read: &mut [u8]) {
+        let Some(command) = write
+            .first()
+            .and_then(|opcode| SX126X_COMMANDS.iter().find(|command| **command as u8 == *opcode))
+        else {
+            return;
+        };
+        let count = Self::parameter_count(*command, write.len() - 1);
+        let (params, _) = write[1..].split_at(count);
+        self.command(*command, params, &mut read[1 + count..]);
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn pin_level(&mut self, pin: SimulatedPinKind) -> bool {
+        match pin {
+            SimulatedPinKind::Dio1 => self.irq_status & self.dio1_mask != 0,
+            SimulatedPinKind::Reset | SimulatedPinKind::Dio0 | SimulatedPinKind::Busy => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::*;
+    use crate::csv_writer::CSVPacketWrapper;
+    use crate::encryption::Encryption;
+    use crate::lora::LoRa;
+    use crate::mqtt::MQTTMessage;
+    use crate::packet::{Data, DataType, Packet, BME280, DATA_SIZE};
+    use crate::sx1278::{ReceiveResult, SX1278};
+    use crate::tx_queue::{TxMessage, TxQueue};
+    use log::error;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    macro_rules! handle_error {
+        ($func:expr) => {
+            match $func {
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                    std::process::exit(-1);
+                }
+                Ok(s) => s,
+            }
+        };
+    }
+
+    type SimulatedSX1278Driver = SX1278<
+        SimulatedSpi<SimulatedSX1278>,
+        SimulatedPin<SimulatedSX1278>,
+        SimulatedPin<SimulatedSX1278>,
+    >;
+
+    /*
+     * A configured transmitter and receiver on a new air, SF7 at 500 kHz
+     */
+    fn simulated_pair(conditions: AirConditions) -> (SimulatedSX1278Driver, SimulatedSX1278Driver) {
+        simulated_pair_from_config("./tests/configs/simulator_conf.toml", conditions)
+    }
+
+    fn simulated_pair_from_config(
+        config_path: &str,
+        conditions: AirConditions,
+    ) -> (SimulatedSX1278Driver, SimulatedSX1278Driver) {
+        let config = handle_error!(Config::from_file(config_path.to_string()));
+        let mut lora_config = config.lora_config.unwrap();
+        let air = SimulatedAir::new(conditions);
+
+        let mut receiver = handle_error!(SX1278::simulated_on_air(&lora_config, air.clone()));
+        handle_error!(receiver.configure_lora(&lora_config.radio_config));
+        lora_config.mode = Mode::TX;
+        let mut transmitter = handle_error!(SX1278::simulated_on_air(&lora_config, air));
+        handle_error!(transmitter.configure_lora(&lora_config.radio_config));
+        handle_error!(transmitter.standby_mode());
+
+        (transmitter, receiver)
+    }
+
+    fn sms_packet(text: &str) -> Packet {
+        Packet {
+            version: 0x01,
+            id: 0x22,
+            msg_id: 0x11,
+            msg_count: 1,
+            data_type: DataType::Sms,
+            data: Data::Sms(text.to_string()),
+        }
+    }
+
+    fn bme280_packet(msg_id: u8) -> Packet {
+        Packet {
+            version: 0x01,
+            id: 0x22,
+            msg_id,
+            msg_count: 0x00,
+            data_type: DataType::BME280,
+            data: Data::Bme280(BME280 {
+                temperature: 23,
+                humidity: 45,
+                pressure: 67,
+            }),
+        }
+    }
+
+    fn read_register(device: &mut SimulatedSX1278, register: SX1278LoRaRegister) -> u8 {
+        let mut read = [0x00; 2];
+        device.transfer(&[register as u8, 0x00], &mut read);
+        read[1]
+    }
+
+    fn write_register(device: &mut SimulatedSX1278, register: SX1278LoRaRegister, value: u8) {
+        device.transfer(&[register as u8 | SPIIO::SPI_WRITE as u8, value], &mut [0x00; 2]);
+    }
+
+    #[test]
+    fn op_mode_transitions_correct() {
+        let mut device = SimulatedSX1278::new(Chip::SX1278, SimulatedAir::new(AirConditions::default()));
+        assert_eq!(0x09, read_register(&mut device, SX1278LoRaRegister::OP_MODE));
+
+        // LoRa sleep and standby, then TX of a 1 byte packet with DIO0 mapped to TxDone
+        write_register(&mut device, SX1278LoRaRegister::OP_MODE, 0x80);
+        write_register(&mut device, SX1278LoRaRegister::OP_MODE, 0x81);
+        write_register(&mut device, SX1278LoRaRegister::DIO_MAPPING_1, 0x40);
+        write_register(&mut device, SX1278LoRaRegister::FIFO_ADDR_PTR, 0x80);
+        write_register(&mut device, SX1278LoRaRegister::FIFO, 0x42);
+        write_register(&mut device, SX1278LoRaRegister::OP_MODE, 0x83);
+        assert_eq!(0x83, read_register(&mut device, SX1278LoRaRegister::OP_MODE));
+        assert!(!device.pin_level(SimulatedPinKind::Dio0));
+
+        // after the time on air the chip raises TxDone and returns to STDBY
+        thread::sleep(device.time_on_air(1));
+        assert!(device.pin_level(SimulatedPinKind::Dio0));
+        assert_eq!(0x81, read_register(&mut device, SX1278LoRaRegister::OP_MODE));
+        assert_eq!(
+            SX1278IRQMask::IRQ_TX_DONE_MASK as u8,
+            read_register(&mut device, SX1278LoRaRegister::IRQ_FLAGS)
+        );
+
+        write_register(&mut device, SX1278LoRaRegister::IRQ_FLAGS, 0xFF);
+        assert!(!device.pin_level(SimulatedPinKind::Dio0));
+
+        // reset returns to FSK/OOK standby
+        device.reset();
+        assert_eq!(0x09, read_register(&mut device, SX1278LoRaRegister::OP_MODE));
+    }
+
+    #[test]
+    fn time_on_air_correct() {
+        let mut device = SimulatedSX1278::new(Chip::SX1278, SimulatedAir::new(AirConditions::default()));
+        write_register(&mut device, SX1278LoRaRegister::OP_MODE, 0x80);
+        // SF7, 125 kHz, CR 4/5, CRC on, 8 symbol preamble, 8 byte payload: 35.25 symbols of 1.024 ms
+        write_register(&mut device, SX1278LoRaRegister::MODEM_CONFIG_2, 0x74);
+        assert_eq!(Duration::from_micros(36_096), device.time_on_air(8));
+    }
+
+    #[test]
+    fn send_receive_metadata_correct() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions {
+            snr_db: -7.25,
+            rssi_dbm: -110,
+            ..AirConditions::default()
+        });
+
+        let sent = handle_error!(bme280_packet(0x11).to_bytes());
+        handle_error!(transmitter.send_packet(sent.clone()));
+
+        let mut crc_error = false;
+        assert_eq!(sent, handle_error!(receiver.receive_packet(&mut crc_error)));
+        assert!(!crc_error);
+        assert_eq!(-7.25, handle_error!(receiver.get_packet_snr()));
+        assert!(handle_error!(receiver.get_packet_rssi()).abs_diff(-110) <= 1);
+    }
+
+    #[test]
+    fn crc_corruption_correct() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions {
+            crc_corruption: 1.0,
+            ..AirConditions::default()
+        });
+
+        let sent = handle_error!(bme280_packet(0x11).to_bytes());
+        handle_error!(transmitter.send_packet(sent.clone()));
+
+        let mut crc_error = false;
+        let received = handle_error!(receiver.receive_packet(&mut crc_error));
+        assert!(crc_error);
+        assert_eq!(sent.len(), received.len());
+        assert_ne!(sent, received);
+    }
+
+    #[test]
+    fn loss_correct() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions {
+            loss: 1.0,
+            ..AirConditions::default()
+        });
+
+        handle_error!(transmitter.send_packet(handle_error!(bme280_packet(0x11).to_bytes())));
+
+        let mut crc_error = false;
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert_eq!(
+            ReceiveResult::Timeout,
+            handle_error!(receiver.receive_packet_until(deadline, &mut crc_error))
+        );
+    }
+
+    #[test]
+    fn channel_activity_detect_transmission() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions::default());
+        assert!(!handle_error!(receiver.channel_activity_detect()));
+
+        // a 255 byte packet stays on the air for about 200 ms at SF7 and 500 kHz
+        handle_error!(transmitter.spi_write_register(SX1278LoRaRegister::FIFO_ADDR_PTR, 0x00));
+        handle_error!(transmitter.spi_write_register(SX1278LoRaRegister::FIFO_TX_BASE_ADDR, 0x00));
+        handle_error!(transmitter.spi_write_register(SX1278LoRaRegister::PAYLOAD_LENGTH, 0xFF));
+        handle_error!(transmitter.transmit_mode());
+        assert!(handle_error!(receiver.channel_activity_detect()));
+    }
+
+    #[test]
+    fn receive_mqtt_pipeline() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions::default());
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || receiver.receive(Some(sender)));
+
+        for msg_id in 0..3 {
+            handle_error!(transmitter.send_packet(handle_error!(bme280_packet(msg_id).to_bytes())));
+        }
+
+        for msg_id in 0..3 {
+            let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+            assert_eq!(0x22, message.get_device_id());
+            let MQTTMessage::PacketWrapper(wrapped) = &message else {
+                panic!("Expected a received packet");
+            };
+            assert_eq!(msg_id, wrapped.packet.msg_id);
+            assert_eq!(9.0, wrapped.metadata.snr);
+            assert!(wrapped.metadata.rssi.abs_diff(-60) <= 1);
+            assert!(handle_error!(message.to_json()).contains(r#""BME280": { "temperature": 11.5"#));
+        }
+    }
+
+    #[test]
+    fn rt_receive_csv_pipeline() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions::default());
+        let (csv_sender, csv_receiver) = channel::<CSVPacketWrapper>();
+        thread::spawn(move || receiver.rt_receive(csv_sender));
+
+        handle_error!(transmitter.send_packet(handle_error!(bme280_packet(0x11).to_bytes())));
+
+        match handle_error!(csv_receiver.recv_timeout(Duration::from_secs(5))) {
+            CSVPacketWrapper::Received(wrapped) => assert_eq!(0x11, wrapped.packet.msg_id),
+            _ => panic!("Expected a received packet"),
+        }
+    }
+
+    #[test]
+    fn transmit_tx_queue_pipeline() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions::default());
+        let config = handle_error!(Config::from_file("./tests/configs/tx_conf.toml".to_string()));
+        let tx_config = config.lora_config.unwrap().tx_config.unwrap();
+
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || receiver.receive(Some(sender)));
+        let (tx_sender, tx_receiver) = channel::<TxMessage>();
+        let tx_queue = TxQueue::new(&tx_config, tx_receiver);
+        thread::spawn(move || transmitter.transmit(tx_queue));
+
+        handle_error!(tx_sender.send(TxMessage::Reading(bme280_packet(0).data)));
+        handle_error!(tx_sender.send(TxMessage::Packet(bme280_packet(0x11))));
+
+        // the queued packet goes out as it is, right away
+        let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+        let MQTTMessage::PacketWrapper(wrapped) = &message else {
+            panic!("Expected a received packet");
+        };
+        assert_eq!(0x22, wrapped.packet.id);
+        assert_eq!(0x11, wrapped.packet.msg_id);
+
+        // readings are sent every transmit interval with the configured device id
+        for msg_id in 0..2 {
+            let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+            let MQTTMessage::PacketWrapper(wrapped) = &message else {
+                panic!("Expected a received packet");
+            };
+            assert_eq!(tx_config.device_id, wrapped.packet.id);
+            assert_eq!(msg_id, wrapped.packet.msg_id);
+            assert!(handle_error!(message.to_json()).contains(r#""BME280": { "temperature": 11.5"#));
+        }
+    }
+
+    #[test]
+    fn transmit_stops_on_shutdown() {
+        let (mut transmitter, _receiver) = simulated_pair(AirConditions::default());
+        let config = handle_error!(Config::from_file("./tests/configs/tx_conf.toml".to_string()));
+        let tx_config = config.lora_config.unwrap().tx_config.unwrap();
+
+        let (_tx_sender, tx_receiver) = channel::<TxMessage>();
+        let tx_queue = TxQueue::new(&tx_config, tx_receiver);
+        let shutdown = transmitter.shutdown();
+        let handle = thread::spawn(move || transmitter.transmit(tx_queue));
+
+        thread::sleep(Duration::from_millis(100));
+        shutdown.request_shutdown();
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn transceive_pipeline() {
+        let (mut node, mut gateway) = simulated_pair(AirConditions::default());
+        let config = handle_error!(Config::from_file("./tests/configs/tx_conf.toml".to_string()));
+        let tx_config = config.lora_config.unwrap().tx_config.unwrap();
+
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        let (tx_sender, tx_receiver) = channel::<TxMessage>();
+        let tx_queue = TxQueue::new(&tx_config, tx_receiver);
+        thread::spawn(move || gateway.transceive(Some(sender), tx_queue));
+
+        for msg_id in 0..2 {
+            handle_error!(node.set_dio0_mapping(SX1278Dio0Mapping::TX_DONE));
+            handle_error!(node.send_packet(handle_error!(bme280_packet(msg_id).to_bytes())));
+
+            // the gateway receives by default
+            let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+            let MQTTMessage::PacketWrapper(wrapped) = &message else {
+                panic!("Expected a received packet");
+            };
+            assert_eq!(msg_id, wrapped.packet.msg_id);
+
+            // and transmits a downlink once it is queued, then returns to receive
+            handle_error!(tx_sender.send(TxMessage::Packet(bme280_packet(0x40 + msg_id))));
+            handle_error!(node.set_dio0_mapping(SX1278Dio0Mapping::RX_DONE));
+            let mut crc_error = false;
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let ReceiveResult::Packet(buffer) = handle_error!(node.receive_packet_until(deadline, &mut crc_error)) else {
+                panic!("Expected a downlink packet");
+            };
+            assert!(!crc_error);
+            assert_eq!(0x40 + msg_id, handle_error!(Packet::new(&buffer)).msg_id);
+        }
+    }
+
+    #[test]
+    fn reliable_delivery_pipeline() {
+        let (mut transmitter, mut receiver) =
+            simulated_pair_from_config("./tests/configs/reliable_conf.toml", AirConditions::default());
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || receiver.receive(Some(sender)));
+
+        for msg_id in 0..2 {
+            handle_error!(transmitter.transmit_packet(&bme280_packet(msg_id)));
+            let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+            let MQTTMessage::PacketWrapper(wrapped) = &message else {
+                panic!("Expected a received packet");
+            };
+            assert_eq!(msg_id, wrapped.packet.msg_id);
+        }
+
+        // a repeat is acknowledged again, but not forwarded
+        handle_error!(transmitter.transmit_packet(&bme280_packet(1)));
+        assert!(mqtt_receiver.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn transceive_stops_on_shutdown() {
+        let (_node, mut gateway) = simulated_pair(AirConditions::default());
+        let config = handle_error!(Config::from_file("./tests/configs/tx_conf.toml".to_string()));
+        let tx_config = config.lora_config.unwrap().tx_config.unwrap();
+
+        let (_tx_sender, tx_receiver) = channel::<TxMessage>();
+        let tx_queue = TxQueue::new(&tx_config, tx_receiver);
+        let shutdown = gateway.shutdown();
+        let handle = thread::spawn(move || gateway.transceive(None, tx_queue));
+
+        thread::sleep(Duration::from_millis(100));
+        shutdown.request_shutdown();
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn transceive_reliable_delivery_pipeline() {
+        let (mut gateway, mut node) =
+            simulated_pair_from_config("./tests/configs/reliable_conf.toml", AirConditions::default());
+        let config = handle_error!(Config::from_file("./tests/configs/tx_conf.toml".to_string()));
+        let tx_config = config.lora_config.unwrap().tx_config.unwrap();
+
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || node.receive(Some(sender)));
+        let (tx_sender, tx_receiver) = channel::<TxMessage>();
+        let tx_queue = TxQueue::new(&tx_config, tx_receiver);
+        thread::spawn(move || gateway.transceive(None, tx_queue));
+
+        // every downlink is acknowledged by the node before the next one is sent
+        for msg_id in 0..2 {
+            handle_error!(tx_sender.send(TxMessage::Packet(bme280_packet(0x40 + msg_id))));
+            let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+            let MQTTMessage::PacketWrapper(wrapped) = &message else {
+                panic!("Expected a received packet");
+            };
+            assert_eq!(0x40 + msg_id, wrapped.packet.msg_id);
+        }
+    }
+
+    #[test]
+    fn fragmentation_pipeline() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions::default());
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || receiver.receive(Some(sender)));
+
+        // 150 bytes of text, 3 parts
+        let text = "Buzzverse ".repeat(15);
+        handle_error!(transmitter.transmit_packet(&sms_packet(&text)));
+
+        let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+        let MQTTMessage::PacketWrapper(wrapped) = &message else {
+            panic!("Expected a received packet");
+        };
+        assert_eq!(3, wrapped.packet.msg_count);
+        assert!(matches!(&wrapped.packet.data, Data::Sms(received) if *received == text));
+
+        // the parts themselves are not forwarded
+        assert!(mqtt_receiver.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn legacy_msg_count_pipeline() {
+        let (mut transmitter, mut receiver) = simulated_pair(AirConditions::default());
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || receiver.receive(Some(sender)));
+
+        // v0 senders put anything in msg_count, such packets are forwarded as they are
+        for msg_count in [2, 3, 0x12] {
+            let mut packet = bme280_packet(msg_count);
+            packet.version = 0x00;
+            packet.msg_count = msg_count;
+            handle_error!(transmitter.send_packet(handle_error!(packet.to_bytes())));
+
+            let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+            let MQTTMessage::PacketWrapper(wrapped) = &message else {
+                panic!("Expected a received packet");
+            };
+            assert_eq!(msg_count, wrapped.packet.msg_count);
+            assert!(handle_error!(message.to_json()).contains(r#""BME280": { "temperature": 11.5"#));
+        }
+    }
+
+    #[test]
+    fn fragmentation_reliable_delivery_pipeline() {
+        let (mut transmitter, mut receiver) =
+            simulated_pair_from_config("./tests/configs/reliable_conf.toml", AirConditions::default());
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || receiver.receive(Some(sender)));
+
+        // every part is acknowledged on its own
+        let text = "Buzzverse ".repeat(15);
+        handle_error!(transmitter.transmit_packet(&sms_packet(&text)));
+
+        let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+        let MQTTMessage::PacketWrapper(wrapped) = &message else {
+            panic!("Expected a received packet");
+        };
+        assert!(matches!(&wrapped.packet.data, Data::Sms(received) if *received == text));
+    }
+
+    #[test]
+    fn encryption_pipeline() {
+        // frames received in earlier runs would be rejected as replays
+        let _ = std::fs::remove_file("/tmp/rusty_beagle_test_received_counter");
+        let (mut transmitter, mut receiver) =
+            simulated_pair_from_config("./tests/configs/encrypted_conf.toml", AirConditions::default());
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || receiver.receive(Some(sender)));
+
+        // packets and ACKs are sealed and opened on both ends
+        handle_error!(transmitter.transmit_packet(&bme280_packet(0)));
+        let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+        let MQTTMessage::PacketWrapper(wrapped) = &message else {
+            panic!("Expected a received packet");
+        };
+        assert_eq!(0, wrapped.packet.msg_id);
+
+        // cleartext frames and frames with an old frame counter are dropped
+        handle_error!(transmitter.send_packet(handle_error!(bme280_packet(1).to_bytes())));
+        let frame_counter_file = "/tmp/rusty_beagle_replay_frame_counter";
+        let _ = std::fs::remove_file(frame_counter_file);
+        let mut encryption = handle_error!(Encryption::from_config(&EncryptionConfig {
+            key_file: "./tests/configs/keys.toml".to_string(),
+            frame_counter_file: frame_counter_file.to_string(),
+            received_counter_file: "/tmp/rusty_beagle_replay_received_counter".to_string(),
+        }));
+        let frame = handle_error!(encryption.seal(&handle_error!(bme280_packet(2).to_bytes())));
+        handle_error!(transmitter.send_packet(frame));
+        assert!(mqtt_receiver.recv_timeout(Duration::from_millis(500)).is_err());
+
+        handle_error!(transmitter.transmit_packet(&bme280_packet(3)));
+        let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+        let MQTTMessage::PacketWrapper(wrapped) = &message else {
+            panic!("Expected a received packet");
+        };
+        assert_eq!(3, wrapped.packet.msg_id);
+    }
+
+    #[test]
+    fn fsk_fragmentation_pipeline() {
+        // frames received in earlier runs would be rejected as replays
+        let _ = std::fs::remove_file("/tmp/rusty_beagle_test_fsk_received_counter");
+        // FSK/OOK frames hold 63 bytes, 43 of them are left for the packet with encryption
+        for (config_path, part_count) in [
+            ("./tests/configs/fsk_conf.toml", 2),
+            ("./tests/configs/fsk_encrypted_conf.toml", 2),
+        ] {
+            let (mut transmitter, mut receiver) = simulated_pair_from_config(config_path, AirConditions::default());
+            let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+            thread::spawn(move || receiver.receive(Some(sender)));
+
+            // a whole packet with LoRa
+            let text = "a".repeat(DATA_SIZE);
+            handle_error!(transmitter.transmit_packet(&sms_packet(&text)));
+
+            let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+            let MQTTMessage::PacketWrapper(wrapped) = &message else {
+                panic!("Expected a received packet");
+            };
+            assert_eq!(part_count, wrapped.packet.msg_count);
+            assert!(matches!(&wrapped.packet.data, Data::Sms(received) if *received == text));
+        }
+    }
+
+    #[test]
+    fn custom_data_type_pipeline() {
+        let (mut transmitter, mut receiver) =
+            simulated_pair_from_config("./tests/configs/custom_data_types_conf.toml", AirConditions::default());
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        thread::spawn(move || receiver.receive(Some(sender)));
+
+        // a SOIL reading, declared only in the config
+        handle_error!(transmitter.send_packet(vec![0x01, 0x07, 0x00, 0x01, 200, 0x9F, 0x01, 0xFB, 0xE6, 0x2D]));
+        let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+        let MQTTMessage::PacketWrapper(wrapped) = &message else {
+            panic!("Expected a received packet");
+        };
+        assert_eq!(DataType::Custom(200), wrapped.packet.data_type);
+        assert!(handle_error!(message.to_json()).starts_with(
+            r#"{ "SOIL": { "moisture": 41.5, "temperature": -10.5, "battery": 2900, "units": { "moisture": "%", "temperature": "°C", "battery": "mV" } }"#
+        ));
+    }
+
+    #[test]
+    fn reliable_delivery_unacknowledged() {
+        let conditions = AirConditions { loss: 1.0, ..AirConditions::default() };
+        let (mut transmitter, _receiver) =
+            simulated_pair_from_config("./tests/configs/reliable_conf.toml", conditions);
+
+        // 3 transmissions with ACK timeouts of 200 ms, 50 and 100 ms backoffs
+        let start = Instant::now();
+        assert!(transmitter.transmit_packet(&bme280_packet(0)).is_err());
+        assert!(start.elapsed() >= Duration::from_millis(750));
+    }
+}
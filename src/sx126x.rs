@@ -0,0 +1,1093 @@
+pub use crate::lora::LoRa;
+
+use core::time;
+
+use crate::config::RadioConfig;
+use crate::csv_writer::CSVPacketWrapper;
+use crate::custom_data::CustomDataTypes;
+use crate::defines::*;
+use crate::encryption::Encryption;
+use crate::fragment::{fragment, Reassembly};
+use crate::graceful_shutdown::Shutdown;
+use crate::hal::{open_spidev, CdevInputPin, GpiodOutputPin, WaitForHigh};
+use crate::mqtt::MQTTMessage;
+use crate::packet::{DataType, Metadata, Packet, PacketWrapper, PACKET_SIZE};
+use crate::reliable::{ack_packet, is_ack_for, retransmit_until_acked, ReliableDelivery};
+use crate::simulator::{SimulatedPin, SimulatedPinKind, SimulatedSX126x, SimulatedSpi};
+use crate::sx1278::{needs_low_data_rate_optimize, packet_snr_db, ReceiveResult, ReceivedOutput};
+use crate::tx_queue::{TxQueue, TX_QUEUE_POLL_INTERVAL_MS};
+use crate::{LoRaConfig, Mode};
+use anyhow::{anyhow, Context, Result};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiDevice;
+use linux_embedded_hal::SpidevDevice;
+use log::{error, info};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Interval between BUSY pin level polls
+const PIN_POLL_INTERVAL_MS: u64 = 1;
+/// Longest time BUSY may stay high, waking up from sleep takes the longest
+const BUSY_TIMEOUT_MS: u128 = 100;
+/// SetRx timeout value keeping the chip in RX continuous mode
+pub const RX_CONTINUOUS: u32 = 0xFF_FFFF;
+/// IRQs routed to DIO1
+const IRQ_MASK: u16 = SX126xIrq::TX_DONE as u16
+    | SX126xIrq::RX_DONE as u16
+    | SX126xIrq::HEADER_ERR as u16
+    | SX126xIrq::CRC_ERR as u16
+    | SX126xIrq::TIMEOUT as u16;
+
+/*
+ * RF frequency in PLL steps, RfFreq = frequency * 2^25 / FXOSC
+ */
+pub fn frequency_to_rf(frequency: u64) -> u32 {
+    ((frequency << 25) / FXOSC) as u32
+}
+
+/*
+ * Image calibration band for CalibrateImage, in 4 MHz steps.
+ * Frequencies outside of the datasheet bands get a band around them
+ */
+pub fn image_calibration_band(frequency: u64) -> [u8; 2] {
+    match frequency / 1_000_000 {
+        430..=440 => [0x6B, 0x6F],
+        470..=510 => [0x75, 0x81],
+        779..=787 => [0xC1, 0xC5],
+        863..=870 => [0xD7, 0xDB],
+        902..=928 => [0xE1, 0xE9],
+        mhz => [((mhz - 4) / 4) as u8, (mhz.div_ceil(4) + 1) as u8],
+    }
+}
+
+/*
+ * The SX127x single byte sync word 0xXY is the SX126x sync word 0xX4Y4,
+ * so 0x12 (private) becomes 0x1424 and 0x34 (LoRaWAN) becomes 0x3444
+ */
+pub fn sync_word_registers(sync_word: u8) -> [u8; 2] {
+    [(sync_word & 0xF0) | 0x04, (sync_word << 4) | 0x04]
+}
+
+/*
+ * Driver for the SX126x family, generic over the SPI device and the reset/BUSY/DIO1 pins
+ */
+pub struct SX126x<SPI, OUT, IN> {
+    spi: SPI,
+    reset_pin: OUT,
+    busy_pin: IN,
+    dio1_pin: IN,
+    frequency: u64,
+    implicit_header: bool,
+    payload_length: u8,
+    preamble_length: u16,
+    rx_timeout_ms: Option<u64>,
+    reliable_delivery: Option<ReliableDelivery>,
+    /// Parts of the multi-part messages received so far
+    reassembly: Reassembly,
+    custom_data_types: CustomDataTypes,
+    encryption: Option<Encryption>,
+    shutdown: Shutdown,
+    pub mode: Mode,
+}
+
+impl SX126x<SpidevDevice, GpiodOutputPin, CdevInputPin> {
+    pub fn from_config(lora_config: &LoRaConfig) -> Result<Self> {
+        let spi = open_spidev(&lora_config.spi_config).context("LoRa::from_config")?;
+        let busy_gpio = lora_config
+            .busy_gpio
+            .ok_or_else(|| anyhow!("busy_gpio not configured"))
+            .context("LoRa::from_config")?;
+        let dio1_gpio = lora_config
+            .dio1_gpio
+            .ok_or_else(|| anyhow!("dio1_gpio not configured"))
+            .context("LoRa::from_config")?;
+        let reset_pin = GpiodOutputPin::new(lora_config.reset_gpio).context("LoRa::from_config")?;
+        let busy_pin = CdevInputPin::new(busy_gpio).context("LoRa::from_config")?;
+        let dio1_pin = CdevInputPin::new(dio1_gpio).context("LoRa::from_config")?;
+
+        Self::new(spi, reset_pin, busy_pin, dio1_pin, lora_config)
+    }
+}
+
+impl SX126x<SimulatedSpi<SimulatedSX126x>, SimulatedPin<SimulatedSX126x>, SimulatedPin<SimulatedSX126x>> {
+    pub fn simulated(lora_config: &LoRaConfig) -> Result<Self> {
+        let device = Arc::new(Mutex::new(SimulatedSX126x::new()));
+        let spi = SimulatedSpi::new(device.clone());
+        let reset_pin = SimulatedPin::new(device.clone(), SimulatedPinKind::Reset);
+        let busy_pin = SimulatedPin::new(device.clone(), SimulatedPinKind::Busy);
+        let dio1_pin = SimulatedPin::new(device, SimulatedPinKind::Dio1);
+
+        Self::new(spi, reset_pin, busy_pin, dio1_pin, lora_config)
+    }
+}
+
+impl<SPI: SpiDevice, OUT: OutputPin, IN: WaitForHigh> SX126x<SPI, OUT, IN> {
+    pub fn sleep(ms: u64) {
+        std::thread::sleep(time::Duration::from_millis(ms));
+    }
+
+    pub fn new(spi: SPI, reset_pin: OUT, busy_pin: IN, dio1_pin: IN, lora_config: &LoRaConfig) -> Result<Self> {
+        let radio_config = &lora_config.radio_config;
+        let custom_data_types = CustomDataTypes::new(&lora_config.custom_data_types);
+        let encryption = match &lora_config.encryption {
+            Some(encryption) => Some(Encryption::from_config(encryption).context("LoRa::new")?),
+            None => None,
+        };
+
+        Ok(Self {
+            spi,
+            reset_pin,
+            busy_pin,
+            dio1_pin,
+            frequency: radio_config.frequency,
+            implicit_header: radio_config.implicit_header,
+            payload_length: radio_config.payload_length,
+            preamble_length: radio_config.preamble_length,
+            rx_timeout_ms: radio_config.rx_timeout_ms,
+            reliable_delivery: lora_config.reliable_delivery.as_ref().map(ReliableDelivery::new),
+            reassembly: Reassembly::new(
+                time::Duration::from_millis(lora_config.reassembly_timeout_ms),
+                custom_data_types.clone(),
+            ),
+            custom_data_types,
+            encryption,
+            shutdown: Shutdown::default(),
+            mode: lora_config.mode.clone(),
+        })
+    }
+
+    /*
+     * The chip holds BUSY high while it processes a command or wakes up,
+     * no command may be sent until it goes low
+     */
+    fn wait_busy(&mut self) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            let busy = self
+                .busy_pin
+                .is_high()
+                .map_err(|e| anyhow!("{:?}", e))
+                .context("LoRa::wait_busy")?;
+            if !busy {
+                return Ok(());
+            }
+            if start.elapsed().as_millis() > BUSY_TIMEOUT_MS {
+                return Err(anyhow!("BUSY stuck high for {} ms", BUSY_TIMEOUT_MS))
+                    .context("LoRa::wait_busy");
+            }
+            Self::sleep(PIN_POLL_INTERVAL_MS);
+        }
+    }
+
+    /*
+     * Sends the opcode and its parameters, then clocks out response.len() bytes.
+     * The first response byte is the status, followed by the data of read commands
+     */
+    pub fn spi_command(
+        &mut self,
+        command: SX126xCommand,
+        params: &[u8],
+        response: &mut [u8],
+    ) -> Result<()> {
+        self.wait_busy().context("LoRa::spi_command")?;
+
+        let mut tx_buf = Vec::with_capacity(1 + params.len() + response.len());
+        tx_buf.push(command as u8);
+        tx_buf.extend_from_slice(params);
+        tx_buf.resize(1 + params.len() + response.len(), 0x00);
+        let mut rx_buf = vec![0x00; tx_buf.len()];
+
+        match self.spi.transfer(&mut rx_buf, &tx_buf) {
+            Ok(()) => {
+                response.copy_from_slice(&rx_buf[1 + params.len()..]);
+                Ok(())
+            }
+            Err(e) => Err(anyhow!(
+                "While sending {:#?} got {:#?}",
+                command,
+                e
+            )),
+        }
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        // pull NRST pin low for 5 ms
+        self.reset_pin
+            .set_low()
+            .map_err(|e| anyhow!("{:?}", e))
+            .context("LoRa::LoRa reset: while setting reset_pin low")?;
+
+        Self::sleep(5);
+
+        self.reset_pin
+            .set_high()
+            .map_err(|e| anyhow!("{:?}", e))
+            .context("LoRa::LoRa reset: while setting reset_pin high")?;
+
+        // BUSY goes low once the chip is in STDBY_RC
+        self.wait_busy().context("LoRa::reset")
+    }
+
+    fn write_command(&mut self, command: SX126xCommand, params: &[u8]) -> Result<()> {
+        self.spi_command(command, params, &mut [])
+    }
+
+    pub fn get_status(&mut self) -> Result<u8> {
+        let mut response = [0x00; 1];
+        self.spi_command(SX126xCommand::GET_STATUS, &[], &mut response)
+            .context("LoRa::get_status")?;
+        Ok(response[0])
+    }
+
+    pub fn read_register(&mut self, register: SX126xRegister, buffer: &mut [u8]) -> Result<()> {
+        let address = (register as u16).to_be_bytes();
+        let mut response = vec![0x00; buffer.len() + 1];
+        self.spi_command(SX126xCommand::READ_REGISTER, &address, &mut response)
+            .context("LoRa::read_register")?;
+        buffer.copy_from_slice(&response[1..]);
+        Ok(())
+    }
+
+    pub fn write_register(&mut self, register: SX126xRegister, values: &[u8]) -> Result<()> {
+        let mut params = (register as u16).to_be_bytes().to_vec();
+        params.extend_from_slice(values);
+        self.write_command(SX126xCommand::WRITE_REGISTER, &params)
+            .context("LoRa::write_register")
+    }
+
+    pub fn write_buffer(&mut self, offset: u8, values: &[u8]) -> Result<()> {
+        let mut params = vec![offset];
+        params.extend_from_slice(values);
+        self.write_command(SX126xCommand::WRITE_BUFFER, &params)
+            .context("LoRa::write_buffer")
+    }
+
+    pub fn read_buffer(&mut self, offset: u8, buffer: &mut [u8]) -> Result<()> {
+        let mut response = vec![0x00; buffer.len() + 1];
+        self.spi_command(SX126xCommand::READ_BUFFER, &[offset], &mut response)
+            .context("LoRa::read_buffer")?;
+        buffer.copy_from_slice(&response[1..]);
+        Ok(())
+    }
+
+    /*
+     * Returns the ASCII version string, e.g. "SX1261 V2D 2D02" (shared by the SX1261 and SX1262)
+     */
+    pub fn get_version_string(&mut self) -> Result<String> {
+        let mut values = [0x00; 16];
+        self.read_register(SX126xRegister::VERSION_STRING, &mut values)
+            .context("LoRa::get_version_string")?;
+        let version_string = String::from_utf8_lossy(&values);
+        Ok(version_string.trim_end_matches('\0').to_string())
+    }
+
+    pub fn standby_mode(&mut self) -> Result<()> {
+        self.write_command(SX126xCommand::SET_STANDBY, &[SX126xConfig::STANDBY_RC as u8])
+            .context("LoRa::standby_mode")
+    }
+
+    /*
+     * Warm start sleep, the configuration is kept and the next command wakes the chip up
+     */
+    pub fn sleep_mode(&mut self) -> Result<()> {
+        self.write_command(SX126xCommand::SET_SLEEP, &[SX126xConfig::SLEEP_WARM_START as u8])
+            .context("LoRa::sleep_mode")
+    }
+
+    /*
+     * Should be called in STDBY mode
+     */
+    pub fn set_frequency(&mut self, frequency: u64) -> Result<()> {
+        Chip::SX1262.check_frequency(frequency).context("LoRa::set_frequency")?;
+
+        self.write_command(SX126xCommand::CALIBRATE_IMAGE, &image_calibration_band(frequency))
+            .context("LoRa::set_frequency")?;
+        self.write_command(
+            SX126xCommand::SET_RF_FREQUENCY,
+            &frequency_to_rf(frequency).to_be_bytes(),
+        )
+        .context("LoRa::set_frequency")?;
+        self.frequency = frequency;
+
+        Ok(())
+    }
+
+    pub fn get_frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    /*
+     * SX1262 high power PA, -9 to +22 dBm
+     */
+    pub fn set_tx_power(&mut self, level: i8) -> Result<()> {
+        if !(-9..=22).contains(&level) {
+            return Err(anyhow!("tx_power {} dBm out of range, should be within -9-22", level))
+                .context("LoRa::set_tx_power");
+        }
+        // paDutyCycle, hpMax, deviceSel (SX1262), paLut: the +22 dBm setting
+        self.write_command(SX126xCommand::SET_PA_CONFIG, &[0x04, 0x07, 0x00, 0x01])
+            .context("LoRa::set_tx_power")?;
+        self.write_command(
+            SX126xCommand::SET_TX_PARAMS,
+            &[level as u8, SX126xPowerConfig::RAMP_200_US as u8],
+        )
+        .context("LoRa::set_tx_power")
+    }
+
+    /*
+     * Over-current protection limit in 2.5 mA steps, must follow SetPaConfig
+     * which resets it to 140 mA
+     */
+    pub fn set_ocp(&mut self, current_ma: u8) -> Result<()> {
+        let value = (current_ma as u16 * 10 / 25) as u8;
+        self.write_register(SX126xRegister::OCP, &[value])
+            .context("LoRa::set_ocp")
+    }
+
+    pub fn set_modulation_params(
+        &mut self,
+        spreading_factor: SpreadingFactor,
+        bandwidth: Bandwidth,
+        coding_rate: CodingRate,
+        low_data_rate_optimize: bool,
+    ) -> Result<()> {
+        self.write_command(
+            SX126xCommand::SET_MODULATION_PARAMS,
+            &[
+                spreading_factor as u8,
+                bandwidth.sx126x_value(),
+                coding_rate as u8 - 4,
+                low_data_rate_optimize as u8,
+            ],
+        )
+        .context("LoRa::set_modulation_params")
+    }
+
+    /*
+     * Preamble length, header type, payload length, CRC on, standard IQ.
+     * The payload length is the maximum one in explicit header RX
+     */
+    pub fn set_packet_params(&mut self, payload_length: u8) -> Result<()> {
+        let [preamble_msb, preamble_lsb] = self.preamble_length.to_be_bytes();
+        self.write_command(
+            SX126xCommand::SET_PACKET_PARAMS,
+            &[
+                preamble_msb,
+                preamble_lsb,
+                self.implicit_header as u8,
+                payload_length,
+                0x01,
+                0x00,
+            ],
+        )
+        .context("LoRa::set_packet_params")
+    }
+
+    pub fn set_sync_word(&mut self, sync_word: u8) -> Result<()> {
+        self.write_register(SX126xRegister::LORA_SYNC_WORD_MSB, &sync_word_registers(sync_word))
+            .context("LoRa::set_sync_word")
+    }
+
+    pub fn get_sync_word(&mut self) -> Result<u8> {
+        let mut values = [0x00; 2];
+        self.read_register(SX126xRegister::LORA_SYNC_WORD_MSB, &mut values)
+            .context("LoRa::get_sync_word")?;
+        Ok((values[0] & 0xF0) | (values[1] >> 4))
+    }
+
+    pub fn set_rx_gain(&mut self, boosted: bool) -> Result<()> {
+        let value = if boosted {
+            SX126xConfig::RX_GAIN_BOOSTED
+        } else {
+            SX126xConfig::RX_GAIN_POWER_SAVING
+        };
+        self.write_register(SX126xRegister::RX_GAIN, &[value as u8])
+            .context("LoRa::set_rx_gain")
+    }
+
+    pub fn get_irq_status(&mut self) -> Result<u16> {
+        let mut response = [0x00; 3];
+        self.spi_command(SX126xCommand::GET_IRQ_STATUS, &[], &mut response)
+            .context("LoRa::get_irq_status")?;
+        Ok(u16::from_be_bytes([response[1], response[2]]))
+    }
+
+    pub fn clear_irq_status(&mut self) -> Result<()> {
+        self.write_command(SX126xCommand::CLEAR_IRQ_STATUS, &0xFFFF_u16.to_be_bytes())
+            .context("LoRa::clear_irq_status")
+    }
+
+    pub fn config_radio(&mut self, radio_config: &RadioConfig) -> Result<()> {
+        self.standby_mode().context("LoRa::config_radio")?;
+        self.write_command(SX126xCommand::SET_PACKET_TYPE, &[SX126xConfig::PACKET_TYPE_LORA as u8])
+            .context("LoRa::config_radio")?;
+        // the usual module wiring, DIO2 drives the antenna switch
+        self.write_command(SX126xCommand::SET_DIO2_AS_RF_SWITCH_CTRL, &[0x01])
+            .context("LoRa::config_radio")?;
+        self.write_command(
+            SX126xCommand::SET_REGULATOR_MODE,
+            &[SX126xPowerConfig::REGULATOR_DC_DC as u8],
+        )
+        .context("LoRa::config_radio")?;
+        self.write_command(SX126xCommand::CALIBRATE, &[SX126xConfig::CALIBRATE_ALL as u8])
+            .context("LoRa::config_radio")?;
+        self.set_frequency(radio_config.frequency)
+            .context("LoRa::config_radio")?;
+        self.write_command(SX126xCommand::SET_BUFFER_BASE_ADDRESS, &[0x00, 0x00])
+            .context("LoRa::config_radio")?;
+        self.set_tx_power(radio_config.tx_power)
+            .context("LoRa::config_radio")?;
+        if let Some(ocp_trim_ma) = radio_config.ocp_trim_ma {
+            self.set_ocp(ocp_trim_ma).context("LoRa::config_radio")?;
+        }
+
+        let low_data_rate_optimize = radio_config.low_data_rate_optimize.unwrap_or_else(|| {
+            needs_low_data_rate_optimize(radio_config.bandwidth, radio_config.spreading_factor)
+        });
+        self.set_modulation_params(
+            radio_config.spreading_factor,
+            radio_config.bandwidth,
+            radio_config.coding_rate,
+            low_data_rate_optimize,
+        )
+        .context("LoRa::config_radio")?;
+        self.implicit_header = radio_config.implicit_header;
+        self.payload_length = radio_config.payload_length;
+        self.preamble_length = radio_config.preamble_length;
+        self.set_packet_params(self.rx_payload_length())
+            .context("LoRa::config_radio")?;
+        self.set_sync_word(radio_config.sync_word)
+            .context("LoRa::config_radio")?;
+        self.set_rx_gain(radio_config.lna_boost)
+            .context("LoRa::config_radio")?;
+
+        let [mask_msb, mask_lsb] = IRQ_MASK.to_be_bytes();
+        // IRQ mask, DIO1 mask, DIO2 and DIO3 unused
+        self.write_command(
+            SX126xCommand::SET_DIO_IRQ_PARAMS,
+            &[mask_msb, mask_lsb, mask_msb, mask_lsb, 0x00, 0x00, 0x00, 0x00],
+        )
+        .context("LoRa::config_radio")?;
+
+        Ok(())
+    }
+
+    fn rx_payload_length(&self) -> u8 {
+        if self.implicit_header {
+            self.payload_length
+        } else {
+            0xFF
+        }
+    }
+
+    fn wait_for_dio1(&mut self) -> Result<()> {
+        self.wait_for_dio1_until(None).context("LoRa::wait_for_dio1")?;
+        Ok(())
+    }
+
+    /*
+     * Blocks until DIO1 goes high or the timeout passes, returns whether it went high
+     */
+    fn wait_for_dio1_until(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        let option_index = IN::wait_for_high(&mut [&mut self.dio1_pin], timeout)
+            .map_err(|e| anyhow!("{:?}", e))
+            .context("LoRa::wait_for_dio1_until")?;
+        Ok(option_index.is_some())
+    }
+
+    fn start_receive(&mut self, timeout: u32) -> Result<()> {
+        self.set_packet_params(self.rx_payload_length())
+            .context("LoRa::start_receive")?;
+        self.clear_irq_status().context("LoRa::start_receive")?;
+        self.write_command(SX126xCommand::SET_RX, &timeout.to_be_bytes()[1..])
+            .context("LoRa::start_receive")?;
+        Ok(())
+    }
+
+    /*
+     * Reads the received packet after DIO1 went high, the chip is left in STDBY mode
+     */
+    fn read_received_packet(&mut self, crc_error: &mut bool) -> Result<ReceiveResult> {
+        let irq_status = self.get_irq_status().context("LoRa::read_received_packet")?;
+        self.clear_irq_status().context("LoRa::read_received_packet")?;
+        if irq_status & SX126xIrq::TIMEOUT as u16 != 0 {
+            return Ok(ReceiveResult::Timeout);
+        }
+        *crc_error = irq_status & (SX126xIrq::CRC_ERR as u16 | SX126xIrq::HEADER_ERR as u16) != 0;
+
+        // payload length, RX start buffer pointer
+        let mut response = [0x00; 3];
+        self.spi_command(SX126xCommand::GET_RX_BUFFER_STATUS, &[], &mut response)
+            .context("LoRa::read_received_packet")?;
+        let mut buffer = vec![0x00; response[1] as usize];
+        self.read_buffer(response[2], &mut buffer)
+            .context("LoRa::read_received_packet")?;
+
+        Ok(ReceiveResult::Packet(buffer))
+    }
+
+    /*
+     * Receives in RX single mode when rx_timeout_ms is set (the timeout runs on the chip,
+     * in 15.625 us steps), in RX continuous mode otherwise
+     */
+    pub fn receive_next(&mut self, crc_error: &mut bool) -> Result<ReceiveResult> {
+        let timeout = match self.rx_timeout_ms {
+            Some(rx_timeout_ms) => (rx_timeout_ms * 64) as u32,
+            None => RX_CONTINUOUS,
+        };
+
+        self.start_receive(timeout).context("LoRa::receive_next")?;
+        self.wait_for_dio1().context("LoRa::receive_next")?;
+        self.read_received_packet(crc_error)
+            .context("LoRa::receive_next")
+    }
+
+    pub fn send_packet(&mut self, buffer: Vec<u8>) -> Result<()> {
+        if self.implicit_header && buffer.len() != self.payload_length as usize {
+            return Err(anyhow!(
+                "Packet length {} doesn't match the implicit header payload length {}",
+                buffer.len(),
+                self.payload_length
+            ))
+            .context("LoRa::send_packet");
+        }
+        if buffer.len() > 255 {
+            return Err(anyhow!("Packet length {} exceeds 255 bytes", buffer.len()))
+                .context("LoRa::send_packet");
+        }
+
+        self.standby_mode().context("LoRa::send_packet")?;
+        self.write_buffer(0x00, &buffer).context("LoRa::send_packet")?;
+        self.set_packet_params(buffer.len() as u8)
+            .context("LoRa::send_packet")?;
+        self.clear_irq_status().context("LoRa::send_packet")?;
+        // no TX timeout
+        self.write_command(SX126xCommand::SET_TX, &[0x00, 0x00, 0x00])
+            .context("LoRa::send_packet")?;
+
+        // DIO1 (TxDone) going high indicates succesful packet send
+        self.wait_for_dio1().context("LoRa::send_packet")?;
+        println!("Packet sent.");
+
+        self.clear_irq_status().context("LoRa::send_packet")?;
+
+        Ok(())
+    }
+
+    /*
+     * Sends a packet, split into parts if it is longer than PACKET_SIZE, with reliable delivery
+     * every part is retransmitted until it is acknowledged
+     */
+    pub fn transmit_packet(&mut self, packet: &Packet) -> Result<()> {
+        // LoRa frames hold 255 bytes, enough for any packet along with the encryption overhead
+        for part in fragment(packet, PACKET_SIZE).context("LoRa::transmit_packet")? {
+            self.transmit_part(&part).context("LoRa::transmit_packet")?;
+        }
+        Ok(())
+    }
+
+    fn transmit_part(&mut self, packet: &Packet) -> Result<()> {
+        let config = match &self.reliable_delivery {
+            Some(reliable_delivery) => reliable_delivery.config().clone(),
+            None => {
+                let buffer = self.encode(packet).context("LoRa::transmit_part")?;
+                return self.send_packet(buffer).context("LoRa::transmit_part");
+            }
+        };
+
+        // every retransmission is sealed with a new frame counter
+        retransmit_until_acked(&config, packet, |ack_timeout| {
+            let buffer = self.encode(packet)?;
+            self.send_packet(buffer)?;
+            self.wait_for_ack(packet, Instant::now() + ack_timeout)
+        })
+        .context("LoRa::transmit_part")
+    }
+
+
+    /*
+     * Receives in RX single mode until the ACK of the packet comes in or the deadline passes,
+     * other packets are dropped
+     */
+    fn wait_for_ack(&mut self, packet: &Packet, deadline: Instant) -> Result<bool> {
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+            // 15.625 us steps, 0 and RX_CONTINUOUS would never time out
+            let timeout = ((remaining.as_micros() * 64 / 1000) as u32).clamp(1, RX_CONTINUOUS - 1);
+
+            self.start_receive(timeout).context("LoRa::wait_for_ack")?;
+            self.wait_for_dio1().context("LoRa::wait_for_ack")?;
+            let mut crc_error = false;
+            match self
+                .read_received_packet(&mut crc_error)
+                .context("LoRa::wait_for_ack")?
+            {
+                ReceiveResult::Packet(buffer) => {
+                    if !crc_error && self.decode(&buffer).is_ok_and(|ack| is_ack_for(&ack, packet)) {
+                        return Ok(true);
+                    }
+                }
+                ReceiveResult::Timeout => return Ok(false),
+            }
+        }
+    }
+
+    /*
+     * With reliable delivery every received packet but an ACK is acknowledged.
+     * Returns whether the packet should be forwarded, ACKs and repeats within
+     * the duplicate window are not
+     */
+    fn acknowledge(&mut self, packet: &Packet) -> Result<bool> {
+        let Some(reliable_delivery) = &mut self.reliable_delivery else {
+            return Ok(true);
+        };
+        if packet.data_type == DataType::Ack {
+            return Ok(false);
+        }
+
+        // a repeat means the last ACK got lost, so it is acknowledged again
+        let duplicate = reliable_delivery.is_duplicate(packet);
+        let ack = self.encode(&ack_packet(packet)).context("LoRa::acknowledge")?;
+        if let Err(e) = self.send_packet(ack) {
+            eprintln!("{:?}", e);
+            error!("{:?}", e);
+        }
+
+        if duplicate {
+            println!("Duplicate of msg_id {}, not forwarded", packet.msg_id);
+            info!("Duplicate of msg_id {}, not forwarded", packet.msg_id);
+        }
+        Ok(!duplicate)
+    }
+
+    /*
+     * Serializes a packet for the air, with encryption sealed with the key of its device id
+     */
+    fn encode(&mut self, packet: &Packet) -> Result<Vec<u8>> {
+        let bytes = packet.to_bytes().context("LoRa::encode")?;
+        match &mut self.encryption {
+            Some(encryption) => encryption.seal(&bytes).context("LoRa::encode"),
+            None => Ok(bytes),
+        }
+    }
+
+    /*
+     * Parses a received frame, with encryption frames that fail authentication
+     * or are replayed are rejected
+     */
+    fn decode(&mut self, buffer: &[u8]) -> Result<Packet> {
+        match &mut self.encryption {
+            Some(encryption) => {
+                let bytes = encryption.open(buffer).context("LoRa::decode")?;
+                Packet::with_custom_types(&bytes, &self.custom_data_types).context("LoRa::decode")
+            }
+            None => Packet::with_custom_types(buffer, &self.custom_data_types).context("LoRa::decode"),
+        }
+    }
+
+    /*
+     * Collects the parts of multi-part messages, returns the packet to forward:
+     * the whole message once its last part is received, or the packet itself if it was sent whole
+     */
+    fn reassemble(&mut self, packet: Packet) -> Option<Packet> {
+        let is_part = packet.data_type == DataType::Fragment;
+        match self.reassembly.add(packet) {
+            Ok(Some(packet)) => {
+                if is_part {
+                    println!("Reassembled: {:#?}", packet);
+                    info!("Reassembled: {:?}", packet);
+                }
+                Some(packet)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                error!("{:?}", e);
+                None
+            }
+        }
+    }
+
+    /*
+     * Returns SNR[dB] and RSSI[dBm] of the last packet received
+     */
+    pub fn get_packet_status(&mut self) -> Result<(f32, i16)> {
+        // RssiPkt, SnrPkt, SignalRssiPkt
+        let mut response = [0x00; 4];
+        self.spi_command(SX126xCommand::GET_PACKET_STATUS, &[], &mut response)
+            .context("LoRa::get_packet_status")?;
+        let rssi = -(response[1] as i16) / 2;
+        Ok((packet_snr_db(response[2]), rssi))
+    }
+
+    /*
+     * The SX126x has no documented frequency error indicator, 0 is reported
+     */
+    fn packet_metadata(&mut self) -> Result<Metadata> {
+        let (snr, rssi) = self.get_packet_status().context("LoRa::packet_metadata")?;
+        Ok(Metadata { snr, rssi, frequency_error: 0 })
+    }
+
+    /*
+     * Prints a received packet and forwards it to MQTT or CSV, the chip is left in STDBY mode
+     */
+    fn publish_received(
+        &mut self,
+        received_buffer: Vec<u8>,
+        crc_error: bool,
+        output: &ReceivedOutput,
+    ) -> Result<()> {
+        println!();
+        println!(
+            "--------------------------------------------------------------------------------"
+        );
+        println!();
+
+        match self.decode(&received_buffer) {
+            Ok(packet) => {
+                let metadata = self.packet_metadata().context("LoRa::publish_received")?;
+
+                if !crc_error {
+                    println!(
+                        "Received: {:#?}, SNR = {} dB, RSSI = {} dBm",
+                        packet, metadata.snr, metadata.rssi
+                    );
+                    info!(
+                        "Received: {:?}, SNR = {} dB, RSSI = {} dBm",
+                        packet, metadata.snr, metadata.rssi
+                    );
+                    let forward = self.acknowledge(&packet).context("LoRa::publish_received")?;
+                    let option_packet = if forward { self.reassemble(packet) } else { None };
+                    if let Some(packet) = option_packet {
+                        output.send(PacketWrapper { packet, metadata });
+                    }
+                } else {
+                    // using ANSI escape codes for colors in terminal
+                    println!("\x1b[0;31m[CRC ERROR]\x1b[0m\nReceived: {:#?}, SNR = {} dB, RSSI = {} dBm", packet, metadata.snr, metadata.rssi);
+                    info!(
+                        "[CRC ERROR] Received: {:?}, SNR = {} dB, RSSI = {} dBm",
+                        packet, metadata.snr, metadata.rssi
+                    );
+                }
+            }
+            Err(e) => {
+                println!("Bad package: {:?}", e);
+                error!("Bad package: {:?}", e);
+                println!();
+                println!("Received: {:02X?}", received_buffer);
+            }
+        };
+
+        self.standby_mode().context("LoRa::publish_received")?;
+
+        Ok(())
+    }
+
+    /*
+     * Receives until a shutdown is requested, every packet goes through publish_received
+     */
+    fn receive_into(&mut self, output: ReceivedOutput) -> Result<()> {
+        loop {
+            let mut crc_error = false;
+
+            let received_buffer = match self.receive_next(&mut crc_error) {
+                Ok(ReceiveResult::Packet(s)) => s,
+                // the receive timeout is where the loop can stop
+                Ok(ReceiveResult::Timeout) if self.shutdown.shutdown_requested() => return Ok(()),
+                Ok(ReceiveResult::Timeout) => continue,
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                    std::process::exit(-1);
+                }
+            };
+            self.publish_received(received_buffer, crc_error, &output)
+                .context("LoRa::start")?;
+        }
+    }
+}
+
+impl<SPI, OUT, IN> LoRa for SX126x<SPI, OUT, IN>
+where
+    SPI: SpiDevice + Send,
+    OUT: OutputPin + Send,
+    IN: WaitForHigh + Send,
+{
+    fn get_mode(&self) -> Mode {
+        self.mode.clone()
+    }
+
+    fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    fn configure_lora(&mut self, radio_config: &RadioConfig) -> Result<()> {
+        self.reset().context("LoRa::start")?;
+        self.config_radio(radio_config).context("LoRa::start")?;
+        Ok(())
+    }
+
+    fn display_parameters(&mut self, radio_config: &RadioConfig) -> Result<()> {
+        println!("+-------------------------+");
+        println!("| Frequency: {:.3} MHz  |", self.get_frequency() as f64 / 1_000_000.0);
+        println!("| Bandwidth: {}            |", radio_config.bandwidth as u8);
+        println!("| Coding rate: {}          |", radio_config.coding_rate as u8);
+        println!("| Spreading factor: {:02}    |", radio_config.spreading_factor as u8);
+        println!("| Preamble length: {:<5}  |", self.preamble_length);
+        println!(
+            "| Sync word: 0x{:02X}         |",
+            self.get_sync_word().context("LoRa::start")?
+        );
+        if self.implicit_header {
+            println!("| Header: implicit, {:<3}   |", self.payload_length);
+        } else {
+            println!("| Header: explicit        |");
+        }
+        if let Some(rx_timeout_ms) = self.rx_timeout_ms {
+            println!("| RX timeout: {:<6} ms   |", rx_timeout_ms);
+        }
+        println!("| Mode: {:?}                |", self.mode);
+        println!("+-------------------------+");
+        Ok(())
+    }
+
+    fn receive(&mut self, option_sender: Option<Sender<MQTTMessage>>) -> Result<()> {
+        self.receive_into(ReceivedOutput::Mqtt(option_sender))
+    }
+
+    fn transmit(&mut self, mut tx_queue: TxQueue) -> Result<()> {
+        loop {
+            let Some(packet) = tx_queue.next_packet(&self.shutdown) else {
+                return Ok(());
+            };
+            // a busy channel only skips this packet
+            match self.transmit_packet(&packet) {
+                Ok(()) => info!("Sent: {:?}", packet),
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                }
+            }
+            self.sleep_mode().context("LoRa::transmit")?;
+        }
+    }
+
+    fn rt_receive(&mut self, csv_sender: Sender<CSVPacketWrapper>) -> Result<()> {
+        self.receive_into(ReceivedOutput::Csv(csv_sender))
+    }
+
+    fn rt_transmit(&mut self, mut tx_queue: TxQueue, csv_sender: Sender<CSVPacketWrapper>) -> Result<()> {
+        loop {
+            let Some(packet) = tx_queue.next_packet(&self.shutdown) else {
+                return Ok(());
+            };
+            match self.transmit_packet(&packet) {
+                // send to the CSV writer
+                Ok(()) => {
+                    info!("Sent: {:?}", packet);
+                    let _ = csv_sender.send(CSVPacketWrapper::Packet(packet));
+                }
+                // a busy channel only skips this packet
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                }
+            }
+
+            self.sleep_mode().context("LoRa::rt_transmit")?;
+        }
+    }
+
+    /*
+     * TxDone and RxDone are both signalled on DIO1, no remapping is needed between RX and TX
+     */
+    fn transceive(&mut self, option_sender: Option<Sender<MQTTMessage>>, mut tx_queue: TxQueue) -> Result<()> {
+        let output = ReceivedOutput::Mqtt(option_sender);
+        loop {
+            self.start_receive(RX_CONTINUOUS).context("LoRa::start")?;
+
+            // RX continuous until a packet is received or one is queued for transmission
+            let option_packet = loop {
+                // TX_QUEUE_POLL_INTERVAL_MS at the latest after a shutdown is requested
+                if self.shutdown.shutdown_requested() {
+                    self.sleep_mode().context("LoRa::transceive")?;
+                    return Ok(());
+                }
+                if let Some(packet) = tx_queue.try_next_packet() {
+                    break Some(packet);
+                }
+                let timeout = Duration::from_millis(TX_QUEUE_POLL_INTERVAL_MS);
+                if self.wait_for_dio1_until(Some(timeout)).context("LoRa::start")? {
+                    break None;
+                }
+            };
+
+            match option_packet {
+                // send_packet switches to STDBY first, the reception restarts afterwards
+                Some(packet) => match self.transmit_packet(&packet) {
+                    Ok(()) => info!("Sent: {:?}", packet),
+                    Err(e) => {
+                        eprintln!("{:?}", e);
+                        error!("{:?}", e);
+                    }
+                },
+                None => {
+                    let mut crc_error = false;
+                    if let ReceiveResult::Packet(received_buffer) = self
+                        .read_received_packet(&mut crc_error)
+                        .context("LoRa::start")?
+                    {
+                        self.publish_received(received_buffer, crc_error, &output)
+                            .context("LoRa::start")?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::*;
+    use crate::tx_queue::TxMessage;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    macro_rules! handle_error {
+        ($func:expr) => {
+            match $func {
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                    std::process::exit(-1);
+                }
+                Ok(s) => s,
+            }
+        };
+    }
+
+    fn sx1262_config() -> LoRaConfig {
+        let config = handle_error!(Config::from_file("./tests/configs/sx1262_conf.toml".to_string()));
+        config.lora_config.unwrap()
+    }
+
+    #[test]
+    fn frequency_to_rf_correct() {
+        // datasheet example: 868 MHz -> 0x36400000
+        assert_eq!(0x3640_0000, frequency_to_rf(868_000_000));
+        assert_eq!(0x1B20_0000, frequency_to_rf(434_000_000));
+    }
+
+    #[test]
+    fn image_calibration_band_correct() {
+        assert_eq!([0xD7, 0xDB], image_calibration_band(868_000_000));
+        assert_eq!([0xE1, 0xE9], image_calibration_band(915_000_000));
+        assert_eq!([0x6B, 0x6F], image_calibration_band(433_000_000));
+        // 150 MHz, 4 MHz around it
+        assert_eq!([0x24, 0x27], image_calibration_band(150_000_000));
+    }
+
+    #[test]
+    fn sync_word_registers_correct() {
+        assert_eq!([0x14, 0x24], sync_word_registers(0x12));
+        assert_eq!([0x34, 0x44], sync_word_registers(0x34));
+    }
+
+    #[test]
+    fn get_version_string_correct() {
+        let mut lora = handle_error!(SX126x::simulated(&sx1262_config()));
+        handle_error!(lora.reset());
+
+        let status = handle_error!(lora.get_status());
+        assert_eq!(SX126xChipMode::STBY_RC, handle_error!(SX126xChipMode::from_status(status)));
+        assert_eq!("SX1261 V2D 2D02", handle_error!(lora.get_version_string()));
+    }
+
+    #[test]
+    fn config_radio_correct() {
+        let lora_config = sx1262_config();
+        let mut lora = handle_error!(SX126x::simulated(&lora_config));
+        handle_error!(lora.reset());
+        handle_error!(lora.config_radio(&lora_config.radio_config));
+
+        assert_eq!(lora_config.radio_config.frequency, lora.get_frequency());
+        assert_eq!(lora_config.radio_config.sync_word, handle_error!(lora.get_sync_word()));
+
+        let mut value = [0x00; 1];
+        handle_error!(lora.read_register(SX126xRegister::RX_GAIN, &mut value));
+        assert_eq!(SX126xConfig::RX_GAIN_BOOSTED as u8, value[0]);
+
+        // 140 mA in 2.5 mA steps
+        handle_error!(lora.read_register(SX126xRegister::OCP, &mut value));
+        assert_eq!(56, value[0]);
+
+        assert!(lora.set_tx_power(23).is_err());
+        assert!(lora.set_frequency(1_000_000_000).is_err());
+    }
+
+    #[test]
+    fn send_receive_correct() {
+        let lora_config = sx1262_config();
+        let mut lora = handle_error!(SX126x::simulated(&lora_config));
+        handle_error!(lora.reset());
+        handle_error!(lora.config_radio(&lora_config.radio_config));
+
+        let payload = vec![0x01, 0xFF, 0x11, 0x00, 0x01, 0xFA, 0x2E, 0xFB];
+        handle_error!(lora.send_packet(payload.clone()));
+
+        let mut crc_error = true;
+        let result = handle_error!(lora.receive_next(&mut crc_error));
+        assert_eq!(ReceiveResult::Packet(payload), result);
+        assert!(!crc_error);
+
+        let metadata = handle_error!(lora.packet_metadata());
+        assert_eq!(10.0, metadata.snr);
+        assert_eq!(-64, metadata.rssi);
+
+        // nothing sent, the configured rx_timeout_ms runs out
+        assert_eq!(ReceiveResult::Timeout, handle_error!(lora.receive_next(&mut crc_error)));
+    }
+
+    #[test]
+    fn transceive_correct() {
+        let lora_config = sx1262_config();
+        let mut lora = handle_error!(SX126x::simulated(&lora_config));
+        handle_error!(lora.configure_lora(&lora_config.radio_config));
+
+        let (sender, mqtt_receiver) = channel::<MQTTMessage>();
+        let (tx_sender, tx_receiver) = channel::<TxMessage>();
+        let tx_config = TxConfig { device_id: 7, transmit_interval: 1, packet_version: 1, cayenne_lpp: false };
+        thread::spawn(move || lora.transceive(Some(sender), TxQueue::new(&tx_config, tx_receiver)));
+
+        // the simulated chip receives back the packet it sent last, once it is receiving again
+        let packet = Packet::new(&[0x01, 0xFF, 0x11, 0x00, 0x01, 0xFA, 0x2E, 0xFB]).unwrap();
+        handle_error!(tx_sender.send(TxMessage::Packet(packet)));
+        let message = handle_error!(mqtt_receiver.recv_timeout(Duration::from_secs(5)));
+        let MQTTMessage::PacketWrapper(wrapped) = &message else {
+            panic!("Expected a received packet");
+        };
+        assert_eq!(0x11, wrapped.packet.msg_id);
+    }
+
+    #[test]
+    fn transceive_stops_on_shutdown() {
+        let lora_config = sx1262_config();
+        let mut lora = handle_error!(SX126x::simulated(&lora_config));
+        handle_error!(lora.configure_lora(&lora_config.radio_config));
+
+        let (_tx_sender, tx_receiver) = channel::<TxMessage>();
+        let tx_config = TxConfig { device_id: 7, transmit_interval: 1, packet_version: 1, cayenne_lpp: false };
+        let shutdown = lora.shutdown();
+        let handle = thread::spawn(move || lora.transceive(None, TxQueue::new(&tx_config, tx_receiver)));
+
+        thread::sleep(Duration::from_millis(100));
+        shutdown.request_shutdown();
+        assert!(handle.join().unwrap().is_ok());
+    }
+}
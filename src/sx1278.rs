@@ -2,151 +2,291 @@ pub use crate::lora::LoRa;
 
 use core::time;
 
-use crate::config::{config_input_pin, config_output_pin, RadioConfig};
+use crate::config::{AfcConfig, FskConfig, ListenBeforeTalkConfig, RadioConfig};
 use crate::csv_writer::CSVPacketWrapper;
+use crate::custom_data::CustomDataTypes;
 use crate::defines::*;
+use crate::encryption::{Encryption, ENCRYPTION_OVERHEAD};
+use crate::fragment::{fragment, Reassembly};
+use crate::graceful_shutdown::Shutdown;
+use crate::hal::{open_spidev, CdevInputPin, GpiodOutputPin, WaitForHigh};
 use crate::mqtt::MQTTMessage;
-use crate::packet::{Data, DataType, Metadata, Packet, PacketWrapper, BME280};
+use crate::packet::{DataType, Metadata, Packet, PacketWrapper, PACKET_SIZE};
+use crate::reliable::{ack_packet, is_ack_for, retransmit_until_acked, ReliableDelivery};
+use crate::simulator::{AirConditions, SimulatedAir, SimulatedPin, SimulatedPinKind, SimulatedSX1278, SimulatedSpi};
+use crate::tx_queue::{TxQueue, TX_QUEUE_POLL_INTERVAL_MS};
 use crate::{LoRaConfig, Mode};
 use anyhow::{anyhow, Context, Result};
-use gpiod::{Edge, Input, Lines, Output};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiDevice;
+use linux_embedded_hal::SpidevDevice;
 use log::{error, info};
-use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+use rand::Rng;
 use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Frequency synthesizer step, FXOSC / 2^19 ~= 61 Hz
+const FSTEP: u64 = FXOSC >> 19;
+/// FSK/OOK FIFO size minus the length byte, longer packets would need FIFO refills
+const FSK_MAX_PAYLOAD_LENGTH: usize = 63;
+/// Symbol time above which LowDataRateOptimize is mandatory
+const LOW_DATA_RATE_SYMBOL_TIME_US: u32 = 16_000;
+
+/*
+ * Returns LoRa symbol duration in microseconds, Ts = 2^SF / BW
+ */
+pub fn symbol_duration_us(bandwidth: Bandwidth, spreading_factor: SpreadingFactor) -> u32 {
+    ((1u64 << spreading_factor as u8) * 1_000_000 / bandwidth.hz() as u64) as u32
+}
+
+pub fn needs_low_data_rate_optimize(bandwidth: Bandwidth, spreading_factor: SpreadingFactor) -> bool {
+    symbol_duration_us(bandwidth, spreading_factor) > LOW_DATA_RATE_SYMBOL_TIME_US
+}
+
+/*
+ * Shuffles the channels with a Fisher-Yates shuffle driven by xorshift32,
+ * so every node derives the same hopping sequence from the same seed.
+ * Seed 0 keeps the configured order
+ */
+pub fn hopping_sequence(channels: &[u64], seed: u32) -> Vec<u64> {
+    let mut sequence = channels.to_vec();
+    if seed == 0 {
+        return sequence;
+    }
+
+    let mut state = seed;
+    for i in (1..sequence.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        sequence.swap(i, state as usize % (i + 1));
+    }
+    sequence
+}
+
+/*
+ * PKT_SNR_VALUE is a two's complement value in 0.25 dB steps
+ */
+pub fn packet_snr_db(value: u8) -> f32 {
+    (value as i8) as f32 / 4.0
+}
+
+/*
+ * FEI registers hold a 20-bit two's complement value,
+ * Ferr = FreqError * 2^24 / FXOSC * BW[kHz] / 500
+ */
+pub fn frequency_error_hz(value: u32, bandwidth: Bandwidth) -> i32 {
+    // sign extend from 20 bits
+    let freq_error = ((value << 12) as i32 >> 12) as i64;
+    (freq_error * (1 << 24) * bandwidth.hz() as i64 / (FXOSC as i64 * 500_000)) as i32
+}
+
+/*
+ * Packet strength, datasheet 5.5.5:
+ * SNR >= 0: RSSI = offset + 16/15 * PacketRssi
+ * SNR < 0:  RSSI = offset + PacketRssi + PacketSnr * 0.25
+ * with offset -157 dBm on the HF port and -164 dBm on the LF port
+ */
+pub fn packet_rssi_dbm(value: u8, snr: f32, frequency_band: FrequencyBand) -> i16 {
+    let offset = match frequency_band {
+        FrequencyBand::HF => -157.0,
+        FrequencyBand::LF => -164.0,
+    };
+    let rssi = if snr < 0.0 {
+        offset + value as f32 + snr
+    } else {
+        offset + value as f32 * 16.0 / 15.0
+    };
+    rssi.round() as i16
+}
 
-macro_rules! handle_error_continue {
+macro_rules! log_error {
     ($func:expr) => {
         match $func {
             Err(e) => {
                 eprintln!("{:?}", e);
                 error!("{:?}", e);
-                continue;
             }
             Ok(s) => s,
         }
     };
 }
 
-#[cfg(target_arch = "arm")]
-pub struct SX1278 {
-    spidev: Spidev,
-    reset_pin: Lines<Output>,
-    dio0_pin: Lines<Input>,
+/*
+ * Driver for the SX127x family, generic over the SPI device and the reset/DIO pins
+ */
+pub struct SX1278<SPI, OUT, IN> {
+    spi: SPI,
+    reset_pin: OUT,
+    dio0_pin: IN,
+    dio1_pin: Option<IN>,
+    chip: Chip,
+    modulation: Modulation,
+    frequency_band: FrequencyBand,
+    listen_before_talk: Option<ListenBeforeTalkConfig>,
+    afc: Option<AfcConfig>,
+    /// Frequency errors of the packets received since the last correction
+    afc_errors: Vec<i32>,
+    /// Configured frequency, the AFC stays within afc.max_offset_hz of it
+    center_frequency: u64,
+    /// Hopping sequence and period in symbols, when FHSS is enabled
+    fhss: Option<(Vec<u64>, u8)>,
+    symbol_timeout: Option<u16>,
+    rx_timeout_ms: Option<u64>,
+    reliable_delivery: Option<ReliableDelivery>,
+    /// Parts of the multi-part messages received so far
+    reassembly: Reassembly,
+    custom_data_types: CustomDataTypes,
+    encryption: Option<Encryption>,
+    shutdown: Shutdown,
     pub mode: Mode,
 }
 
-#[cfg(target_arch = "x86_64")]
-pub struct SX1278 {
-    mock_registers: [u8; 112],
-    dio0_pin: MockGPIO,
-    pub mode: Mode,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DioPin {
+    DIO0,
+    DIO1,
 }
 
-#[cfg(target_arch = "x86_64")]
-pub struct MockGPIO {}
-
-#[cfg(target_arch = "x86_64")]
-impl MockGPIO {
-    fn read_event(&mut self) -> Result<MockEvent> {
-        let event = MockEvent { edge: Edge::Rising };
-        Ok(event)
-    }
+/// Outcome of a receive that can give up waiting
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReceiveResult {
+    Packet(Vec<u8>),
+    Timeout,
 }
 
-#[cfg(target_arch = "x86_64")]
-pub struct MockEvent {
-    edge: Edge,
+/// Where received packets go, MQTT in RX and TRX modes, the CSV writer in RX_RANGE_TEST mode
+pub enum ReceivedOutput {
+    Mqtt(Option<Sender<MQTTMessage>>),
+    Csv(Sender<CSVPacketWrapper>),
 }
 
-impl SX1278 {
-    pub fn sleep(ms: u64) {
-        std::thread::sleep(time::Duration::from_millis(ms));
-    }
-
-    #[cfg(target_arch = "x86_64")]
-    pub fn from_config(_lora_config: &LoRaConfig) -> Result<Self> {
-        let mock_registers = [1; 112];
-        let dio0_pin = MockGPIO {};
-        let mode = _lora_config.mode.clone();
-
-        Ok(Self {
-            mock_registers,
-            dio0_pin,
-            mode,
-        })
+impl ReceivedOutput {
+    pub fn send(&self, wrapped: PacketWrapper) {
+        match self {
+            ReceivedOutput::Mqtt(Some(lora_sender)) => {
+                log_error!(lora_sender.send(MQTTMessage::PacketWrapper(wrapped)));
+            }
+            ReceivedOutput::Mqtt(None) => {}
+            ReceivedOutput::Csv(csv_sender) => {
+                let _ = csv_sender.send(CSVPacketWrapper::Received(wrapped));
+            }
+        }
     }
+}
 
-    #[cfg(target_arch = "arm")]
+impl SX1278<SpidevDevice, GpiodOutputPin, CdevInputPin> {
     pub fn from_config(lora_config: &LoRaConfig) -> Result<Self> {
-        let local_spi_config = lora_config.spi_config.clone();
-        let mut spidev = Spidev::open(local_spi_config.spidev_path.clone())?;
-
-        let spi_options = SpidevOptions::new()
-            .bits_per_word(local_spi_config.bits_per_word)
-            .max_speed_hz(local_spi_config.max_speed_hz)
-            .mode(SpiModeFlags::from_bits(local_spi_config.spi_mode as u32).unwrap())
-            .build();
-        spidev.configure(&spi_options)?;
-
-        let reset_pin =
-            config_output_pin(lora_config.reset_gpio).context("LoRa::from_config")?;
-        let dio0_pin =
-            config_input_pin(lora_config.dio0_gpio).context("LoRa::from_config")?;
-
-        let mode = lora_config.mode.clone();
-
-        let lora = Self {
-            spidev,
-            reset_pin,
-            dio0_pin,
-            mode,
+        let spi = open_spidev(&lora_config.spi_config).context("LoRa::from_config")?;
+        let reset_pin = GpiodOutputPin::new(lora_config.reset_gpio).context("LoRa::from_config")?;
+        let dio0_gpio = lora_config
+            .dio0_gpio
+            .ok_or_else(|| anyhow!("dio0_gpio not configured"))
+            .context("LoRa::from_config")?;
+        let dio0_pin = CdevInputPin::new(dio0_gpio).context("LoRa::from_config")?;
+        let dio1_pin = match lora_config.dio1_gpio {
+            Some(dio1_gpio) => Some(CdevInputPin::new(dio1_gpio).context("LoRa::from_config")?),
+            None => None,
         };
 
-        Ok(lora)
+        Self::new(spi, reset_pin, dio0_pin, dio1_pin, lora_config)
     }
+}
 
-    #[cfg(target_arch = "x86_64")]
-    pub fn spi_read_register(
-        &mut self,
-        register: SX1278LoRaRegister,
-        value: &mut u8,
-    ) -> Result<()> {
-        *value = self.mock_registers[register as usize];
-        Ok(())
+impl SX1278<SimulatedSpi<SimulatedSX1278>, SimulatedPin<SimulatedSX1278>, SimulatedPin<SimulatedSX1278>> {
+    /*
+     * Simulated chip alone on its own air
+     */
+    pub fn simulated(lora_config: &LoRaConfig) -> Result<Self> {
+        Self::simulated_on_air(lora_config, SimulatedAir::new(AirConditions::default()))
     }
 
-    #[cfg(target_arch = "x86_64")]
-    pub fn spi_write_register(&mut self, register: SX1278LoRaRegister, value: u8) -> Result<()> {
-        self.mock_registers[register as usize] = value;
-        Ok(())
+    /*
+     * Simulated chip hearing and heard by the other chips on the same air
+     */
+    pub fn simulated_on_air(lora_config: &LoRaConfig, air: Arc<SimulatedAir>) -> Result<Self> {
+        let device = Arc::new(Mutex::new(SimulatedSX1278::new(lora_config.chip, air)));
+        let spi = SimulatedSpi::new(device.clone());
+        let reset_pin = SimulatedPin::new(device.clone(), SimulatedPinKind::Reset);
+        let dio0_pin = SimulatedPin::new(device.clone(), SimulatedPinKind::Dio0);
+        let dio1_pin = lora_config
+            .dio1_gpio
+            .map(|_| SimulatedPin::new(device.clone(), SimulatedPinKind::Dio1));
+
+        Self::new(spi, reset_pin, dio0_pin, dio1_pin, lora_config)
     }
+}
 
-    #[cfg(target_arch = "x86_64")]
-    pub fn reset(&mut self) -> Result<()> {
-        self.mock_registers = [1; 112];
-
-        // wait for 10 ms before using the chip
-        Self::sleep(10);
-
-        Ok(())
+impl<SPI: SpiDevice, OUT: OutputPin, IN: WaitForHigh> SX1278<SPI, OUT, IN> {
+    pub fn sleep(ms: u64) {
+        std::thread::sleep(time::Duration::from_millis(ms));
     }
 
-    #[cfg(target_arch = "x86_64")]
-    pub fn config_dio(&mut self) -> Result<()> {
-        Ok(())
+    pub fn new(
+        spi: SPI,
+        reset_pin: OUT,
+        dio0_pin: IN,
+        dio1_pin: Option<IN>,
+        lora_config: &LoRaConfig,
+    ) -> Result<Self> {
+        let chip = lora_config.chip;
+        let modulation = lora_config.radio_config.modulation;
+        let frequency_band = FrequencyBand::from_frequency(lora_config.radio_config.frequency)
+            .context("LoRa::new")?;
+        let listen_before_talk = lora_config.radio_config.listen_before_talk.clone();
+        let afc = lora_config.radio_config.afc.clone();
+        let center_frequency = lora_config.radio_config.frequency;
+        let fhss = lora_config.radio_config.fhss.as_ref().map(|fhss| {
+            (hopping_sequence(&fhss.channels, fhss.seed), fhss.hop_period)
+        });
+        let symbol_timeout = lora_config.radio_config.symbol_timeout;
+        let rx_timeout_ms = lora_config.radio_config.rx_timeout_ms;
+        let reliable_delivery = lora_config.reliable_delivery.as_ref().map(ReliableDelivery::new);
+        let custom_data_types = CustomDataTypes::new(&lora_config.custom_data_types);
+        let reassembly = Reassembly::new(
+            time::Duration::from_millis(lora_config.reassembly_timeout_ms),
+            custom_data_types.clone(),
+        );
+        let encryption = match &lora_config.encryption {
+            Some(encryption) => Some(Encryption::from_config(encryption).context("LoRa::new")?),
+            None => None,
+        };
+        let mode = lora_config.mode.clone();
+
+        Ok(Self {
+            spi,
+            reset_pin,
+            dio0_pin,
+            dio1_pin,
+            chip,
+            modulation,
+            frequency_band,
+            listen_before_talk,
+            afc,
+            afc_errors: Vec::new(),
+            center_frequency,
+            fhss,
+            symbol_timeout,
+            rx_timeout_ms,
+            reliable_delivery,
+            reassembly,
+            custom_data_types,
+            encryption,
+            shutdown: Shutdown::default(),
+            mode,
+        })
     }
 
-    #[cfg(target_arch = "arm")]
     pub fn spi_read_register(
         &mut self,
-        register: SX1278LoRaRegister,
+        register: impl Register,
         value: &mut u8,
     ) -> Result<()> {
-        let tx_buf: [u8; 2] = [register as u8 | SPIIO::SPI_READ as u8, 0x00];
+        let tx_buf: [u8; 2] = [register.address() | SPIIO::SPI_READ as u8, 0x00];
         let mut rx_buf: [u8; 2] = [0x00, 0x00];
-        let mut transfer = SpidevTransfer::read_write(&tx_buf, &mut rx_buf);
 
-        match self.spidev.transfer(&mut transfer) {
+        match self.spi.transfer(&mut rx_buf, &tx_buf) {
             Ok(()) => {
                 *value = rx_buf[1];
                 Ok(())
@@ -154,49 +294,92 @@ impl SX1278 {
             Err(e) => Err(anyhow!(
                 "While reading {:#?} got {:#?}",
                 register,
-                e.to_string()
+                e
+            )),
+        }
+    }
+
+    /*
+     * Burst access: the address byte is followed by all data bytes in one transfer,
+     * the chip auto-increments the register address (or FIFO_ADDR_PTR for the FIFO)
+     */
+    pub fn spi_burst_read(&mut self, register: impl Register, buffer: &mut [u8]) -> Result<()> {
+        let mut tx_buf = vec![0x00; buffer.len() + 1];
+        tx_buf[0] = register.address() | SPIIO::SPI_READ as u8;
+        let mut rx_buf = vec![0x00; buffer.len() + 1];
+
+        match self.spi.transfer(&mut rx_buf, &tx_buf) {
+            Ok(()) => {
+                buffer.copy_from_slice(&rx_buf[1..]);
+                Ok(())
+            }
+            Err(e) => Err(anyhow!(
+                "While burst reading {:#?} got {:#?}",
+                register,
+                e
             )),
         }
     }
 
-    pub fn read_fifo(&mut self, buffer: &mut Vec<u8>) -> Result<()> {
-        for value in buffer {
-            self.spi_read_register(SX1278LoRaRegister::FIFO, value)
-                .context("LoRa::read_fifo")?;
+    pub fn spi_burst_write(&mut self, register: impl Register, buffer: &[u8]) -> Result<()> {
+        let mut tx_buf = Vec::with_capacity(buffer.len() + 1);
+        tx_buf.push(register.address() | SPIIO::SPI_WRITE as u8);
+        tx_buf.extend_from_slice(buffer);
+
+        match self.spi.write(&tx_buf) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(anyhow!(
+                "While burst writing to {:#?} got {:#?}",
+                register,
+                e
+            )),
         }
+    }
 
-        Ok(())
+    pub fn read_fifo(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.spi_burst_read(SX1278LoRaRegister::FIFO, buffer)
+            .context("LoRa::read_fifo")
     }
 
-    #[cfg(target_arch = "arm")]
-    pub fn spi_write_register(&mut self, register: SX1278LoRaRegister, value: u8) -> Result<()> {
-        let tx_buf: [u8; 2] = [register as u8 | SPIIO::SPI_WRITE as u8, value];
-        let mut rx_buf: [u8; 2] = [0x00, 0x00];
-        let mut transfer = SpidevTransfer::read_write(&tx_buf, &mut rx_buf);
+    pub fn spi_write_register(&mut self, register: impl Register, value: u8) -> Result<()> {
+        let tx_buf: [u8; 2] = [register.address() | SPIIO::SPI_WRITE as u8, value];
 
-        match self.spidev.transfer(&mut transfer) {
+        match self.spi.write(&tx_buf) {
             Ok(()) => Ok(()),
             Err(e) => Err(anyhow!(
                 "While writing to {:#?} got {:#?}",
                 register,
-                e.to_string()
+                e
             )),
         }
     }
 
-    pub fn write_fifo(&mut self, buffer: Vec<u8>) -> Result<()> {
-        for value in buffer {
-            self.spi_write_register(SX1278LoRaRegister::FIFO, value)
-                .context("LoRa::write_fifo")?;
-        }
+    pub fn write_fifo(&mut self, buffer: &[u8]) -> Result<()> {
+        self.spi_burst_write(SX1278LoRaRegister::FIFO, buffer)
+            .context("LoRa::write_fifo")
+    }
 
-        Ok(())
+    /*
+     * Returns the OP_MODE value for the given transceiver mode,
+     * keeping the modem and the LowFrequencyModeOn bit matching the config.
+     * SLEEP, STDBY, TX and RX_CONTINUOUS share their values between modems
+     */
+    fn op_mode_value(&self, mode: SX1278LoRaMode) -> u8 {
+        let value = match self.modulation {
+            Modulation::LoRa => SX1278LoRaMode::LONG_RANGE as u8 | mode as u8,
+            Modulation::FSK => mode as u8,
+            Modulation::OOK => SX1278LoRaMode::MODULATION_OOK as u8 | mode as u8,
+        };
+        match self.frequency_band {
+            FrequencyBand::LF => value | SX1278LoRaMode::LOW_FREQUENCY_MODE_ON as u8,
+            FrequencyBand::HF => value,
+        }
     }
 
     pub fn standby_mode(&mut self) -> Result<()> {
         self.spi_write_register(
             SX1278LoRaRegister::OP_MODE,
-            SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::STDBY as u8,
+            self.op_mode_value(SX1278LoRaMode::STDBY),
         )
         .context("LoRa::standby_mode")?;
         Self::sleep(10);
@@ -206,7 +389,7 @@ impl SX1278 {
     pub fn sleep_mode(&mut self) -> Result<()> {
         self.spi_write_register(
             SX1278LoRaRegister::OP_MODE,
-            SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::SLEEP as u8,
+            self.op_mode_value(SX1278LoRaMode::SLEEP),
         )
         .context("LoRa::sleep_mode")?;
         Self::sleep(10);
@@ -216,7 +399,7 @@ impl SX1278 {
     pub fn receive_mode(&mut self) -> Result<()> {
         self.spi_write_register(
             SX1278LoRaRegister::OP_MODE,
-            SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::RX_CONTINUOUS as u8,
+            self.op_mode_value(SX1278LoRaMode::RX_CONTINUOUS),
         )
         .context("LoRa::recieve_mode")?;
         Self::sleep(10);
@@ -226,41 +409,106 @@ impl SX1278 {
     pub fn transmit_mode(&mut self) -> Result<()> {
         self.spi_write_register(
             SX1278LoRaRegister::OP_MODE,
-            SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::TX as u8,
+            self.op_mode_value(SX1278LoRaMode::TX),
         )
         .context("LoRa::transmit_mode")?;
         Self::sleep(10);
         Ok(())
     }
 
-    pub fn set_tx_power(&mut self, level: u8) -> Result<()> {
-        let correct_level = match level {
-            0 | 1 => 2,
-            2..=17 => level,
-            _ => 17,
-        };
+    pub fn cad_mode(&mut self) -> Result<()> {
+        self.spi_write_register(
+            SX1278LoRaRegister::OP_MODE,
+            self.op_mode_value(SX1278LoRaMode::CAD),
+        )
+        .context("LoRa::cad_mode")?;
+        Ok(())
+    }
+
+    pub fn set_tx_power(&mut self, pa_output: PaOutput, level: i8, max_power: u8) -> Result<()> {
+        let (pa_config, pa_dac) = pa_output
+            .register_values(level, max_power)
+            .context("LoRa::set_tx_power")?;
+        self.spi_write_register(SX1278LoRaRegister::PA_CONFIG, pa_config)
+            .context("LoRa::set_tx_power")?;
+        self.spi_write_register(SX1278LoRaRegister::PA_DAC, pa_dac as u8)
+            .context("LoRa::set_tx_power")?;
+        Self::sleep(10);
+        Ok(())
+    }
+
+    /*
+     * Enables over-current protection at the given limit
+     */
+    pub fn set_ocp(&mut self, current_ma: u8) -> Result<()> {
+        let trim = ocp_trim(current_ma).context("LoRa::set_ocp")?;
         self.spi_write_register(
-            SX1278LoRaRegister::PA_CONFIG,
-            SX1278PAConfiguration::PA_BOOST as u8 | correct_level,
+            SX1278LoRaRegister::OCP,
+            SX1278PAConfiguration::OCP_ON as u8 | trim,
         )
-        .context("LoRa::set_tx_power")?;
+        .context("LoRa::set_ocp")?;
         Self::sleep(10);
         Ok(())
     }
 
+    /*
+     * Writes FRF only, fast enough to retune in the middle of a packet
+     */
+    fn write_frf(&mut self, frequency: u64) -> Result<()> {
+        let frf = (frequency << 19) / FXOSC;
+        // FRF_MSB, FRF_MID, FRF_LSB
+        self.spi_burst_write(
+            SX1278LoRaRegister::FRF_MSB,
+            &[(frf >> 16) as u8, (frf >> 8) as u8, frf as u8],
+        )
+        .context("LoRa::write_frf")
+    }
+
+    /*
+     * Programs the carrier frequency and switches LowFrequencyModeOn to the matching band.
+     * The frequency has to be within the ranges of the configured chip.
+     * Should be called in SLEEP or STDBY mode
+     */
     pub fn set_frequency(&mut self, frequency: u64) -> Result<()> {
-        let frf = (frequency << 19) / 32_000_000;
-        self.spi_write_register(SX1278LoRaRegister::FRF_MSB, (frf >> 16) as u8)
-            .context("LoRa::set_frequency ")?;
-        self.spi_write_register(SX1278LoRaRegister::FRF_MID, (frf >> 8) as u8)
-            .context("LoRa::set_frequency ")?;
-        self.spi_write_register(SX1278LoRaRegister::FRF_LSB, frf as u8)
-            .context("LoRa::set_frequency ")?;
+        self.chip.check_frequency(frequency).context("LoRa::set_frequency")?;
+        let band = FrequencyBand::from_frequency(frequency).context("LoRa::set_frequency")?;
+
+        self.write_frf(frequency).context("LoRa::set_frequency")?;
+
+        let mut op_mode = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut op_mode)
+            .context("LoRa::set_frequency")?;
+        let low_frequency_mode_on = SX1278LoRaMode::LOW_FREQUENCY_MODE_ON as u8;
+        let op_mode = match band {
+            FrequencyBand::LF => op_mode | low_frequency_mode_on,
+            FrequencyBand::HF => op_mode & !low_frequency_mode_on,
+        };
+        self.spi_write_register(SX1278LoRaRegister::OP_MODE, op_mode)
+            .context("LoRa::set_frequency")?;
+        self.frequency_band = band;
         Self::sleep(10);
 
         Ok(())
     }
 
+    /*
+     * Reads back FRF and compares it with the requested frequency,
+     * set_frequency already checked the band against the chip variant
+     */
+    pub fn verify_frequency(&mut self, frequency: u64) -> Result<()> {
+        let chip_frequency = self.get_frequency().context("LoRa::verify_frequency")?;
+        if chip_frequency.abs_diff(frequency) > FSTEP {
+            return Err(anyhow!(
+                "Requested {} Hz, but the chip is set to {} Hz",
+                frequency,
+                chip_frequency
+            ))
+            .context("LoRa::verify_frequency");
+        }
+
+        Ok(())
+    }
+
     pub fn set_bandwidth(&mut self, bandwidth: Bandwidth) -> Result<()> {
         let mut value = 0x00;
         let register = SX1278LoRaRegister::MODEM_CONFIG_1;
@@ -303,11 +551,34 @@ impl SX1278 {
             (value & reg_mask) | (((spreading_factor as u8) << 4) & val_mask),
         )
         .context("LoRa::set_spreading_factor")?;
+        self.set_detection(spreading_factor)
+            .context("LoRa::set_spreading_factor")?;
         Self::sleep(10);
 
         Ok(())
     }
 
+    /*
+     * SF6 needs its own detection optimization and threshold values
+     */
+    fn set_detection(&mut self, spreading_factor: SpreadingFactor) -> Result<()> {
+        let (detection_optimize, detection_threshold) = match spreading_factor {
+            SpreadingFactor::spreading_factor_64 => (0x05, 0x0C),
+            _ => (0x03, 0x0A),
+        };
+
+        let mut value = 0x00;
+        let register = SX1278LoRaRegister::DETECTION_OPTIMIZE;
+        self.spi_read_register(register, &mut value)
+            .context("LoRa::set_detection")?;
+        self.spi_write_register(register, (value & 0xf8) | detection_optimize)
+            .context("LoRa::set_detection")?;
+        self.spi_write_register(SX1278LoRaRegister::DETECTION_THRESHOLD, detection_threshold)
+            .context("LoRa::set_detection")?;
+
+        Ok(())
+    }
+
     pub fn enable_crc(&mut self) -> Result<()> {
         let mut value = 0x00;
         let crc_on = 0x04;
@@ -322,6 +593,88 @@ impl SX1278 {
         Ok(())
     }
 
+    pub fn set_preamble_length(&mut self, preamble_length: u16) -> Result<()> {
+        self.spi_burst_write(SX1278LoRaRegister::PREAMBLE_MSB, &preamble_length.to_be_bytes())
+            .context("LoRa::set_preamble_length")?;
+        Self::sleep(10);
+
+        Ok(())
+    }
+
+    pub fn set_sync_word(&mut self, sync_word: u8) -> Result<()> {
+        self.spi_write_register(SX1278LoRaRegister::SYNC_WORD, sync_word)
+            .context("LoRa::set_sync_word")?;
+        Self::sleep(10);
+
+        Ok(())
+    }
+
+    /*
+     * In implicit header mode the packet has no header, so both ends
+     * need to use the same fixed payload length, coding rate and CRC setting
+     */
+    pub fn set_header_mode(&mut self, implicit_header: bool, payload_length: u8) -> Result<()> {
+        let mut value = 0x00;
+        let implicit_header_mode_on = 0x01;
+        let register = SX1278LoRaRegister::MODEM_CONFIG_1;
+        self.spi_read_register(register, &mut value)
+            .context("LoRa::set_header_mode")?;
+
+        let value = if implicit_header {
+            value | implicit_header_mode_on
+        } else {
+            value & !implicit_header_mode_on
+        };
+        self.spi_write_register(register, value)
+            .context("LoRa::set_header_mode")?;
+
+        if implicit_header {
+            self.spi_write_register(SX1278LoRaRegister::PAYLOAD_LENGTH, payload_length)
+                .context("LoRa::set_header_mode")?;
+        }
+        Self::sleep(10);
+
+        Ok(())
+    }
+
+    pub fn set_modem_config_3(&mut self, low_data_rate_optimize: bool, agc: bool) -> Result<()> {
+        let mut value = 0x00;
+        let low_data_rate_optimize_on = 0x08;
+        let agc_auto_on = 0x04;
+        let register = SX1278LoRaRegister::MODEM_CONFIG_3;
+        self.spi_read_register(register, &mut value)
+            .context("LoRa::set_modem_config_3")?;
+
+        value &= !(low_data_rate_optimize_on | agc_auto_on);
+        if low_data_rate_optimize {
+            value |= low_data_rate_optimize_on;
+        }
+        if agc {
+            value |= agc_auto_on;
+        }
+        self.spi_write_register(register, value)
+            .context("LoRa::set_modem_config_3")?;
+        Self::sleep(10);
+
+        Ok(())
+    }
+
+    /*
+     * LNA gain is overridden by the chip while AGC is on
+     */
+    pub fn set_lna(&mut self, lna_gain: LnaGain, lna_boost: bool) -> Result<()> {
+        let lna_boost_hf_on = 0x03;
+        let mut value = (lna_gain as u8) << 5;
+        if lna_boost {
+            value |= lna_boost_hf_on;
+        }
+        self.spi_write_register(SX1278LoRaRegister::LNA, value)
+            .context("LoRa::set_lna")?;
+        Self::sleep(10);
+
+        Ok(())
+    }
+
     pub fn get_bandwidth(&mut self) -> Result<u8> {
         let mut value = 0x00;
         self.spi_read_register(SX1278LoRaRegister::MODEM_CONFIG_1, &mut value)
@@ -340,465 +693,2048 @@ impl SX1278 {
 
     pub fn get_spreading_factor(&mut self) -> Result<u8> {
         let mut value = 0x00;
-        self.spi_read_register(SX1278LoRaRegister::MODEM_CONFIG_1, &mut value)
+        self.spi_read_register(SX1278LoRaRegister::MODEM_CONFIG_2, &mut value)
             .context("LoRa::get_spreading_factor")?;
 
-        Ok((value >> 4) + 8)
+        Ok(value >> 4)
     }
 
     pub fn get_frequency(&mut self) -> Result<u64> {
+        // FRF_MSB, FRF_MID, FRF_LSB
         let mut values: [u8; 3] = [0, 0, 0];
-        self.spi_read_register(SX1278LoRaRegister::FRF_MSB, &mut values[0])
-            .context("LoRa::get_frequency")?;
-        self.spi_read_register(SX1278LoRaRegister::FRF_MID, &mut values[1])
-            .context("LoRa::get_frequency")?;
-        self.spi_read_register(SX1278LoRaRegister::FRF_LSB, &mut values[2])
+        self.spi_burst_read(SX1278LoRaRegister::FRF_MSB, &mut values)
             .context("LoRa::get_frequency")?;
 
         let msb = (values[0] as u32) << 16;
         let mid = (values[1] as u32) << 8;
         let lsb = values[2] as u32;
         let frf = msb | mid | lsb;
-        let frequency = (frf as u64) * (32_000_000 / (0b1 << 19) as u64);
+        let frequency = ((frf as u64) * FXOSC) >> 19;
 
         Ok(frequency)
     }
 
-    pub fn has_crc_error(&mut self, has_crc_error: &mut bool) -> Result<()> {
-        let mut irq: u8 = 0x00;
-
-        self.spi_read_register(SX1278LoRaRegister::IRQ_FLAGS, &mut irq)
-            .context("LoRa::has_crc_error")?;
-        if irq & SX1278IRQMask::IRQ_PAYLOAD_CRC_ERROR as u8
-            == SX1278IRQMask::IRQ_PAYLOAD_CRC_ERROR as u8
-        {
-            *has_crc_error = true;
-        }
+    /*
+     * Silicon revision, 0x12 for SX1276/77/78
+     */
+    pub fn get_version(&mut self) -> Result<u8> {
+        let mut value = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::VERSION, &mut value)
+            .context("LoRa::get_version")?;
 
-        Ok(())
+        Ok(value)
     }
 
-    pub fn config_radio(&mut self, radio_config: &RadioConfig) -> Result<()> {
-        self.set_frequency(433_000_000)
-            .context("LoRa::config_radio")?;
-        self.set_bandwidth(radio_config.bandwidth)
-            .context("LoRa::config_radio")?;
-        self.set_coding_rate(radio_config.coding_rate)
-            .context("LoRa::config_radio")?;
-        self.set_spreading_factor(radio_config.spreading_factor)
-            .context("LoRa::config_radio")?;
-        self.enable_crc().context("LoRa::config_radio")?;
-        self.set_tx_power(radio_config.tx_power)
-            .context("LoRa::config_radio")?;
-        self.spi_write_register(SX1278LoRaRegister::MODEM_CONFIG_3, 0x04u8)
-            .context("LoRa::config_radio")?;
+    pub fn get_low_data_rate_optimize(&mut self) -> Result<bool> {
+        let mut value = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::MODEM_CONFIG_3, &mut value)
+            .context("LoRa::get_low_data_rate_optimize")?;
 
-        Ok(())
+        Ok(value & 0x08 == 0x08)
     }
 
-    pub fn receive_packet(&mut self, crc_error: &mut bool) -> Result<Vec<u8>> {
-        let mut return_length = 0;
-
-        self.receive_mode().context("LoRa::receive_packet")?;
-
-        loop {
-            // this blocks and waits for a pin event
-            let dio0_event = self.dio0_pin.read_event().context("LoRa::receive_packet")?;
-
-            // packet is received on rising edge of DIO0
-            if dio0_event.edge == Edge::Rising {
-                let mut has_crc_error = false;
-                self.has_crc_error(&mut has_crc_error)
-                    .context("LoRa::receive_packet")?;
-                if has_crc_error {
-                    *crc_error = true;
-                }
-
-                break;
-            }
-        }
+    pub fn get_agc(&mut self) -> Result<bool> {
+        let mut value = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::MODEM_CONFIG_3, &mut value)
+            .context("LoRa::get_agc")?;
 
-        self.standby_mode().context("LoRa::receive_packet")?;
+        Ok(value & 0x04 == 0x04)
+    }
 
-        self.spi_read_register(SX1278LoRaRegister::RX_NB_BYTES, &mut return_length)
-            .context("LoRa::receive_packet")?;
-        let mut buffer: Vec<u8> = vec![0; return_length.into()];
+    pub fn get_preamble_length(&mut self) -> Result<u16> {
+        let mut values: [u8; 2] = [0, 0];
+        self.spi_burst_read(SX1278LoRaRegister::PREAMBLE_MSB, &mut values)
+            .context("LoRa::get_preamble_length")?;
 
-        let mut received_address = 0x00;
-        self.spi_read_register(
-            SX1278LoRaRegister::FIFO_RX_CURRENT_ADDR,
-            &mut received_address,
-        )
-        .context("LoRa::receive_packet")?;
-        self.spi_write_register(SX1278LoRaRegister::FIFO_ADDR_PTR, received_address)
-            .context("LoRa::receive_packet")?;
+        Ok(u16::from_be_bytes(values))
+    }
 
-        self.read_fifo(&mut buffer)
-            .context("LoRa::receive_packet")?;
+    pub fn get_sync_word(&mut self) -> Result<u8> {
+        let mut value = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::SYNC_WORD, &mut value)
+            .context("LoRa::get_sync_word")?;
 
-        Ok(buffer)
+        Ok(value)
     }
 
-    pub fn send_packet(&mut self, buffer: Vec<u8>) -> Result<()> {
-        let mut tx_address = 0x00;
-        self.spi_read_register(SX1278LoRaRegister::FIFO_TX_BASE_ADDR, &mut tx_address)
-            .context("LoRa::send_packet")?;
-        self.spi_write_register(SX1278LoRaRegister::FIFO_ADDR_PTR, tx_address)
-            .context("LoRa::send_packet")?;
+    pub fn get_implicit_header(&mut self) -> Result<bool> {
+        let mut value = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::MODEM_CONFIG_1, &mut value)
+            .context("LoRa::get_implicit_header")?;
 
-        self.spi_write_register(SX1278LoRaRegister::PAYLOAD_LENGTH, buffer.len() as u8)
-            .context("LoRa::send_packet")?;
-        self.write_fifo(buffer).context("LoRa::send_packet")?;
+        Ok(value & 0x01 == 0x01)
+    }
 
-        self.transmit_mode().context("LoRa::send_packet")?;
+    pub fn get_payload_length(&mut self) -> Result<u8> {
+        let mut value = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::PAYLOAD_LENGTH, &mut value)
+            .context("LoRa::get_payload_length")?;
 
-        loop {
-            let dio0_event = self.dio0_pin.read_event().context("LoRa::send_packet")?;
+        Ok(value)
+    }
 
-            if dio0_event.edge == Edge::Rising {
-                // rising edge of DIO0 indicates succesful packet send
-                println!("Packet sent.");
-                break;
+    /*
+     * In FSK/OOK mode only the latched flags are cleared, the rest follow the FIFO and mode
+     */
+    pub fn clear_irq_flags(&mut self) -> Result<()> {
+        match self.modulation {
+            Modulation::LoRa => {
+                self.spi_write_register(SX1278LoRaRegister::IRQ_FLAGS, 0xFF)
+                    .context("LoRa::clear_irq_flags")?;
+            }
+            _ => {
+                self.spi_write_register(SX1278FskRegister::IRQ_FLAGS_1, 0xFF)
+                    .context("LoRa::clear_irq_flags")?;
+                self.spi_write_register(SX1278FskRegister::IRQ_FLAGS_2, 0xFF)
+                    .context("LoRa::clear_irq_flags")?;
             }
         }
+        Ok(())
+    }
 
-        self.sleep_mode().context("LoRa::send_packet")?;
-
+    pub fn set_dio0_mapping(&mut self, mapping: SX1278Dio0Mapping) -> Result<()> {
+        let mut value = 0x00;
+        let register = SX1278LoRaRegister::DIO_MAPPING_1;
+        self.spi_read_register(register, &mut value)
+            .context("LoRa::set_dio0_mapping")?;
+        self.spi_write_register(register, (value & 0x3f) | ((mapping as u8) << 6))
+            .context("LoRa::set_dio0_mapping")?;
         Ok(())
     }
 
-    #[cfg(target_arch = "arm")]
-    pub fn reset(&mut self) -> Result<()> {
-        // pull NRST pin low for 5 ms
-        self.reset_pin
-            .set_values(0x00_u8)
-            .context("LoRa::LoRa reset: while setting reset_pin low")?;
+    /*
+     * Performs a single Channel Activity Detection,
+     * returns true if a LoRa preamble was detected.
+     * DIO0 mapping is restored afterwards, the chip is left in STDBY
+     */
+    pub fn channel_activity_detect(&mut self) -> Result<bool> {
+        if self.modulation != Modulation::LoRa {
+            return Err(anyhow!("Channel Activity Detection requires LoRa modulation"))
+                .context("LoRa::channel_activity_detect");
+        }
 
-        Self::sleep(5);
+        self.standby_mode()
+            .context("LoRa::channel_activity_detect")?;
 
-        self.reset_pin
-            .set_values(0x01_u8)
-            .context("LoRa::LoRa reset: while setting reset_pin high")?;
+        let mut dio_mapping = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::DIO_MAPPING_1, &mut dio_mapping)
+            .context("LoRa::channel_activity_detect")?;
+        self.set_dio0_mapping(SX1278Dio0Mapping::CAD_DONE)
+            .context("LoRa::channel_activity_detect")?;
+        self.clear_irq_flags()
+            .context("LoRa::channel_activity_detect")?;
 
-        // wait 10 ms before using the chip
-        Self::sleep(10);
+        self.cad_mode().context("LoRa::channel_activity_detect")?;
+
+        // DIO0 going high indicates CadDone
+        self.wait_for_dio(&[DioPin::DIO0], None)
+            .context("LoRa::channel_activity_detect")?;
+
+        let mut irq = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::IRQ_FLAGS, &mut irq)
+            .context("LoRa::channel_activity_detect")?;
+        let detected = irq & SX1278IRQMask::IRQ_CAD_DETECTED_MASK as u8
+            == SX1278IRQMask::IRQ_CAD_DETECTED_MASK as u8;
+
+        self.clear_irq_flags()
+            .context("LoRa::channel_activity_detect")?;
+        self.spi_write_register(SX1278LoRaRegister::DIO_MAPPING_1, dio_mapping)
+            .context("LoRa::channel_activity_detect")?;
+        self.standby_mode()
+            .context("LoRa::channel_activity_detect")?;
+
+        Ok(detected)
+    }
+
+    /*
+     * Waits for a free channel using CAD with a random backoff,
+     * fails if the channel is still busy after max_attempts
+     */
+    pub fn listen_before_talk(&mut self, config: &ListenBeforeTalkConfig) -> Result<()> {
+        for attempt in 1..=config.max_attempts {
+            if !self
+                .channel_activity_detect()
+                .context("LoRa::listen_before_talk")?
+            {
+                return Ok(());
+            }
+
+            let backoff = rand::thread_rng().gen_range(config.min_backoff_ms..=config.max_backoff_ms);
+            info!(
+                "Channel busy (attempt {}/{}), backing off for {} ms",
+                attempt, config.max_attempts, backoff
+            );
+            Self::sleep(backoff);
+        }
+
+        Err(anyhow!(
+            "Channel still busy after {} CAD attempts",
+            config.max_attempts
+        ))
+        .context("LoRa::listen_before_talk")
+    }
+
+    pub fn has_crc_error(&mut self, has_crc_error: &mut bool) -> Result<()> {
+        let mut irq: u8 = 0x00;
+
+        if self.modulation != Modulation::LoRa {
+            let mut packet_config = 0x00;
+            self.spi_read_register(SX1278FskRegister::PACKET_CONFIG_1, &mut packet_config)
+                .context("LoRa::has_crc_error")?;
+            self.spi_read_register(SX1278FskRegister::IRQ_FLAGS_2, &mut irq)
+                .context("LoRa::has_crc_error")?;
+            let crc_on = packet_config & SX1278FskPacketConfig1::CRC_ON as u8 != 0;
+            if crc_on && irq & SX1278FskIRQMask::IRQ_CRC_OK_MASK as u8 == 0 {
+                *has_crc_error = true;
+            }
+            return Ok(());
+        }
+
+        self.spi_read_register(SX1278LoRaRegister::IRQ_FLAGS, &mut irq)
+            .context("LoRa::has_crc_error")?;
+        if irq & SX1278IRQMask::IRQ_PAYLOAD_CRC_ERROR as u8
+            == SX1278IRQMask::IRQ_PAYLOAD_CRC_ERROR as u8
+        {
+            *has_crc_error = true;
+        }
 
         Ok(())
     }
 
-    #[cfg(target_arch = "arm")]
-    pub fn config_dio(&mut self) -> Result<()> {
-        let mut initial_value = 0x00;
-        self.spi_read_register(SX1278LoRaRegister::DIO_MAPPING_1, &mut initial_value)
-            .context("LoRa::config_dio")?;
-        match self.mode {
-            Mode::RX | Mode::RX_RANGE_TEST => {}
-            Mode::TX | Mode::TX_RANGE_TEST => {
-                self.spi_write_register(
-                    SX1278LoRaRegister::DIO_MAPPING_1,
-                    initial_value | (0b01 << 6),
-                )
-                .context("LoRa::config_dio")?; // DIO0 TxDone
+    pub fn config_radio(&mut self, radio_config: &RadioConfig) -> Result<()> {
+        self.set_frequency(radio_config.frequency)
+            .context("LoRa::config_radio")?;
+        self.verify_frequency(radio_config.frequency)
+            .context("LoRa::config_radio")?;
+        self.set_tx_power(radio_config.pa_output, radio_config.tx_power, radio_config.max_power)
+            .context("LoRa::config_radio")?;
+        self.set_ocp(radio_config.ocp_trim_ma())
+            .context("LoRa::config_radio")?;
+        self.set_lna(radio_config.lna_gain.unwrap_or(LnaGain::G1), radio_config.lna_boost)
+            .context("LoRa::config_radio")?;
+
+        match (radio_config.modulation, &radio_config.fsk) {
+            (Modulation::LoRa, _) => self
+                .config_lora_modem(radio_config)
+                .context("LoRa::config_radio"),
+            (_, Some(fsk_config)) => self
+                .config_fsk_modem(fsk_config, radio_config.agc)
+                .context("LoRa::config_radio"),
+            (modulation, None) => Err(anyhow!("{:?} modulation requires the fsk settings", modulation))
+                .context("LoRa::config_radio"),
+        }
+    }
+
+    fn config_lora_modem(&mut self, radio_config: &RadioConfig) -> Result<()> {
+        self.set_bandwidth(radio_config.bandwidth)
+            .context("LoRa::config_lora_modem")?;
+        self.set_coding_rate(radio_config.coding_rate)
+            .context("LoRa::config_lora_modem")?;
+        self.set_spreading_factor(radio_config.spreading_factor)
+            .context("LoRa::config_lora_modem")?;
+        self.set_header_mode(radio_config.implicit_header, radio_config.payload_length)
+            .context("LoRa::config_lora_modem")?;
+        self.set_preamble_length(radio_config.preamble_length)
+            .context("LoRa::config_lora_modem")?;
+        self.set_sync_word(radio_config.sync_word)
+            .context("LoRa::config_lora_modem")?;
+        self.enable_crc().context("LoRa::config_lora_modem")?;
+
+        let low_data_rate_optimize = radio_config.low_data_rate_optimize.unwrap_or_else(|| {
+            needs_low_data_rate_optimize(radio_config.bandwidth, radio_config.spreading_factor)
+        });
+        self.set_modem_config_3(low_data_rate_optimize, radio_config.agc)
+            .context("LoRa::config_lora_modem")?;
+        if let Some(symbol_timeout) = radio_config.symbol_timeout {
+            self.set_symbol_timeout(symbol_timeout)
+                .context("LoRa::config_lora_modem")?;
+        }
+        if let Some((_, hop_period)) = self.fhss {
+            self.set_hop_period(hop_period)
+                .context("LoRa::config_lora_modem")?;
+            self.set_dio1_mapping(SX1278Dio1Mapping::FHSS_CHANGE_CHANNEL)
+                .context("LoRa::config_lora_modem")?;
+        }
+
+        Ok(())
+    }
+
+    fn config_fsk_modem(&mut self, fsk_config: &FskConfig, agc: bool) -> Result<()> {
+        self.set_bitrate(fsk_config.bitrate)
+            .context("LoRa::config_fsk_modem")?;
+        if self.modulation == Modulation::FSK {
+            self.set_frequency_deviation(fsk_config.frequency_deviation)
+                .context("LoRa::config_fsk_modem")?;
+        }
+        self.set_rx_bandwidth(fsk_config.rx_bandwidth)
+            .context("LoRa::config_fsk_modem")?;
+        self.set_data_shaping(fsk_config.data_shaping)
+            .context("LoRa::config_fsk_modem")?;
+        self.set_fsk_preamble_length(fsk_config.preamble_length)
+            .context("LoRa::config_fsk_modem")?;
+        self.set_fsk_sync_word(&fsk_config.sync_word)
+            .context("LoRa::config_fsk_modem")?;
+        self.set_fsk_packet_config(fsk_config.whitening, fsk_config.crc)
+            .context("LoRa::config_fsk_modem")?;
+        self.set_rx_config(agc).context("LoRa::config_fsk_modem")?;
+
+        Ok(())
+    }
+
+    /*
+     * FSK/OOK bitrate, BitRate = FXOSC / (BitRate(15:0) + BitRateFrac / 16)
+     */
+    pub fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
+        let value = FXOSC * 16 / bitrate as u64;
+        if value >> 4 > u16::MAX as u64 {
+            return Err(anyhow!("Bitrate {} too low", bitrate)).context("LoRa::set_bitrate");
+        }
+        self.spi_burst_write(SX1278FskRegister::BITRATE_MSB, &((value >> 4) as u16).to_be_bytes())
+            .context("LoRa::set_bitrate")?;
+        self.spi_write_register(SX1278FskRegister::BITRATE_FRAC, (value & 0x0F) as u8)
+            .context("LoRa::set_bitrate")?;
+        Ok(())
+    }
+
+    pub fn get_bitrate(&mut self) -> Result<u32> {
+        let mut values: [u8; 2] = [0, 0];
+        let mut fraction = 0x00;
+        self.spi_burst_read(SX1278FskRegister::BITRATE_MSB, &mut values)
+            .context("LoRa::get_bitrate")?;
+        self.spi_read_register(SX1278FskRegister::BITRATE_FRAC, &mut fraction)
+            .context("LoRa::get_bitrate")?;
+
+        let value = ((u16::from_be_bytes(values) as u64) << 4) | (fraction & 0x0F) as u64;
+        if value == 0 {
+            return Err(anyhow!("Bitrate registers are empty")).context("LoRa::get_bitrate");
+        }
+        Ok((FXOSC * 16 / value) as u32)
+    }
+
+    /*
+     * FSK frequency deviation, Fdev = FSTEP * Fdev(13:0)
+     */
+    pub fn set_frequency_deviation(&mut self, deviation: u32) -> Result<()> {
+        let fdev = ((deviation as u64) << 19) / FXOSC;
+        if fdev > 0x3FFF {
+            return Err(anyhow!("Frequency deviation {} Hz too high", deviation))
+                .context("LoRa::set_frequency_deviation");
+        }
+        self.spi_burst_write(SX1278FskRegister::FDEV_MSB, &(fdev as u16).to_be_bytes())
+            .context("LoRa::set_frequency_deviation")?;
+        Ok(())
+    }
+
+    pub fn get_frequency_deviation(&mut self) -> Result<u32> {
+        let mut values: [u8; 2] = [0, 0];
+        self.spi_burst_read(SX1278FskRegister::FDEV_MSB, &mut values)
+            .context("LoRa::get_frequency_deviation")?;
+
+        let fdev = (u16::from_be_bytes(values) & 0x3FFF) as u64;
+        Ok(((fdev * FXOSC) >> 19) as u32)
+    }
+
+    /*
+     * Picks the narrowest channel filter at least `bandwidth` Hz wide, AFC uses the same one
+     */
+    pub fn set_rx_bandwidth(&mut self, bandwidth: u32) -> Result<()> {
+        let (value, _) = fsk_rx_bandwidth(self.modulation, bandwidth)
+            .context("LoRa::set_rx_bandwidth")?;
+        self.spi_write_register(SX1278FskRegister::RX_BW, value)
+            .context("LoRa::set_rx_bandwidth")?;
+        self.spi_write_register(SX1278FskRegister::AFC_BW, value)
+            .context("LoRa::set_rx_bandwidth")?;
+        Ok(())
+    }
+
+    pub fn set_data_shaping(&mut self, data_shaping: Option<GaussianFilter>) -> Result<()> {
+        let shaping = data_shaping.map_or(0x00, |filter| filter as u8);
+        self.spi_write_register(
+            SX1278FskRegister::PA_RAMP,
+            (shaping << 5) | SX1278FskConfig::PA_RAMP_40_US as u8,
+        )
+        .context("LoRa::set_data_shaping")?;
+        Ok(())
+    }
+
+    /*
+     * Preamble length in bytes, the receiver triggers on a 2 byte preamble
+     */
+    pub fn set_fsk_preamble_length(&mut self, preamble_length: u16) -> Result<()> {
+        self.spi_burst_write(SX1278FskRegister::PREAMBLE_MSB, &preamble_length.to_be_bytes())
+            .context("LoRa::set_fsk_preamble_length")?;
+        self.spi_write_register(
+            SX1278FskRegister::PREAMBLE_DETECT,
+            SX1278FskConfig::PREAMBLE_DETECTOR_ON as u8,
+        )
+        .context("LoRa::set_fsk_preamble_length")?;
+        Ok(())
+    }
+
+    pub fn set_fsk_sync_word(&mut self, sync_word: &[u8]) -> Result<()> {
+        if !(1..=8).contains(&sync_word.len()) {
+            return Err(anyhow!("Sync word should be 1 to 8 bytes long"))
+                .context("LoRa::set_fsk_sync_word");
+        }
+        self.spi_write_register(
+            SX1278FskRegister::SYNC_CONFIG,
+            SX1278FskSyncConfig::AUTO_RESTART_RX as u8
+                | SX1278FskSyncConfig::SYNC_ON as u8
+                | (sync_word.len() - 1) as u8,
+        )
+        .context("LoRa::set_fsk_sync_word")?;
+        self.spi_burst_write(SX1278FskRegister::SYNC_VALUE_1, sync_word)
+            .context("LoRa::set_fsk_sync_word")?;
+        Ok(())
+    }
+
+    /*
+     * Variable length packets in packet mode, optionally whitened and CRC protected
+     */
+    pub fn set_fsk_packet_config(&mut self, whitening: bool, crc: bool) -> Result<()> {
+        let mut packet_config = SX1278FskPacketConfig1::VARIABLE_LENGTH as u8;
+        if whitening {
+            packet_config |= SX1278FskPacketConfig1::WHITENING as u8;
+        }
+        if crc {
+            packet_config |= SX1278FskPacketConfig1::CRC_ON as u8
+                | SX1278FskPacketConfig1::CRC_AUTO_CLEAR_OFF as u8;
+        }
+        self.spi_write_register(SX1278FskRegister::PACKET_CONFIG_1, packet_config)
+            .context("LoRa::set_fsk_packet_config")?;
+        self.spi_write_register(
+            SX1278FskRegister::PACKET_CONFIG_2,
+            SX1278FskPacketConfig2::PACKET_MODE as u8,
+        )
+        .context("LoRa::set_fsk_packet_config")?;
+        // maximum length accepted in RX
+        self.spi_write_register(SX1278FskRegister::PAYLOAD_LENGTH, FSK_MAX_PAYLOAD_LENGTH as u8)
+            .context("LoRa::set_fsk_packet_config")?;
+        self.spi_write_register(
+            SX1278FskRegister::FIFO_THRESH,
+            SX1278FskConfig::TX_START_FIFO_NOT_EMPTY as u8,
+        )
+        .context("LoRa::set_fsk_packet_config")?;
+        Ok(())
+    }
+
+    pub fn set_rx_config(&mut self, agc: bool) -> Result<()> {
+        let mut value = SX1278FskRxConfig::RX_TRIGGER_PREAMBLE_DETECT as u8;
+        if agc {
+            value |= SX1278FskRxConfig::AGC_AUTO_ON as u8;
+        }
+        self.spi_write_register(SX1278FskRegister::RX_CONFIG, value)
+            .context("LoRa::set_rx_config")?;
+        Ok(())
+    }
+
+    /*
+     * Blocks until one of the given DIO pins goes high and returns it.
+     * Returns None if the deadline passes first, without a deadline this blocks.
+     * DIO lines stay high until IRQ flags are cleared
+     */
+    fn wait_for_dio(&mut self, pins: &[DioPin], deadline: Option<Instant>) -> Result<Option<DioPin>> {
+        let dio0 = pins.contains(&DioPin::DIO0).then_some(&mut self.dio0_pin);
+        let dio1 = match (pins.contains(&DioPin::DIO1), &mut self.dio1_pin) {
+            (true, Some(dio1_pin)) => Some(dio1_pin),
+            (true, None) => return Err(anyhow!("dio1_gpio not configured")).context("LoRa::wait_for_dio"),
+            (false, _) => None,
+        };
+        let (mut lines, dio_pins): (Vec<&mut IN>, Vec<DioPin>) = [(dio0, DioPin::DIO0), (dio1, DioPin::DIO1)]
+            .into_iter()
+            .filter_map(|(line, pin)| line.map(|line| (line, pin)))
+            .unzip();
+
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let index = IN::wait_for_high(&mut lines, timeout)
+            .map_err(|e| anyhow!("{:?}", e))
+            .context("LoRa::wait_for_dio")?;
+        Ok(index.map(|index| dio_pins[index]))
+    }
+
+    pub fn receive_single_mode(&mut self) -> Result<()> {
+        self.spi_write_register(
+            SX1278LoRaRegister::OP_MODE,
+            self.op_mode_value(SX1278LoRaMode::RX_SINGLE),
+        )
+        .context("LoRa::receive_single_mode")?;
+        Ok(())
+    }
+
+    /*
+     * Number of symbols RX_SINGLE waits for a preamble before raising RxTimeout
+     */
+    pub fn set_symbol_timeout(&mut self, symbol_timeout: u16) -> Result<()> {
+        if !(4..=1023).contains(&symbol_timeout) {
+            return Err(anyhow!(
+                "Symbol timeout {} out of range, should be within 4-1023",
+                symbol_timeout
+            ))
+            .context("LoRa::set_symbol_timeout");
+        }
+
+        let mut value = 0x00;
+        let register = SX1278LoRaRegister::MODEM_CONFIG_2;
+        self.spi_read_register(register, &mut value)
+            .context("LoRa::set_symbol_timeout")?;
+        self.spi_write_register(register, (value & 0xfc) | ((symbol_timeout >> 8) as u8 & 0x03))
+            .context("LoRa::set_symbol_timeout")?;
+        self.spi_write_register(SX1278LoRaRegister::SYMB_TIMEOUT_LSB, symbol_timeout as u8)
+            .context("LoRa::set_symbol_timeout")?;
+
+        Ok(())
+    }
+
+    pub fn get_symbol_timeout(&mut self) -> Result<u16> {
+        let mut msb = 0x00;
+        let mut lsb = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::MODEM_CONFIG_2, &mut msb)
+            .context("LoRa::get_symbol_timeout")?;
+        self.spi_read_register(SX1278LoRaRegister::SYMB_TIMEOUT_LSB, &mut lsb)
+            .context("LoRa::get_symbol_timeout")?;
+
+        Ok((((msb & 0x03) as u16) << 8) | lsb as u16)
+    }
+
+    pub fn set_dio1_mapping(&mut self, mapping: SX1278Dio1Mapping) -> Result<()> {
+        let mut value = 0x00;
+        let register = SX1278LoRaRegister::DIO_MAPPING_1;
+        self.spi_read_register(register, &mut value)
+            .context("LoRa::set_dio1_mapping")?;
+        self.spi_write_register(register, (value & 0xcf) | ((mapping as u8) << 4))
+            .context("LoRa::set_dio1_mapping")?;
+        Ok(())
+    }
+
+    /*
+     * Symbols between frequency hops, 0 disables hopping
+     */
+    pub fn set_hop_period(&mut self, hop_period: u8) -> Result<()> {
+        self.spi_write_register(SX1278LoRaRegister::HOP_PERIOD, hop_period)
+            .context("LoRa::set_hop_period")?;
+        Ok(())
+    }
+
+    pub fn get_hop_period(&mut self) -> Result<u8> {
+        let mut value = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::HOP_PERIOD, &mut value)
+            .context("LoRa::get_hop_period")?;
+        Ok(value)
+    }
+
+    /*
+     * Tunes to the first channel of the hopping sequence, every packet starts there.
+     * Should be called in SLEEP or STDBY mode
+     */
+    fn fhss_start(&mut self) -> Result<()> {
+        if let Some((sequence, _)) = &self.fhss {
+            let first_channel = sequence[0];
+            self.write_frf(first_channel).context("LoRa::fhss_start")?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Handles FhssChangeChannel: FhssPresentChannel counts the hops since the start
+     * of the packet, FRF is set to the matching channel of the sequence
+     */
+    pub fn hop_channel(&mut self) -> Result<()> {
+        let sequence = match &self.fhss {
+            Some((sequence, _)) => sequence.clone(),
+            None => return Err(anyhow!("FHSS not configured")).context("LoRa::hop_channel"),
+        };
+
+        let mut hop_channel = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::HOP_CHANNEL, &mut hop_channel)
+            .context("LoRa::hop_channel")?;
+        let present_channel = (hop_channel & 0x3f) as usize;
+        self.write_frf(sequence[present_channel % sequence.len()])
+            .context("LoRa::hop_channel")?;
+        self.spi_write_register(
+            SX1278LoRaRegister::IRQ_FLAGS,
+            SX1278IRQMask::IRQ_FHSS_CHANGE_CHANNEL_MASK as u8,
+        )
+        .context("LoRa::hop_channel")?;
+        Ok(())
+    }
+
+    /*
+     * Waits for RxDone/TxDone on DIO0, hopping channels on FhssChangeChannel (DIO1) meanwhile.
+     * Returns false if the deadline passes first
+     */
+    fn wait_for_done(&mut self, deadline: Option<Instant>) -> Result<bool> {
+        let pins: &[DioPin] = match self.fhss {
+            Some(_) => &[DioPin::DIO0, DioPin::DIO1],
+            None => &[DioPin::DIO0],
+        };
+
+        loop {
+            match self.wait_for_dio(pins, deadline).context("LoRa::wait_for_done")? {
+                Some(DioPin::DIO0) => return Ok(true),
+                Some(DioPin::DIO1) => self.hop_channel().context("LoRa::wait_for_done")?,
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /*
+     * Reads the last received packet from the FIFO, should be called after RxDone
+     */
+    fn read_received_packet(&mut self, crc_error: &mut bool) -> Result<Vec<u8>> {
+        let mut return_length = 0;
+
+        let mut has_crc_error = false;
+        self.has_crc_error(&mut has_crc_error)
+            .context("LoRa::read_received_packet")?;
+        if has_crc_error {
+            *crc_error = true;
+        }
+
+        if self.modulation != Modulation::LoRa {
+            // variable length packets start with their length
+            self.spi_read_register(SX1278LoRaRegister::FIFO, &mut return_length)
+                .context("LoRa::read_received_packet")?;
+            let mut buffer: Vec<u8> = vec![0; (return_length as usize).min(FSK_MAX_PAYLOAD_LENGTH)];
+            self.read_fifo(&mut buffer)
+                .context("LoRa::read_received_packet")?;
+            self.standby_mode().context("LoRa::read_received_packet")?;
+            self.clear_irq_flags()
+                .context("LoRa::read_received_packet")?;
+            return Ok(buffer);
+        }
+
+        self.standby_mode().context("LoRa::read_received_packet")?;
+
+        self.spi_read_register(SX1278LoRaRegister::RX_NB_BYTES, &mut return_length)
+            .context("LoRa::read_received_packet")?;
+        let mut buffer: Vec<u8> = vec![0; return_length.into()];
+
+        let mut received_address = 0x00;
+        self.spi_read_register(
+            SX1278LoRaRegister::FIFO_RX_CURRENT_ADDR,
+            &mut received_address,
+        )
+        .context("LoRa::read_received_packet")?;
+        self.spi_write_register(SX1278LoRaRegister::FIFO_ADDR_PTR, received_address)
+            .context("LoRa::read_received_packet")?;
+
+        self.read_fifo(&mut buffer)
+            .context("LoRa::read_received_packet")?;
+        self.clear_irq_flags()
+            .context("LoRa::read_received_packet")?;
+
+        Ok(buffer)
+    }
+
+    pub fn receive_packet(&mut self, crc_error: &mut bool) -> Result<Vec<u8>> {
+        self.fhss_start().context("LoRa::receive_packet")?;
+        self.clear_irq_flags().context("LoRa::receive_packet")?;
+        self.receive_mode().context("LoRa::receive_packet")?;
+
+        // packet is received when DIO0 (RxDone) goes high, this blocks
+        self.wait_for_done(None).context("LoRa::receive_packet")?;
+
+        self.read_received_packet(crc_error)
+            .context("LoRa::receive_packet")
+    }
+
+    /*
+     * Receives in RX_CONTINUOUS mode, giving up at the deadline
+     */
+    pub fn receive_packet_until(
+        &mut self,
+        deadline: Instant,
+        crc_error: &mut bool,
+    ) -> Result<ReceiveResult> {
+        self.fhss_start().context("LoRa::receive_packet_until")?;
+        self.clear_irq_flags()
+            .context("LoRa::receive_packet_until")?;
+        self.receive_mode().context("LoRa::receive_packet_until")?;
+
+        if self
+            .wait_for_done(Some(deadline))
+            .context("LoRa::receive_packet_until")?
+        {
+            Ok(ReceiveResult::Packet(
+                self.read_received_packet(crc_error)
+                    .context("LoRa::receive_packet_until")?,
+            ))
+        } else {
+            self.standby_mode()
+                .context("LoRa::receive_packet_until")?;
+            Ok(ReceiveResult::Timeout)
+        }
+    }
+
+    /*
+     * Receives in RX_SINGLE mode, the chip gives up after symbol_timeout symbols
+     * without a preamble and signals RxTimeout on DIO1
+     */
+    pub fn receive_packet_single(
+        &mut self,
+        symbol_timeout: u16,
+        crc_error: &mut bool,
+    ) -> Result<ReceiveResult> {
+        if self.dio1_pin.is_none() {
+            return Err(anyhow!("RX_SINGLE receive requires dio1_gpio"))
+                .context("LoRa::receive_packet_single");
+        }
+        if self.modulation != Modulation::LoRa {
+            return Err(anyhow!("RX_SINGLE receive requires LoRa modulation"))
+                .context("LoRa::receive_packet_single");
+        }
+
+        self.standby_mode()
+            .context("LoRa::receive_packet_single")?;
+        self.set_symbol_timeout(symbol_timeout)
+            .context("LoRa::receive_packet_single")?;
+        self.set_dio1_mapping(SX1278Dio1Mapping::RX_TIMEOUT)
+            .context("LoRa::receive_packet_single")?;
+        self.clear_irq_flags()
+            .context("LoRa::receive_packet_single")?;
+        self.receive_single_mode()
+            .context("LoRa::receive_packet_single")?;
+
+        // the chip returns to STDBY by itself after RxDone or RxTimeout
+        match self
+            .wait_for_dio(&[DioPin::DIO0, DioPin::DIO1], None)
+            .context("LoRa::receive_packet_single")?
+        {
+            Some(DioPin::DIO0) => Ok(ReceiveResult::Packet(
+                self.read_received_packet(crc_error)
+                    .context("LoRa::receive_packet_single")?,
+            )),
+            _ => {
+                self.clear_irq_flags()
+                    .context("LoRa::receive_packet_single")?;
+                Ok(ReceiveResult::Timeout)
+            }
+        }
+    }
+
+    /*
+     * Receives the next packet using the receive timeout from the config, if any
+     */
+    pub fn receive_next(&mut self, crc_error: &mut bool) -> Result<ReceiveResult> {
+        match (self.symbol_timeout, self.rx_timeout_ms) {
+            (Some(symbol_timeout), _) => self
+                .receive_packet_single(symbol_timeout, crc_error)
+                .context("LoRa::receive_next"),
+            (None, Some(rx_timeout_ms)) => {
+                let deadline = Instant::now() + time::Duration::from_millis(rx_timeout_ms);
+                self.receive_packet_until(deadline, crc_error)
+                    .context("LoRa::receive_next")
+            }
+            (None, None) => Ok(ReceiveResult::Packet(
+                self.receive_packet(crc_error)
+                    .context("LoRa::receive_next")?,
+            )),
+        }
+    }
+
+    pub fn send_packet(&mut self, buffer: Vec<u8>) -> Result<()> {
+        if let Some(listen_before_talk) = self.listen_before_talk.clone() {
+            self.listen_before_talk(&listen_before_talk)
+                .context("LoRa::send_packet")?;
+        }
+
+        if self.modulation != Modulation::LoRa {
+            if buffer.len() > FSK_MAX_PAYLOAD_LENGTH {
+                return Err(anyhow!(
+                    "Packet length {} exceeds the FSK/OOK limit of {} bytes",
+                    buffer.len(),
+                    FSK_MAX_PAYLOAD_LENGTH
+                ))
+                .context("LoRa::send_packet");
+            }
+            // variable length packets start with their length
+            self.spi_write_register(SX1278LoRaRegister::FIFO, buffer.len() as u8)
+                .context("LoRa::send_packet")?;
+            self.write_fifo(&buffer).context("LoRa::send_packet")?;
+        } else {
+            self.write_lora_payload(&buffer).context("LoRa::send_packet")?;
+        }
+
+        self.fhss_start().context("LoRa::send_packet")?;
+        self.clear_irq_flags().context("LoRa::send_packet")?;
+        self.transmit_mode().context("LoRa::send_packet")?;
+
+        // DIO0 (TxDone/PacketSent) going high indicates succesful packet send
+        self.wait_for_done(None).context("LoRa::send_packet")?;
+        println!("Packet sent.");
+
+        self.clear_irq_flags().context("LoRa::send_packet")?;
+        self.sleep_mode().context("LoRa::send_packet")?;
+
+        Ok(())
+    }
+
+    /*
+     * Sends a packet in between receptions: DIO0 is mapped to TxDone for the transmission
+     * and back to RxDone afterwards, the rest of the configuration is kept
+     */
+    pub fn send_packet_from_rx(&mut self, buffer: Vec<u8>) -> Result<()> {
+        self.standby_mode().context("LoRa::send_packet_from_rx")?;
+
+        // FSK/OOK DIO0 mapping 00 is PayloadReady in RX and PacketSent in TX
+        if self.modulation != Modulation::LoRa {
+            return self.send_packet(buffer).context("LoRa::send_packet_from_rx");
+        }
+
+        self.set_dio0_mapping(SX1278Dio0Mapping::TX_DONE)
+            .context("LoRa::send_packet_from_rx")?;
+        let result = self.send_packet(buffer);
+        self.set_dio0_mapping(SX1278Dio0Mapping::RX_DONE)
+            .context("LoRa::send_packet_from_rx")?;
+
+        result.context("LoRa::send_packet_from_rx")
+    }
+
+    /*
+     * Sends a packet, split into parts if it is longer than PACKET_SIZE, with reliable delivery
+     * every part is retransmitted until it is acknowledged. Should be called with DIO0 mapped to TxDone
+     */
+    pub fn transmit_packet(&mut self, packet: &Packet) -> Result<()> {
+        let max_packet_size = self.max_packet_size();
+        for part in fragment(packet, max_packet_size).context("LoRa::transmit_packet")? {
+            self.standby_mode().context("LoRa::transmit_packet")?;
+            self.transmit_part(&part).context("LoRa::transmit_packet")?;
+        }
+        Ok(())
+    }
+
+    /*
+     * The longest packet sent in one frame, FSK/OOK frames hold at most FSK_MAX_PAYLOAD_LENGTH bytes
+     * and encryption makes a frame ENCRYPTION_OVERHEAD bytes longer than its packet
+     */
+    fn max_packet_size(&self) -> usize {
+        if self.modulation == Modulation::LoRa {
+            return PACKET_SIZE;
+        }
+        let overhead = if self.encryption.is_some() { ENCRYPTION_OVERHEAD } else { 0 };
+        PACKET_SIZE.min(FSK_MAX_PAYLOAD_LENGTH - overhead)
+    }
+
+    fn transmit_part(&mut self, packet: &Packet) -> Result<()> {
+        let config = match &self.reliable_delivery {
+            Some(reliable_delivery) => reliable_delivery.config().clone(),
+            None => {
+                let buffer = self.encode(packet).context("LoRa::transmit_part")?;
+                return self.send_packet(buffer).context("LoRa::transmit_part");
+            }
+        };
+
+        // every retransmission is sealed with a new frame counter
+        retransmit_until_acked(&config, packet, |ack_timeout| {
+            self.standby_mode()?;
+            let buffer = self.encode(packet)?;
+            self.send_packet(buffer)?;
+            self.wait_for_ack(packet, Instant::now() + ack_timeout)
+        })
+        .context("LoRa::transmit_part")
+    }
+
+    /*
+     * Sends a packet in between receptions like transmit_packet, reliable delivery included:
+     * DIO0 is mapped to TxDone for the transmission and back to RxDone afterwards
+     */
+    fn transmit_packet_from_rx(&mut self, packet: &Packet) -> Result<()> {
+        self.standby_mode().context("LoRa::transmit_packet_from_rx")?;
+
+        // FSK/OOK DIO0 mapping 00 is PayloadReady in RX and PacketSent in TX
+        if self.modulation != Modulation::LoRa {
+            return self.transmit_packet(packet).context("LoRa::transmit_packet_from_rx");
+        }
+
+        self.set_dio0_mapping(SX1278Dio0Mapping::TX_DONE)
+            .context("LoRa::transmit_packet_from_rx")?;
+        let result = self.transmit_packet(packet);
+        self.set_dio0_mapping(SX1278Dio0Mapping::RX_DONE)
+            .context("LoRa::transmit_packet_from_rx")?;
+
+        result.context("LoRa::transmit_packet_from_rx")
+    }
+
+    /*
+     * Receives until the ACK of the packet comes in or the deadline passes,
+     * other packets are dropped. DIO0 is mapped back to TxDone afterwards
+     */
+    fn wait_for_ack(&mut self, packet: &Packet, deadline: Instant) -> Result<bool> {
+        if self.modulation == Modulation::LoRa {
+            self.set_dio0_mapping(SX1278Dio0Mapping::RX_DONE)
+                .context("LoRa::wait_for_ack")?;
+        }
+
+        let acknowledged = loop {
+            let mut crc_error = false;
+            match self
+                .receive_packet_until(deadline, &mut crc_error)
+                .context("LoRa::wait_for_ack")?
+            {
+                ReceiveResult::Packet(buffer) => {
+                    if !crc_error && self.decode(&buffer).is_ok_and(|ack| is_ack_for(&ack, packet)) {
+                        break true;
+                    }
+                }
+                ReceiveResult::Timeout => break false,
+            }
+        };
+
+        if self.modulation == Modulation::LoRa {
+            self.set_dio0_mapping(SX1278Dio0Mapping::TX_DONE)
+                .context("LoRa::wait_for_ack")?;
+        }
+        Ok(acknowledged)
+    }
+
+    /*
+     * With reliable delivery every received packet but an ACK is acknowledged.
+     * Returns whether the packet should be forwarded, ACKs and repeats within
+     * the duplicate window are not
+     */
+    fn acknowledge(&mut self, packet: &Packet) -> Result<bool> {
+        let Some(reliable_delivery) = &mut self.reliable_delivery else {
+            return Ok(true);
+        };
+        if packet.data_type == DataType::Ack {
+            return Ok(false);
+        }
+
+        // a repeat means the last ACK got lost, so it is acknowledged again
+        let duplicate = reliable_delivery.is_duplicate(packet);
+        let ack = self.encode(&ack_packet(packet)).context("LoRa::acknowledge")?;
+        if let Err(e) = self.send_packet_from_rx(ack) {
+            eprintln!("{:?}", e);
+            error!("{:?}", e);
+        }
+
+        if duplicate {
+            println!("Duplicate of msg_id {}, not forwarded", packet.msg_id);
+            info!("Duplicate of msg_id {}, not forwarded", packet.msg_id);
+        }
+        Ok(!duplicate)
+    }
+
+    /*
+     * Serializes a packet for the air, with encryption sealed with the key of its device id
+     */
+    fn encode(&mut self, packet: &Packet) -> Result<Vec<u8>> {
+        let bytes = packet.to_bytes().context("LoRa::encode")?;
+        match &mut self.encryption {
+            Some(encryption) => encryption.seal(&bytes).context("LoRa::encode"),
+            None => Ok(bytes),
+        }
+    }
+
+    /*
+     * Parses a received frame, with encryption frames that fail authentication
+     * or are replayed are rejected
+     */
+    fn decode(&mut self, buffer: &[u8]) -> Result<Packet> {
+        match &mut self.encryption {
+            Some(encryption) => {
+                let bytes = encryption.open(buffer).context("LoRa::decode")?;
+                Packet::with_custom_types(&bytes, &self.custom_data_types).context("LoRa::decode")
+            }
+            None => Packet::with_custom_types(buffer, &self.custom_data_types).context("LoRa::decode"),
+        }
+    }
+
+    /*
+     * Collects the parts of multi-part messages, returns the packet to forward:
+     * the whole message once its last part is received, or the packet itself if it was sent whole
+     */
+    fn reassemble(&mut self, packet: Packet) -> Option<Packet> {
+        let is_part = packet.data_type == DataType::Fragment;
+        match self.reassembly.add(packet) {
+            Ok(Some(packet)) => {
+                if is_part {
+                    println!("Reassembled: {:#?}", packet);
+                    info!("Reassembled: {:?}", packet);
+                }
+                Some(packet)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                error!("{:?}", e);
+                None
+            }
+        }
+    }
+
+    fn write_lora_payload(&mut self, buffer: &[u8]) -> Result<()> {
+        let mut tx_address = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::FIFO_TX_BASE_ADDR, &mut tx_address)
+            .context("LoRa::write_lora_payload")?;
+        self.spi_write_register(SX1278LoRaRegister::FIFO_ADDR_PTR, tx_address)
+            .context("LoRa::write_lora_payload")?;
+
+        if self.get_implicit_header().context("LoRa::write_lora_payload")? {
+            let payload_length = self.get_payload_length().context("LoRa::write_lora_payload")?;
+            if buffer.len() != payload_length as usize {
+                return Err(anyhow!(
+                    "Packet length {} doesn't match the implicit header payload length {}",
+                    buffer.len(),
+                    payload_length
+                ))
+                .context("LoRa::write_lora_payload");
+            }
+        } else {
+            self.spi_write_register(SX1278LoRaRegister::PAYLOAD_LENGTH, buffer.len() as u8)
+                .context("LoRa::write_lora_payload")?;
+        }
+        self.write_fifo(buffer).context("LoRa::write_lora_payload")?;
+
+        Ok(())
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        // pull NRST pin low for 5 ms
+        self.reset_pin
+            .set_low()
+            .map_err(|e| anyhow!("{:?}", e))
+            .context("LoRa::LoRa reset: while setting reset_pin low")?;
+
+        Self::sleep(5);
+
+        self.reset_pin
+            .set_high()
+            .map_err(|e| anyhow!("{:?}", e))
+            .context("LoRa::LoRa reset: while setting reset_pin high")?;
+
+        // wait 10 ms before using the chip
+        Self::sleep(10);
+
+        Ok(())
+    }
+
+    pub fn config_dio(&mut self) -> Result<()> {
+        let mut initial_value = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::DIO_MAPPING_1, &mut initial_value)
+            .context("LoRa::config_dio")?;
+        if self.modulation != Modulation::LoRa {
+            // DIO0 mapping 00 is PayloadReady in RX and PacketSent in TX
+            self.spi_write_register(SX1278LoRaRegister::DIO_MAPPING_1, initial_value & 0x3f)
+                .context("LoRa::config_dio")?;
+            return Ok(());
+        }
+        match self.mode {
+            // TRX maps DIO0 to TxDone only while transmitting
+            Mode::RX | Mode::RX_RANGE_TEST | Mode::TRX => {}
+            Mode::TX | Mode::TX_RANGE_TEST => {
+                self.spi_write_register(
+                    SX1278LoRaRegister::DIO_MAPPING_1,
+                    initial_value | ((SX1278Dio0Mapping::TX_DONE as u8) << 6),
+                )
+                .context("LoRa::config_dio")?; // DIO0 TxDone
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Returns SNR[dB] on last packet received, 0.25 dB resolution.
+     * The FSK/OOK demodulator doesn't measure SNR, 0 is returned
+     */
+    pub fn get_packet_snr(&mut self) -> Result<f32> {
+        if self.modulation != Modulation::LoRa {
+            return Ok(0.0);
+        }
+
+        let mut value: u8 = 0x00;
+        self.spi_read_register(SX1278LoRaRegister::PKT_SNR_VALUE, &mut value)
+            .context("LoRa::get_packet_snr")?;
+
+        Ok(packet_snr_db(value))
+    }
+
+    /*
+     * Returns RSSI[dBm] of the last packet received
+     */
+    pub fn get_packet_rssi(&mut self) -> Result<i16> {
+        let mut value: u8 = 0x00;
+        if self.modulation != Modulation::LoRa {
+            // RSSI = -RssiValue / 2
+            self.spi_read_register(SX1278FskRegister::RSSI_VALUE, &mut value)
+                .context("LoRa::get_packet_rssi")?;
+            return Ok(-(value as i16) / 2);
+        }
+
+        let snr = self.get_packet_snr().context("LoRa::get_packet_rssi")?;
+        self.spi_read_register(SX1278LoRaRegister::PKT_RSSI_VALUE, &mut value)
+            .context("LoRa::get_packet_rssi")?;
+
+        Ok(packet_rssi_dbm(value, snr, self.frequency_band))
+    }
+
+    /*
+     * Returns the carrier frequency error[Hz] of the last packet received
+     */
+    pub fn get_frequency_error(&mut self) -> Result<i32> {
+        if self.modulation != Modulation::LoRa {
+            // two's complement in FSTEP units
+            let mut values: [u8; 2] = [0, 0];
+            self.spi_burst_read(SX1278FskRegister::FEI_MSB, &mut values)
+                .context("LoRa::get_frequency_error")?;
+            let fei = i16::from_be_bytes(values) as i64;
+            return Ok(((fei * FXOSC as i64) >> 19) as i32);
+        }
+
+        // FEI_MSB, FEI_MID, FEI_LSB
+        let mut values: [u8; 3] = [0, 0, 0];
+        self.spi_burst_read(SX1278LoRaRegister::FEI_MSB, &mut values)
+            .context("LoRa::get_frequency_error")?;
+        let bandwidth = self.get_bandwidth().context("LoRa::get_frequency_error")?;
+        let bandwidth = Bandwidth::from_register(bandwidth).context("LoRa::get_frequency_error")?;
+
+        let value = ((values[0] as u32 & 0x0F) << 16) | ((values[1] as u32) << 8) | values[2] as u32;
+        Ok(frequency_error_hz(value, bandwidth))
+    }
+
+    /*
+     * Collects the frequency error of a received packet and, once afc.window errors
+     * are collected, moves the RX frequency by their average.
+     * Must be called in sleep or standby mode, returns the new frequency if it changed
+     */
+    pub fn apply_afc(&mut self, frequency_error: i32) -> Result<Option<u64>> {
+        let afc = match &self.afc {
+            Some(afc) => afc.clone(),
+            None => return Ok(None),
+        };
+
+        self.afc_errors.push(frequency_error);
+        if self.afc_errors.len() < afc.window {
+            return Ok(None);
+        }
+
+        let average = self.afc_errors.iter().map(|e| *e as i64).sum::<i64>() / self.afc_errors.len() as i64;
+        self.afc_errors.clear();
+        if average.unsigned_abs() < FSTEP {
+            return Ok(None);
+        }
+
+        let frequency = self.get_frequency().context("LoRa::apply_afc")?;
+        let lowest = self.center_frequency.saturating_sub(afc.max_offset_hz);
+        let highest = self.center_frequency + afc.max_offset_hz;
+        let corrected = (frequency as i64 + average).clamp(lowest as i64, highest as i64) as u64;
+        if corrected.abs_diff(frequency) < FSTEP {
+            return Ok(None);
+        }
+
+        self.set_frequency(corrected).context("LoRa::apply_afc")?;
+        Ok(Some(corrected))
+    }
+
+    /*
+     * Prints a received packet and forwards it to MQTT or CSV, then corrects the RX frequency (AFC).
+     * The chip is left in SLEEP mode
+     */
+    fn publish_received(
+        &mut self,
+        received_buffer: Vec<u8>,
+        crc_error: bool,
+        output: &ReceivedOutput,
+    ) -> Result<()> {
+        println!();
+        println!(
+            "--------------------------------------------------------------------------------"
+        );
+        println!();
+
+        // FEI is read once, while the chip still holds it, and reused for the AFC
+        let frequency_error = match self.decode(&received_buffer) {
+            Ok(packet) => {
+                let snr = self.get_packet_snr().context("LoRa::publish_received")?;
+                let rssi = self.get_packet_rssi().context("LoRa::publish_received")?;
+                let frequency_error = self.get_frequency_error().context("LoRa::publish_received")?;
+
+                if !crc_error {
+                    println!(
+                        "Received: {:#?}, SNR = {} dB, RSSI = {} dBm, frequency error = {} Hz",
+                        packet, snr, rssi, frequency_error
+                    );
+                    info!(
+                        "Received: {:?}, SNR = {} dB, RSSI = {} dBm, frequency error = {} Hz",
+                        packet, snr, rssi, frequency_error
+                    );
+                    let forward = self.acknowledge(&packet).context("LoRa::publish_received")?;
+                    let option_packet = if forward { self.reassemble(packet) } else { None };
+                    if let Some(packet) = option_packet {
+                        output.send(PacketWrapper {
+                            packet,
+                            metadata: Metadata { snr, rssi, frequency_error },
+                        });
+                    }
+                } else {
+                    // using ANSI escape codes for colors in terminal
+                    println!("\x1b[0;31m[CRC ERROR]\x1b[0m\nReceived: {:#?}, SNR = {} dB, RSSI = {} dBm, frequency error = {} Hz", packet, snr, rssi, frequency_error);
+                    info!(
+                        "[CRC ERROR] Received: {:?}, SNR = {} dB, RSSI = {} dBm, frequency error = {} Hz",
+                        packet, snr, rssi, frequency_error
+                    );
+                }
+                frequency_error
+            }
+            Err(e) => {
+                println!("Bad package: {:?}", e);
+                error!("Bad package: {:?}", e);
+                println!();
+                println!("Received: {:02X?}", received_buffer);
+                self.sleep_mode().context("LoRa::publish_received")?;
+                return Ok(());
+            }
+        };
+
+        self.sleep_mode().context("LoRa::publish_received")?;
+
+        if !crc_error {
+            if let Some(frequency) = self.apply_afc(frequency_error).context("LoRa::publish_received")? {
+                println!("AFC: RX frequency corrected to {} Hz", frequency);
+                info!("AFC: RX frequency corrected to {} Hz", frequency);
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Receives until a shutdown is requested, every packet goes through publish_received
+     */
+    fn receive_into(&mut self, output: ReceivedOutput) -> Result<()> {
+        loop {
+            let mut crc_error = false;
+
+            let received_buffer = match self.receive_next(&mut crc_error) {
+                Ok(ReceiveResult::Packet(s)) => s,
+                // the receive timeout is where the loop can stop
+                Ok(ReceiveResult::Timeout) if self.shutdown.shutdown_requested() => return Ok(()),
+                Ok(ReceiveResult::Timeout) => continue,
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                    std::process::exit(-1);
+                }
+            };
+            self.publish_received(received_buffer, crc_error, &output)
+                .context("LoRa::start")?;
+        }
+    }
+}
+
+impl<SPI, OUT, IN> LoRa for SX1278<SPI, OUT, IN>
+where
+    SPI: SpiDevice + Send,
+    OUT: OutputPin + Send,
+    IN: WaitForHigh + Send,
+{
+    fn get_mode(&self) -> Mode {
+        self.mode.clone()
+    }
+
+    fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    fn configure_lora(&mut self, radio_config: &RadioConfig) -> Result<()> {
+        self.reset().context("LoRa::start")?;
+        self.sleep_mode().context("LoRa::start")?;
+        self.config_radio(radio_config).context("LoRa::start")?;
+        self.config_dio().context("LoRa::start")?;
+        Ok(())
+    }
+
+    fn display_parameters(&mut self, _radio_config: &RadioConfig) -> Result<()> {
+        let frequency = self.get_frequency().context("LoRa::start")?;
+        println!("+-------------------------+");
+        println!("| Frequency: {:.3} MHz  |", frequency as f64 / 1_000_000.0);
+        if self.modulation != Modulation::LoRa {
+            println!("| Modulation: {:<3}         |", format!("{:?}", self.modulation));
+            println!(
+                "| Bitrate: {:<6} bps     |",
+                self.get_bitrate().context("LoRa::start")?
+            );
+            if self.modulation == Modulation::FSK {
+                println!(
+                    "| Deviation: {:<6} Hz    |",
+                    self.get_frequency_deviation().context("LoRa::start")?
+                );
+            }
+            println!("| Mode: {:?}                |", self.mode);
+            println!("+-------------------------+");
+            return Ok(());
+        }
+        println!(
+            "| Bandwidth: {}            |",
+            self.get_bandwidth().context("LoRa::start")?
+        );
+        println!(
+            "| Coding rate: {}          |",
+            self.get_coding_rate().context("LoRa::start")?
+        );
+        println!(
+            "| Spreading factor: {:02}    |",
+            self.get_spreading_factor().context("LoRa::start")?
+        );
+        println!(
+            "| Preamble length: {:<5}  |",
+            self.get_preamble_length().context("LoRa::start")?
+        );
+        println!(
+            "| Sync word: 0x{:02X}         |",
+            self.get_sync_word().context("LoRa::start")?
+        );
+        if self.get_implicit_header().context("LoRa::start")? {
+            println!(
+                "| Header: implicit, {:<3}   |",
+                self.get_payload_length().context("LoRa::start")?
+            );
+        } else {
+            println!("| Header: explicit        |");
+        }
+        println!(
+            "| Low data rate opt.: {:<3} |",
+            if self.get_low_data_rate_optimize().context("LoRa::start")? { "on" } else { "off" }
+        );
+        println!(
+            "| AGC: {:<3}                |",
+            if self.get_agc().context("LoRa::start")? { "on" } else { "off" }
+        );
+        if self.symbol_timeout.is_some() {
+            println!(
+                "| RX timeout: {:<4} symb.  |",
+                self.get_symbol_timeout().context("LoRa::start")?
+            );
+        }
+        if let Some(rx_timeout_ms) = self.rx_timeout_ms {
+            println!("| RX timeout: {:<6} ms   |", rx_timeout_ms);
+        }
+        if let Some((sequence, _)) = &self.fhss {
+            let channels = sequence.len();
+            println!(
+                "| FHSS: {:<2} ch., {:<3} symb. |",
+                channels,
+                self.get_hop_period().context("LoRa::start")?
+            );
+        }
+        println!("| Mode: {:?}                |", self.mode);
+        println!("+-------------------------+");
+        Ok(())
+    }
+
+    fn receive(&mut self, option_sender: Option<Sender<MQTTMessage>>) -> Result<()> {
+        self.receive_into(ReceivedOutput::Mqtt(option_sender))
+    }
+
+    fn transmit(&mut self, mut tx_queue: TxQueue) -> Result<()> {
+        loop {
+            let Some(packet) = tx_queue.next_packet(&self.shutdown) else {
+                return Ok(());
+            };
+            self.standby_mode().context("LoRa::start")?;
+            // a busy channel only skips this packet
+            match self.transmit_packet(&packet) {
+                Ok(()) => info!("Sent: {:?}", packet),
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                }
+            }
+            self.sleep_mode().context("LoRa::transmit")?;
+        }
+    }
+
+    fn rt_receive(&mut self, csv_sender: Sender<CSVPacketWrapper>) -> Result<()> {
+        self.receive_into(ReceivedOutput::Csv(csv_sender))
+    }
+
+    fn rt_transmit(&mut self, mut tx_queue: TxQueue, csv_sender: Sender<CSVPacketWrapper>) -> Result<()> {
+        loop {
+            let Some(packet) = tx_queue.next_packet(&self.shutdown) else {
+                return Ok(());
+            };
+            self.standby_mode().context("LoRa::start")?;
+            match self.transmit_packet(&packet) {
+                // send to the CSV writer
+                Ok(()) => {
+                    info!("Sent: {:?}", packet);
+                    let _ = csv_sender.send(CSVPacketWrapper::Packet(packet));
+                }
+                // a busy channel only skips this packet
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                }
+            }
+
+            self.sleep_mode().context("LoRa::rt_transmit")?;
+        }
+    }
+
+    fn transceive(&mut self, option_sender: Option<Sender<MQTTMessage>>, mut tx_queue: TxQueue) -> Result<()> {
+        let output = ReceivedOutput::Mqtt(option_sender);
+        loop {
+            self.fhss_start().context("LoRa::start")?;
+            self.clear_irq_flags().context("LoRa::start")?;
+            self.receive_mode().context("LoRa::start")?;
+
+            // RX_CONTINUOUS until a packet is received or one is queued for transmission
+            let option_packet = loop {
+                // TX_QUEUE_POLL_INTERVAL_MS at the latest after a shutdown is requested
+                if self.shutdown.shutdown_requested() {
+                    self.sleep_mode().context("LoRa::transceive")?;
+                    return Ok(());
+                }
+                if let Some(packet) = tx_queue.try_next_packet() {
+                    break Some(packet);
+                }
+                let deadline = Instant::now() + time::Duration::from_millis(TX_QUEUE_POLL_INTERVAL_MS);
+                if self.wait_for_done(Some(deadline)).context("LoRa::start")? {
+                    break None;
+                }
+            };
+
+            match option_packet {
+                // a busy channel only skips this packet
+                Some(packet) => match self.transmit_packet_from_rx(&packet) {
+                    Ok(()) => info!("Sent: {:?}", packet),
+                    Err(e) => {
+                        eprintln!("{:?}", e);
+                        error!("{:?}", e);
+                    }
+                },
+                None => {
+                    let mut crc_error = false;
+                    let received_buffer = self
+                        .read_received_packet(&mut crc_error)
+                        .context("LoRa::start")?;
+                    self.publish_received(received_buffer, crc_error, &output)
+                        .context("LoRa::start")?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::*;
+
+    macro_rules! handle_error {
+        ($func:expr) => {
+            match $func {
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    error!("{:?}", e);
+                    std::process::exit(-1);
+                }
+                Ok(s) => s,
+            }
+        };
+    }
+
+    #[test]
+    fn spi_read_register_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        let mut value: u8 = 0x00;
+        assert!(lora
+            .spi_read_register(SX1278LoRaRegister::PAYLOAD_LENGTH, &mut value)
+            .is_ok());
+    }
+
+    #[test]
+    fn spi_write_register_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        let value: u8 = 0xFF;
+        assert!(lora
+            .spi_write_register(SX1278LoRaRegister::PAYLOAD_LENGTH, value)
+            .is_ok());
+    }
+
+    #[test]
+    fn standby_mode_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.standby_mode());
+
+        let mut mode: u8 = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
+        assert_eq!(
+            (SX1278LoRaMode::LONG_RANGE as u8
+                | SX1278LoRaMode::LOW_FREQUENCY_MODE_ON as u8
+                | SX1278LoRaMode::STDBY as u8),
+            mode
+        );
+    }
+
+    #[test]
+    fn sleep_mode_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.sleep_mode());
+
+        let mut mode: u8 = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
+        assert_eq!(
+            (SX1278LoRaMode::LONG_RANGE as u8
+                | SX1278LoRaMode::LOW_FREQUENCY_MODE_ON as u8
+                | SX1278LoRaMode::SLEEP as u8),
+            mode
+        );
+    }
+
+    #[test]
+    fn receive_mode_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.receive_mode());
+
+        let mut mode: u8 = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
+        assert_eq!(
+            (SX1278LoRaMode::LONG_RANGE as u8
+                | SX1278LoRaMode::LOW_FREQUENCY_MODE_ON as u8
+                | SX1278LoRaMode::RX_CONTINUOUS as u8),
+            mode
+        );
+    }
+
+    #[test]
+    fn transmit_mode_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.transmit_mode());
+
+        let mut mode: u8 = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
+        assert_eq!(
+            (SX1278LoRaMode::LONG_RANGE as u8
+                | SX1278LoRaMode::LOW_FREQUENCY_MODE_ON as u8
+                | SX1278LoRaMode::TX as u8),
+            mode
+        );
+    }
+
+    #[test]
+    fn set_frequency_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.sleep_mode());
+        handle_error!(lora.set_frequency(434_500_000));
+        assert!(lora.verify_frequency(434_500_000).is_ok());
+
+        let frequency = handle_error!(lora.get_frequency());
+        assert!(frequency.abs_diff(434_500_000) <= FSTEP);
+    }
+
+    #[test]
+    fn set_frequency_high_band() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora_config = config.lora_config.unwrap();
+        // the SX1278 only covers the LF band
+        lora_config.chip = Chip::SX1276;
+        let mut lora = match SX1278::simulated(&lora_config) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.set_frequency(868_000_000));
+        handle_error!(lora.standby_mode());
+
+        let mut mode: u8 = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
+        assert_eq!(
+            (SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::STDBY as u8),
+            mode
+        );
+        assert!(lora.verify_frequency(868_000_000).is_ok());
+        assert!(lora.verify_frequency(433_000_000).is_err());
+    }
+
+    #[test]
+    fn set_frequency_chip_bands() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora_config = config.lora_config.unwrap();
+        for (chip, frequency, supported) in [
+            (Chip::SX1278, 868_000_000, false),
+            (Chip::SX1276, 868_000_000, true),
+            (Chip::SX1279, 1_000_000_000, false),
+            (Chip::RFM95, 915_000_000, true),
+            (Chip::RFM95, 433_000_000, false),
+        ] {
+            lora_config.chip = chip;
+            let mut lora = handle_error!(SX1278::simulated(&lora_config));
+            assert_eq!(supported, lora.set_frequency(frequency).is_ok(), "{:?}", chip);
+        }
+    }
+
+    #[test]
+    fn set_frequency_out_of_band() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        assert!(lora.set_frequency(600_000_000).is_err());
+    }
+
+    #[test]
+    fn set_preamble_length_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.set_preamble_length(0x0123));
+        assert_eq!(0x0123, handle_error!(lora.get_preamble_length()));
+    }
+
+    #[test]
+    fn set_sync_word_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.set_sync_word(0x34));
+        assert_eq!(0x34, handle_error!(lora.get_sync_word()));
+    }
+
+    #[test]
+    fn set_header_mode_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        handle_error!(lora.set_header_mode(true, 8));
+        assert!(handle_error!(lora.get_implicit_header()));
+        assert_eq!(8, handle_error!(lora.get_payload_length()));
+        assert!(lora.send_packet(vec![0x00; 7]).is_err());
+
+        handle_error!(lora.set_header_mode(false, 0));
+        assert!(!handle_error!(lora.get_implicit_header()));
+    }
+
+    #[test]
+    fn low_data_rate_optimize_correct() {
+        assert_eq!(
+            131_072,
+            symbol_duration_us(Bandwidth::bandwidth_31_25kHz, SpreadingFactor::spreading_factor_4096)
+        );
+        assert!(needs_low_data_rate_optimize(
+            Bandwidth::bandwidth_31_25kHz,
+            SpreadingFactor::spreading_factor_4096
+        ));
+        assert!(needs_low_data_rate_optimize(
+            Bandwidth::bandwidth_125kHz,
+            SpreadingFactor::spreading_factor_4096
+        ));
+        assert!(needs_low_data_rate_optimize(
+            Bandwidth::bandwidth_125kHz,
+            SpreadingFactor::spreading_factor_2048
+        ));
+        assert!(!needs_low_data_rate_optimize(
+            Bandwidth::bandwidth_125kHz,
+            SpreadingFactor::spreading_factor_1024
+        ));
+        assert!(!needs_low_data_rate_optimize(
+            Bandwidth::bandwidth_500kHz,
+            SpreadingFactor::spreading_factor_4096
+        ));
+    }
+
+    #[test]
+    fn config_radio_modem_config_3() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let lora_config = config.lora_config.unwrap();
+        let mut lora = match SX1278::simulated(&lora_config) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        // conf.toml uses SF12 at 31.25 kHz, 131 ms symbols
+        handle_error!(lora.config_radio(&lora_config.radio_config));
+        assert!(handle_error!(lora.get_low_data_rate_optimize()));
+        assert!(handle_error!(lora.get_agc()));
+
+        let mut radio_config = lora_config.radio_config.clone();
+        radio_config.low_data_rate_optimize = Some(false);
+        radio_config.agc = false;
+        radio_config.lna_gain = Some(LnaGain::G3);
+        radio_config.lna_boost = true;
+        handle_error!(lora.config_radio(&radio_config));
+        assert!(!handle_error!(lora.get_low_data_rate_optimize()));
+        assert!(!handle_error!(lora.get_agc()));
+
+        let mut lna = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::LNA, &mut lna));
+        assert_eq!((3 << 5) | 0x03, lna);
+    }
+
+    #[test]
+    fn set_spreading_factor_sf6() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
             }
-        }
+        };
 
-        Ok(())
-    }
+        let mut detection_optimize = 0x00;
+        let mut detection_threshold = 0x00;
 
-    /*
-     * Returns SNR[dB] on last packet received
-     */
-    pub fn get_packet_snr(&mut self) -> Result<u8> {
-        let mut value: u8 = 0x00;
-        self.spi_read_register(SX1278LoRaRegister::PKT_SNR_VALUE, &mut value)
-            .context("LoRa::get_packet_snr")?;
+        handle_error!(lora.set_spreading_factor(SpreadingFactor::spreading_factor_64));
+        assert_eq!(6, handle_error!(lora.get_spreading_factor()));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::DETECTION_OPTIMIZE, &mut detection_optimize));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::DETECTION_THRESHOLD, &mut detection_threshold));
+        assert_eq!((0x05, 0x0C), (detection_optimize & 0x07, detection_threshold));
 
-        let snr = value.wrapping_neg() / 4;
-        Ok(snr)
+        handle_error!(lora.set_spreading_factor(SpreadingFactor::spreading_factor_4096));
+        assert_eq!(12, handle_error!(lora.get_spreading_factor()));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::DETECTION_OPTIMIZE, &mut detection_optimize));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::DETECTION_THRESHOLD, &mut detection_threshold));
+        assert_eq!((0x03, 0x0A), (detection_optimize & 0x07, detection_threshold));
     }
 
-    /*
-     * Returns RSSI[dBm] of the last packet received
-     */
-    pub fn get_packet_rssi(&mut self) -> Result<i16> {
-        let mut value: u8 = 0x00;
-        let frequency = self.get_frequency().context("LoRa::get_packet_rssi")?;
-        self.spi_read_register(SX1278LoRaRegister::PKT_RSSI_VALUE, &mut value)
-            .context("LoRa::get_packet_rssi")?;
-
-        let rssi = if frequency < 868_000_000 {
-            -164 + (value as i16)
-        } else {
-            -157 + (value as i16)
+    #[test]
+    fn channel_activity_detect_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
         };
-        Ok(rssi)
+
+        let dio_mapping = 0x40;
+        handle_error!(lora.spi_write_register(SX1278LoRaRegister::DIO_MAPPING_1, dio_mapping));
+        handle_error!(lora.clear_irq_flags());
+
+        assert!(!handle_error!(lora.channel_activity_detect()));
+
+        let mut value = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::DIO_MAPPING_1, &mut value));
+        assert_eq!(dio_mapping, value);
     }
-}
 
-impl LoRa for SX1278 {
-    fn get_mode(&self) -> Mode {
-        self.mode.clone()
+    #[test]
+    fn listen_before_talk_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+        let lbt_config = ListenBeforeTalkConfig {
+            max_attempts: 3,
+            min_backoff_ms: 0,
+            max_backoff_ms: 5,
+        };
+
+        handle_error!(lora.clear_irq_flags());
+        assert!(lora.listen_before_talk(&lbt_config).is_ok());
     }
 
-    fn configure_lora(&mut self, radio_config: &RadioConfig) -> Result<()> {
-        self.reset().context("LoRa::start")?;
-        self.sleep_mode().context("LoRa::start")?;
-        self.config_radio(radio_config).context("LoRa::start")?;
-        self.config_dio().context("LoRa::start")?;
-        self.spi_write_register(SX1278LoRaRegister::MODEM_CONFIG_3, 0x04u8)
-            .context("LoRa::start")?;
-        Ok(())
+    #[test]
+    fn receive_packet_until_timeout() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+
+        let mut crc_error = false;
+        let deadline = Instant::now() + time::Duration::from_millis(20);
+        assert_eq!(
+            ReceiveResult::Timeout,
+            handle_error!(lora.receive_packet_until(deadline, &mut crc_error))
+        );
+        assert!(Instant::now() >= deadline);
     }
 
-    fn display_parameters(&mut self, radio_config: &RadioConfig) -> Result<()> {
-        let frequency = radio_config.frequency;
-        println!("+-------------------------+");
-        println!("| Frequency: {} MHz      |", frequency / 1_000_000);
-        println!(
-            "| Bandwidth: {}            |",
-            self.get_bandwidth().context("LoRa::start")?
+    #[test]
+    fn receive_packet_until_correct() {
+        let config = handle_error!(Config::from_file("./tests/configs/simulator_conf.toml".to_string()));
+        let mut lora_config = config.lora_config.unwrap();
+        let air = SimulatedAir::new(AirConditions::default());
+        let mut receiver = handle_error!(SX1278::simulated_on_air(&lora_config, air.clone()));
+        lora_config.mode = Mode::TX;
+        let mut transmitter = handle_error!(SX1278::simulated_on_air(&lora_config, air));
+        handle_error!(transmitter.configure_lora(&lora_config.radio_config));
+        handle_error!(receiver.configure_lora(&lora_config.radio_config));
+
+        let sent = vec![0x01, 0xFF, 0x11, 0x00, 0x01, 0xFA, 0x2E, 0xFC];
+        handle_error!(transmitter.standby_mode());
+        handle_error!(transmitter.send_packet(sent.clone()));
+
+        let mut crc_error = false;
+        let deadline = Instant::now() + time::Duration::from_millis(20);
+        assert_eq!(
+            ReceiveResult::Packet(sent),
+            handle_error!(receiver.receive_packet_until(deadline, &mut crc_error))
         );
-        println!(
-            "| Coding rate: {}          |",
-            self.get_coding_rate().context("LoRa::start")?
+        assert!(!crc_error);
+
+        // the transmitter doesn't hear itself, the packet was received only once
+        let deadline = Instant::now() + time::Duration::from_millis(20);
+        assert_eq!(
+            ReceiveResult::Timeout,
+            handle_error!(transmitter.receive_packet_until(deadline, &mut crc_error))
         );
-        println!(
-            "| Spreading factor: {:02}    |",
-            self.get_spreading_factor().context("LoRa::start")?
+        assert_eq!(
+            ReceiveResult::Timeout,
+            handle_error!(receiver.receive_packet_until(deadline, &mut crc_error))
         );
-        println!("| Mode: {:?}                |", self.mode);
-        println!("+-------------------------+");
-        Ok(())
     }
 
-    fn receive(&mut self, option_sender: Option<Sender<MQTTMessage>>) -> Result<()> {
-        loop {
-            let mut crc_error = false;
+    #[test]
+    fn receive_packet_single_timeout() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora_config = config.lora_config.unwrap();
+        let mut crc_error = false;
 
-            let received_buffer = match self.receive_packet(&mut crc_error) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("{:?}", e);
-                    error!("{:?}", e);
-                    std::process::exit(-1);
-                }
-            };
-            println!();
-            println!(
-                "--------------------------------------------------------------------------------"
-            );
-            println!();
-
-            match Packet::new(&received_buffer) {
-                Ok(packet) => {
-                    let snr = self.get_packet_snr().context("LoRa::start")?;
-                    let rssi = self.get_packet_rssi().context("LoRa::start")?;
-
-                    if !crc_error {
-                        println!(
-                            "Received: {:#?}, SNR = {} dB, RSSI = {} dBm",
-                            packet, snr, rssi
-                        );
-                        info!(
-                            "Received: {:?}, SNR = {} dB, RSSI = {} dBm",
-                            packet, snr, rssi
-                        );
-                        if let Some(lora_sender) = &option_sender {
-                            let wrapped = PacketWrapper {
-                                packet,
-                                metadata: Metadata { snr, rssi },
-                            };
-                            handle_error_continue!(
-                                lora_sender.send(MQTTMessage::PacketWrapper(wrapped))
-                            );
-                        }
-                    } else {
-                        // using ANSI escape codes for colors in terminal
-                        println!("\x1b[0;31m[CRC ERROR]\x1b[0m\nReceived: {:#?}, SNR = {} dB, RSSI = {} dBm", packet, snr, rssi);
-                        info!(
-                            "[CRC ERROR] Received: {:?}, SNR = {} dB, RSSI = {} dBm",
-                            packet, snr, rssi
-                        );
-                    }
-                }
-                Err(e) => {
-                    println!("Bad package: {:?}", e);
-                    println!();
-                    println!("Received: {:02X?}", received_buffer);
-                    self.sleep_mode().context("LoRa::start")?;
-                    continue;
-                }
-            };
+        let mut lora = match SX1278::simulated(&lora_config) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+        // RX_SINGLE needs DIO1 for RxTimeout
+        assert!(lora.receive_packet_single(64, &mut crc_error).is_err());
 
-            self.sleep_mode().context("LoRa::start")?;
-        }
+        lora_config.dio1_gpio = Some(GPIOPinNumber::GPIO_61);
+        let mut lora = match SX1278::simulated(&lora_config) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
+        // 0x0123 symbols of 1.024 ms at the reset SF7 and 125 kHz
+        let start = Instant::now();
+        assert_eq!(
+            ReceiveResult::Timeout,
+            handle_error!(lora.receive_packet_single(0x0123, &mut crc_error))
+        );
+        assert!(start.elapsed() >= time::Duration::from_millis(297));
+        assert_eq!(0x0123, handle_error!(lora.get_symbol_timeout()));
+        assert!(lora.set_symbol_timeout(1024).is_err());
     }
 
-    fn transmit(&mut self) -> Result<()> {
-        loop {
-            let mut lna = 0x00;
-            self.spi_read_register(SX1278LoRaRegister::LNA, &mut lna)
-                .context("LoRa::start")?;
-            self.spi_write_register(SX1278LoRaRegister::LNA, lna | 0x03)
-                .context("LoRa::start")?;
-
-            self.standby_mode().context("LoRa::start")?;
+    #[test]
+    fn fifo_burst_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
 
-            let dummy_temperature: f32 = -3.2;
-            let dummy_humidity: f32 = 45.6;
-            let dummy_pressure: f32 = 996.6;
-            let packet = Packet {
-                version: 0x33,
-                id: 255, // device_id = 255 for tests
-                msg_id: 0x11,
-                msg_count: 0x00,
-                data_type: DataType::BME280,
-                data: Data::Bme280(BME280 {
-                    temperature: (dummy_temperature * 2.0).round() as i8 as u8,
-                    humidity: dummy_humidity.round() as u8,
-                    pressure: (dummy_pressure - 1000.0).round() as i8 as u8,
-                }),
-            };
-            self.send_packet(packet.to_bytes()?)
-                .context("LoRa::start")?;
-            self.sleep_mode()?;
-            Self::sleep(2000);
-        }
-    }
+        // the LoRa FIFO is addressed through FIFO_ADDR_PTR in LoRa mode only
+        handle_error!(lora.sleep_mode());
+        handle_error!(lora.standby_mode());
 
-    fn rt_receive(&mut self, csv_sender: Sender<CSVPacketWrapper>) -> Result<()> {
-        loop {
-            let mut crc_error = false;
+        let written: Vec<u8> = (0..64).collect();
+        handle_error!(lora.spi_write_register(SX1278LoRaRegister::FIFO_ADDR_PTR, 0x80));
+        handle_error!(lora.write_fifo(&written));
 
-            let received_buffer = match self.receive_packet(&mut crc_error) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("{:?}", e);
-                    error!("{:?}", e);
-                    std::process::exit(-1);
-                }
-            };
-            println!();
-            println!(
-                "--------------------------------------------------------------------------------"
-            );
-            println!();
-
-            match Packet::new(&received_buffer) {
-                Ok(packet) => {
-                    let snr = self.get_packet_snr().context("LoRa::start")?;
-                    let rssi = self.get_packet_rssi().context("LoRa::start")?;
-
-                    if !crc_error {
-                        println!(
-                            "Received: {:#?}, SNR = {} dB, RSSI = {} dBm",
-                            packet, snr, rssi
-                        );
-                        info!(
-                            "Received: {:?}, SNR = {} dB, RSSI = {} dBm",
-                            packet, snr, rssi
-                        );
-
-                        // send to the CSV writer
-                        let _ = csv_sender.send(CSVPacketWrapper::Packet(packet));
-                    } else {
-                        // using ANSI escape codes for colors in terminal
-                        println!("\x1b[0;31m[CRC ERROR]\x1b[0m\nReceived: {:#?}, SNR = {} dB, RSSI = {} dBm", packet, snr, rssi);
-                        info!(
-                            "[CRC ERROR] Received: {:?}, SNR = {} dB, RSSI = {} dBm",
-                            packet, snr, rssi
-                        );
-                    }
-                }
-                Err(e) => {
-                    println!("Bad package: {:?}", e);
-                    println!();
-                    println!("Received: {:02X?}", received_buffer);
-                    self.sleep_mode().context("LoRa::start")?;
-                    continue;
-                }
-            };
+        let mut pointer = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::FIFO_ADDR_PTR, &mut pointer));
+        assert_eq!(0x80 + 64, pointer);
 
-            self.sleep_mode().context("LoRa::start")?;
-        }
+        let mut read = vec![0x00; 64];
+        handle_error!(lora.spi_write_register(SX1278LoRaRegister::FIFO_ADDR_PTR, 0x80));
+        handle_error!(lora.read_fifo(&mut read));
+        assert_eq!(written, read);
     }
 
-    fn rt_transmit(&mut self, csv_sender: Sender<CSVPacketWrapper>) -> Result<()> {
-        loop {
-            let mut lna = 0x00;
-            self.spi_read_register(SX1278LoRaRegister::LNA, &mut lna)
-                .context("LoRa::start")?;
-            self.spi_write_register(SX1278LoRaRegister::LNA, lna | 0x03)
-                .context("LoRa::start")?;
-
-            self.standby_mode().context("LoRa::start")?;
+    #[test]
+    fn register_burst_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
+            }
+        };
 
-            let dummy_temperature: f32 = -3.2;
-            let dummy_humidity: f32 = 45.6;
-            let dummy_pressure: f32 = 996.6;
-            let packet = Packet {
-                version: 0x33,
-                id: 255, // device_id = 255 for tests
-                msg_id: 0x11,
-                msg_count: 0x00,
-                data_type: DataType::BME280,
-                data: Data::Bme280(BME280 {
-                    temperature: (dummy_temperature * 2.0).round() as i8 as u8,
-                    humidity: dummy_humidity.round() as u8,
-                    pressure: (dummy_pressure - 1000.0).round() as i8 as u8,
-                }),
-            };
-            self.send_packet(packet.to_bytes()?)
-                .context("LoRa::start")?;
+        handle_error!(lora.spi_burst_write(SX1278LoRaRegister::FRF_MSB, &[0x6C, 0x80, 0x00]));
 
-            // send to the CSV writer
-            let _ = csv_sender.send(CSVPacketWrapper::Packet(packet));
+        let mut values = [0x00; 3];
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::FRF_MSB, &mut values[0]));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::FRF_MID, &mut values[1]));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::FRF_LSB, &mut values[2]));
+        assert_eq!([0x6C, 0x80, 0x00], values);
+        assert_eq!(434_000_000, handle_error!(lora.get_frequency()));
+    }
 
-            self.sleep_mode()?;
-            Self::sleep(2000);
-        }
+    #[test]
+    fn packet_snr_correct() {
+        assert_eq!(0.0, packet_snr_db(0x00));
+        assert_eq!(10.0, packet_snr_db(0x28));
+        assert_eq!(31.75, packet_snr_db(0x7F));
+        assert_eq!(-0.25, packet_snr_db(0xFF));
+        assert_eq!(-4.0, packet_snr_db(0xF0));
+        assert_eq!(-32.0, packet_snr_db(0x80));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::*;
+    #[test]
+    fn packet_rssi_correct() {
+        // SNR >= 0, 16/15 correction
+        assert_eq!(-93, packet_rssi_dbm(60, 5.0, FrequencyBand::HF));
+        assert_eq!(-100, packet_rssi_dbm(60, 0.0, FrequencyBand::LF));
+        // SNR < 0, noise floor correction
+        assert_eq!(-101, packet_rssi_dbm(60, -4.0, FrequencyBand::HF));
+        assert_eq!(-120, packet_rssi_dbm(51, -7.25, FrequencyBand::LF));
+    }
 
-    macro_rules! handle_error {
-        ($func:expr) => {
-            match $func {
-                Err(e) => {
-                    eprintln!("{:?}", e);
-                    error!("{:?}", e);
-                    std::process::exit(-1);
-                }
-                Ok(s) => s,
+    #[test]
+    fn packet_metadata_from_registers_correct() {
+        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
+            Ok(lora) => lora,
+            Err(e) => {
+                error!("When creating lora object: {e}");
+                std::process::exit(-1);
             }
         };
+
+        handle_error!(lora.spi_write_register(SX1278LoRaRegister::PKT_SNR_VALUE, 0xE6));
+        handle_error!(lora.spi_write_register(SX1278LoRaRegister::PKT_RSSI_VALUE, 50));
+        assert_eq!(-6.5, handle_error!(lora.get_packet_snr()));
+        // 433 MHz -> LF port
+        assert_eq!(-121, handle_error!(lora.get_packet_rssi()));
     }
 
     #[test]
-    fn spi_read_register_correct() {
+    fn frequency_error_correct() {
+        assert_eq!(0, frequency_error_hz(0x00000, Bandwidth::bandwidth_125kHz));
+        assert_eq!(134, frequency_error_hz(0x00400, Bandwidth::bandwidth_125kHz));
+        assert_eq!(-134, frequency_error_hz(0xFFC00, Bandwidth::bandwidth_125kHz));
+        assert_eq!(-536, frequency_error_hz(0xFF000, Bandwidth::bandwidth_125kHz));
+        assert_eq!(2147, frequency_error_hz(0x01000, Bandwidth::bandwidth_500kHz));
+    }
+
+    #[test]
+    fn apply_afc_correct() {
         let config = handle_error!(Config::from_file("./conf.toml".to_string()));
-        let mut lora = match SX1278::from_config(&config.lora_config.unwrap()) {
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
             Ok(lora) => lora,
             Err(e) => {
                 error!("When creating lora object: {e}");
                 std::process::exit(-1);
             }
         };
-
-        let mut value: u8 = 0x00;
-        assert!(lora
-            .spi_read_register(SX1278LoRaRegister::PAYLOAD_LENGTH, &mut value)
-            .is_ok());
+        handle_error!(lora.set_frequency(433_000_000));
+        lora.afc = Some(AfcConfig {
+            window: 2,
+            max_offset_hz: 5_000,
+        });
+
+        // waits for the window to fill up
+        assert_eq!(None, handle_error!(lora.apply_afc(1_000)));
+        let frequency = handle_error!(lora.apply_afc(3_000)).unwrap();
+        assert!(frequency.abs_diff(433_002_000) <= FSTEP);
+        assert!(handle_error!(lora.get_frequency()).abs_diff(433_002_000) <= FSTEP);
+
+        // correction never leaves center_frequency +- max_offset_hz
+        assert_eq!(None, handle_error!(lora.apply_afc(10_000)));
+        let frequency = handle_error!(lora.apply_afc(10_000)).unwrap();
+        assert_eq!(433_005_000, frequency);
     }
 
     #[test]
-    fn spi_write_register_correct() {
+    fn get_version_correct() {
         let config = handle_error!(Config::from_file("./conf.toml".to_string()));
-        let mut lora = match SX1278::from_config(&config.lora_config.unwrap()) {
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
             Ok(lora) => lora,
             Err(e) => {
                 error!("When creating lora object: {e}");
@@ -806,58 +2742,83 @@ mod tests {
             }
         };
 
-        let value: u8 = 0xFF;
-        assert!(lora
-            .spi_write_register(SX1278LoRaRegister::PAYLOAD_LENGTH, value)
-            .is_ok());
+        assert_eq!(0x12, handle_error!(lora.get_version()));
+        handle_error!(lora.reset());
+        assert_eq!(0x12, handle_error!(lora.get_version()));
     }
 
     #[test]
-    fn standby_mode_correct() {
+    fn set_tx_power_correct() {
         let config = handle_error!(Config::from_file("./conf.toml".to_string()));
-        let mut lora = match SX1278::from_config(&config.lora_config.unwrap()) {
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
             Ok(lora) => lora,
             Err(e) => {
                 error!("When creating lora object: {e}");
                 std::process::exit(-1);
             }
         };
-
-        handle_error!(lora.standby_mode());
-
-        let mut mode: u8 = 0x00;
-        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
-        assert_eq!(
-            (SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::STDBY as u8),
-            mode
-        );
+        let mut pa_config = 0x00;
+        let mut pa_dac = 0x00;
+
+        handle_error!(lora.set_tx_power(PaOutput::PA_BOOST, 17, 7));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::PA_CONFIG, &mut pa_config));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::PA_DAC, &mut pa_dac));
+        assert_eq!((0x8F, 0x84), (pa_config, pa_dac));
+
+        handle_error!(lora.set_tx_power(PaOutput::PA_BOOST, 20, 7));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::PA_CONFIG, &mut pa_config));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::PA_DAC, &mut pa_dac));
+        assert_eq!((0x8F, 0x87), (pa_config, pa_dac));
+
+        // max_power 7: Pmax = 15 dBm, OutputPower = 14 - 15 + 15
+        handle_error!(lora.set_tx_power(PaOutput::RFO, 14, 7));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::PA_CONFIG, &mut pa_config));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::PA_DAC, &mut pa_dac));
+        assert_eq!((0x7E, 0x84), (pa_config, pa_dac));
+
+        assert!(lora.set_tx_power(PaOutput::RFO, 17, 7).is_err());
+        assert!(lora.set_tx_power(PaOutput::PA_BOOST, 1, 7).is_err());
+        assert!(lora.set_tx_power(PaOutput::PA_BOOST, 21, 7).is_err());
     }
 
     #[test]
-    fn sleep_mode_correct() {
+    fn set_ocp_correct() {
         let config = handle_error!(Config::from_file("./conf.toml".to_string()));
-        let mut lora = match SX1278::from_config(&config.lora_config.unwrap()) {
+        let mut lora = match SX1278::simulated(&config.lora_config.unwrap()) {
             Ok(lora) => lora,
             Err(e) => {
                 error!("When creating lora object: {e}");
                 std::process::exit(-1);
             }
         };
+        let mut ocp = 0x00;
 
-        handle_error!(lora.sleep_mode());
+        handle_error!(lora.set_ocp(100));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OCP, &mut ocp));
+        assert_eq!(0x2B, ocp);
 
-        let mut mode: u8 = 0x00;
-        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
-        assert_eq!(
-            (SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::SLEEP as u8),
-            mode
-        );
+        handle_error!(lora.set_ocp(240));
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OCP, &mut ocp));
+        assert_eq!(0x3B, ocp);
+
+        assert!(lora.set_ocp(125).is_err());
+        assert!(lora.set_ocp(40).is_err());
     }
 
     #[test]
-    fn receive_mode_correct() {
-        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
-        let mut lora = match SX1278::from_config(&config.lora_config.unwrap()) {
+    fn fsk_rx_bandwidth_correct() {
+        // RxBwMant 24, RxBwExp 5: 32 MHz / (24 * 2^7) = 10416 Hz
+        assert_eq!((0x15, 10_416), handle_error!(fsk_rx_bandwidth(Modulation::FSK, 10_000)));
+        assert_eq!((0x15, 5_208), handle_error!(fsk_rx_bandwidth(Modulation::OOK, 5_000)));
+        assert_eq!((0x01, 250_000), handle_error!(fsk_rx_bandwidth(Modulation::FSK, 250_000)));
+        assert!(fsk_rx_bandwidth(Modulation::FSK, 250_001).is_err());
+    }
+
+    #[test]
+    fn fsk_config_radio_correct() {
+        let config = handle_error!(Config::from_file("./tests/configs/fsk_conf.toml".to_string()));
+        let lora_config = config.lora_config.unwrap();
+        let mut lora = match SX1278::simulated(&lora_config) {
             Ok(lora) => lora,
             Err(e) => {
                 error!("When creating lora object: {e}");
@@ -865,34 +2826,127 @@ mod tests {
             }
         };
 
-        handle_error!(lora.receive_mode());
+        handle_error!(lora.sleep_mode());
+        handle_error!(lora.config_radio(&lora_config.radio_config));
 
-        let mut mode: u8 = 0x00;
-        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
-        assert_eq!(
-            (SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::RX_CONTINUOUS as u8),
-            mode
-        );
+        let mut value = 0x00;
+        // FSK, LowFrequencyModeOn, SLEEP
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut value));
+        assert_eq!(0x08, value);
+        assert_eq!(4_800, handle_error!(lora.get_bitrate()));
+        assert!(handle_error!(lora.get_frequency_deviation()).abs_diff(5_000) <= FSTEP as u32);
+        // RxBwMant 24, RxBwExp 4: 20833 Hz filter
+        handle_error!(lora.spi_read_register(SX1278FskRegister::RX_BW, &mut value));
+        assert_eq!(0x14, value);
+        // BT = 0.5
+        handle_error!(lora.spi_read_register(SX1278FskRegister::PA_RAMP, &mut value));
+        assert_eq!(0x49, value);
+        // 2 byte sync word
+        handle_error!(lora.spi_read_register(SX1278FskRegister::SYNC_CONFIG, &mut value));
+        assert_eq!(0x51, value);
+        // variable length, whitening, CRC
+        handle_error!(lora.spi_read_register(SX1278FskRegister::PACKET_CONFIG_1, &mut value));
+        assert_eq!(0xD8, value);
     }
 
     #[test]
-    fn transmit_mode_correct() {
-        let config = handle_error!(Config::from_file("./conf.toml".to_string()));
-        let mut lora = match SX1278::from_config(&config.lora_config.unwrap()) {
+    fn fsk_send_receive_correct() {
+        let config = handle_error!(Config::from_file("./tests/configs/fsk_conf.toml".to_string()));
+        let lora_config = config.lora_config.unwrap();
+        let air = SimulatedAir::new(AirConditions::default());
+        let mut transmitter = handle_error!(SX1278::simulated_on_air(&lora_config, air.clone()));
+        let mut receiver = handle_error!(SX1278::simulated_on_air(&lora_config, air));
+        handle_error!(transmitter.config_radio(&lora_config.radio_config));
+        handle_error!(receiver.config_radio(&lora_config.radio_config));
+
+        let sent: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43];
+        handle_error!(transmitter.send_packet(sent.clone()));
+        let mut crc_error = false;
+        let received = handle_error!(receiver.receive_packet(&mut crc_error));
+        assert_eq!(sent, received);
+        assert!(!crc_error);
+        assert_eq!(-60, handle_error!(receiver.get_packet_rssi()));
+
+        assert!(transmitter.send_packet(vec![0x00; 64]).is_err());
+        assert!(transmitter.channel_activity_detect().is_err());
+    }
+
+    #[test]
+    fn fsk_crc_error_correct() {
+        let config = handle_error!(Config::from_file("./tests/configs/fsk_conf.toml".to_string()));
+        let lora_config = config.lora_config.unwrap();
+        let air = SimulatedAir::new(AirConditions {
+            crc_corruption: 1.0,
+            ..AirConditions::default()
+        });
+        let mut transmitter = handle_error!(SX1278::simulated_on_air(&lora_config, air.clone()));
+        let mut receiver = handle_error!(SX1278::simulated_on_air(&lora_config, air));
+        handle_error!(transmitter.config_radio(&lora_config.radio_config));
+        handle_error!(receiver.config_radio(&lora_config.radio_config));
+
+        // with CrcAutoClearOff packets failing the CRC are still received
+        let sent: Vec<u8> = vec![0x01, 0x22, 0x11, 0x00, 0x01, 0x17, 0x2D, 0x43];
+        handle_error!(transmitter.send_packet(sent.clone()));
+        let mut crc_error = false;
+        let received = handle_error!(receiver.receive_packet(&mut crc_error));
+        assert_ne!(sent, received);
+        assert_eq!(sent.len(), received.len());
+        assert!(crc_error);
+    }
+
+    #[test]
+    fn hopping_sequence_correct() {
+        let channels: Vec<u64> = (0..16).map(|i| 433_000_000 + i * 100_000).collect();
+
+        assert_eq!(channels, hopping_sequence(&channels, 0));
+
+        let sequence = hopping_sequence(&channels, 2024);
+        assert_eq!(sequence, hopping_sequence(&channels, 2024));
+        assert_ne!(sequence, channels);
+        assert_ne!(sequence, hopping_sequence(&channels, 2025));
+
+        let mut sorted = sequence.clone();
+        sorted.sort();
+        assert_eq!(channels, sorted);
+    }
+
+    #[test]
+    fn fhss_hop_channel_correct() {
+        let config = handle_error!(Config::from_file("./tests/configs/fhss_conf.toml".to_string()));
+        let lora_config = config.lora_config.unwrap();
+        let mut lora = match SX1278::simulated(&lora_config) {
             Ok(lora) => lora,
             Err(e) => {
                 error!("When creating lora object: {e}");
                 std::process::exit(-1);
             }
         };
-
-        handle_error!(lora.transmit_mode());
-
-        let mut mode: u8 = 0x00;
-        handle_error!(lora.spi_read_register(SX1278LoRaRegister::OP_MODE, &mut mode));
-        assert_eq!(
-            (SX1278LoRaMode::LONG_RANGE as u8 | SX1278LoRaMode::TX as u8),
-            mode
-        );
+        let fhss_config = lora_config.radio_config.fhss.clone().unwrap();
+        let sequence = hopping_sequence(&fhss_config.channels, fhss_config.seed);
+
+        handle_error!(lora.config_radio(&lora_config.radio_config));
+        assert_eq!(10, handle_error!(lora.get_hop_period()));
+        let mut dio_mapping = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::DIO_MAPPING_1, &mut dio_mapping));
+        assert_eq!(SX1278Dio1Mapping::FHSS_CHANGE_CHANNEL as u8, (dio_mapping >> 4) & 0x03);
+
+        // every packet starts on the first channel of the sequence, sent quickly at SF7 and 500 kHz
+        handle_error!(lora.set_bandwidth(Bandwidth::bandwidth_500kHz));
+        handle_error!(lora.set_spreading_factor(SpreadingFactor::spreading_factor_128));
+        handle_error!(lora.set_dio0_mapping(SX1278Dio0Mapping::TX_DONE));
+        handle_error!(lora.send_packet(vec![0x01, 0x02, 0x03]));
+        assert!(handle_error!(lora.get_frequency()).abs_diff(sequence[0]) <= FSTEP);
+
+        // FhssPresentChannel 11 wraps around the 8 channels
+        handle_error!(lora.spi_write_register(SX1278LoRaRegister::HOP_CHANNEL, 11));
+        lora.spi.device().registers[SX1278LoRaRegister::IRQ_FLAGS as usize] =
+            SX1278IRQMask::IRQ_FHSS_CHANGE_CHANNEL_MASK as u8 | SX1278IRQMask::IRQ_RX_DONE_MASK as u8;
+        handle_error!(lora.hop_channel());
+        assert!(handle_error!(lora.get_frequency()).abs_diff(sequence[3]) <= FSTEP);
+
+        // only FhssChangeChannel is cleared
+        let mut irq_flags = 0x00;
+        handle_error!(lora.spi_read_register(SX1278LoRaRegister::IRQ_FLAGS, &mut irq_flags));
+        assert_eq!(SX1278IRQMask::IRQ_RX_DONE_MASK as u8, irq_flags);
     }
 }
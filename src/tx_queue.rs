@@ -0,0 +1,284 @@
+use crate::cayenne_lpp::CayenneLpp;
+use crate::config::TxConfig;
+use crate::graceful_shutdown::Shutdown;
+use crate::packet::{Data, Packet};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval between TX queue polls while receiving in the TRX mode, and between shutdown
+/// checks while waiting for the next packet
+pub const TX_QUEUE_POLL_INTERVAL_MS: u64 = 10;
+
+// Data sent here from the sensor threads (and any other thread that wants something on the air).
+// Readings replace the previous reading of the same data type and are sent every transmit interval,
+// packets are sent once, as soon as the radio is free. With cayenne_lpp the readings LPP has types for
+// are sent together as one Cayenne LPP packet.
+#[derive(Debug)]
+pub enum TxMessage {
+    Reading(Data),
+    Packet(Packet),
+}
+
+pub struct TxQueue {
+    receiver: Receiver<TxMessage>,
+    device_id: u8,
+    packet_version: u8,
+    cayenne_lpp: bool,
+    interval: Duration,
+    next_interval: Instant,
+    msg_id: u8,
+    readings: Vec<Data>,
+    pending: VecDeque<Packet>,
+}
+
+impl TxQueue {
+    pub fn new(tx_config: &TxConfig, receiver: Receiver<TxMessage>) -> Self {
+        Self {
+            receiver,
+            device_id: tx_config.device_id,
+            packet_version: tx_config.packet_version,
+            cayenne_lpp: tx_config.cayenne_lpp,
+            interval: Duration::from_secs(tx_config.transmit_interval),
+            next_interval: Instant::now(),
+            msg_id: 0,
+            readings: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /*
+     * Blocks until there is something to transmit: a queued packet, or at every transmit interval
+     * one packet per data type with the latest reading of that type. None once a shutdown is requested
+     */
+    pub fn next_packet(&mut self, shutdown: &Shutdown) -> Option<Packet> {
+        loop {
+            if shutdown.shutdown_requested() {
+                return None;
+            }
+            if let Some(packet) = self.try_next_packet() {
+                return Some(packet);
+            }
+
+            // woken up at least every TX_QUEUE_POLL_INTERVAL_MS to notice a shutdown
+            let timeout = self
+                .next_interval
+                .saturating_duration_since(Instant::now())
+                .min(Duration::from_millis(TX_QUEUE_POLL_INTERVAL_MS));
+            match self.receiver.recv_timeout(timeout) {
+                Ok(TxMessage::Packet(packet)) => return Some(packet),
+                Ok(TxMessage::Reading(data)) => self.update_reading(data),
+                Err(RecvTimeoutError::Timeout) => {}
+                // no more readings will come, keep sending the latest ones
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+            }
+        }
+    }
+
+    /*
+     * Returns the next packet to transmit if there is one right now, without blocking
+     */
+    pub fn try_next_packet(&mut self) -> Option<Packet> {
+        if let Some(packet) = self.pending.pop_front() {
+            return Some(packet);
+        }
+
+        while let Ok(message) = self.receiver.try_recv() {
+            match message {
+                TxMessage::Packet(packet) => return Some(packet),
+                TxMessage::Reading(data) => self.update_reading(data),
+            }
+        }
+
+        let now = Instant::now();
+        if now >= self.next_interval {
+            self.next_interval = now + self.interval;
+            let mut records = Vec::new();
+            let readings = self.readings.clone();
+            for data in readings {
+                if self.cayenne_lpp {
+                    if let Some(reading_records) = CayenneLpp::records_from_reading(&data) {
+                        records.extend(reading_records);
+                        continue;
+                    }
+                }
+                let packet = self.packet_from_reading(data);
+                self.pending.push_back(packet);
+            }
+            if !records.is_empty() {
+                let packet = self.packet_from_reading(Data::CayenneLpp(CayenneLpp { records }));
+                self.pending.push_back(packet);
+            }
+        }
+
+        self.pending.pop_front()
+    }
+
+    fn update_reading(&mut self, data: Data) {
+        let data_type = data.data_type();
+        match self.readings.iter_mut().find(|reading| reading.data_type() == data_type) {
+            Some(reading) => *reading = data,
+            None => self.readings.push(data),
+        }
+    }
+
+    fn packet_from_reading(&mut self, data: Data) -> Packet {
+        let msg_id = self.msg_id;
+        self.msg_id = self.msg_id.wrapping_add(1);
+
+        Packet {
+            version: self.packet_version,
+            id: self.device_id,
+            msg_id,
+            msg_count: 1,
+            data_type: data.data_type(),
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{DataType, Gps, Status, BME280};
+    use std::sync::mpsc::channel;
+
+    fn tx_config() -> TxConfig {
+        TxConfig {
+            device_id: 7,
+            transmit_interval: 1,
+            packet_version: 2,
+            cayenne_lpp: false,
+        }
+    }
+
+    fn bme280_reading(temperature: u8) -> TxMessage {
+        TxMessage::Reading(Data::Bme280(BME280 {
+            temperature,
+            humidity: 46,
+            pressure: 253,
+        }))
+    }
+
+    #[test]
+    fn readings_correct() {
+        let (sender, receiver) = channel();
+        let mut tx_queue = TxQueue::new(&tx_config(), receiver);
+
+        sender.send(bme280_reading(10)).unwrap();
+        sender.send(bme280_reading(20)).unwrap();
+        sender
+            .send(TxMessage::Reading(Data::Status(Status {
+                status: 0,
+                battery: 0,
+                sleep: 0,
+                chip_version: 0x12,
+            })))
+            .unwrap();
+
+        // the first interval starts right away, only the latest BME280 reading is sent
+        let packet = tx_queue.next_packet(&Shutdown::default()).unwrap();
+        assert_eq!(7, packet.id);
+        assert_eq!(0, packet.msg_id);
+        assert_eq!(1, packet.msg_count);
+        assert_eq!(2, packet.version);
+        assert_eq!(DataType::BME280, packet.data_type);
+        match packet.data {
+            Data::Bme280(data) => assert_eq!(20, data.temperature),
+            _ => panic!("Expected a BME280 packet"),
+        }
+
+        let packet = tx_queue.next_packet(&Shutdown::default()).unwrap();
+        assert_eq!(1, packet.msg_id);
+        assert_eq!(DataType::Status, packet.data_type);
+
+        // readings are sent again every interval, with a new msg_id
+        assert!(tx_queue.try_next_packet().is_none());
+        drop(sender);
+        let start = Instant::now();
+        let packet = tx_queue.next_packet(&Shutdown::default()).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(900));
+        assert_eq!(2, packet.msg_id);
+        assert_eq!(DataType::BME280, packet.data_type);
+    }
+
+    #[test]
+    fn readings_cayenne_lpp_correct() {
+        let (sender, receiver) = channel();
+        let tx_config = TxConfig { cayenne_lpp: true, ..tx_config() };
+        let mut tx_queue = TxQueue::new(&tx_config, receiver);
+
+        sender.send(bme280_reading(43)).unwrap();
+        sender
+            .send(TxMessage::Reading(Data::Gps(Gps {
+                status: 0,
+                altitude: 120,
+                latitude: 521_234_567,
+                longitude: 210_123_456,
+            })))
+            .unwrap();
+        sender
+            .send(TxMessage::Reading(Data::Status(Status {
+                status: 0,
+                battery: 0,
+                sleep: 0,
+                chip_version: 0x12,
+            })))
+            .unwrap();
+
+        // the BME280 and GPS readings share a packet, LPP has no type for the status
+        let packet = tx_queue.next_packet(&Shutdown::default()).unwrap();
+        assert_eq!(DataType::Status, packet.data_type);
+        let packet = tx_queue.next_packet(&Shutdown::default()).unwrap();
+        assert_eq!(DataType::CayenneLpp, packet.data_type);
+        assert_eq!(7, packet.id);
+        assert_eq!(
+            r#""CAYENNE_LPP": { "temperature_1": 21.5, "relative_humidity_1": 46, "barometric_pressure_1": 997, "gps_4": { "latitude": 52.1235, "longitude": 21.0123, "altitude": 120 } }"#,
+            packet.to_json().unwrap()
+        );
+
+        // and it decodes on the receiver
+        let received = Packet::new(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(DataType::CayenneLpp, received.data_type);
+        assert_eq!(packet.to_json().unwrap(), received.to_json().unwrap());
+    }
+
+    #[test]
+    fn queued_packet_correct() {
+        let (sender, receiver) = channel();
+        let mut tx_queue = TxQueue::new(&tx_config(), receiver);
+
+        let packet = Packet {
+            version: 0,
+            id: 3,
+            msg_id: 0x42,
+            msg_count: 1,
+            data_type: DataType::Sms,
+            data: Data::Sms("hello".to_string()),
+        };
+        sender.send(TxMessage::Packet(packet)).unwrap();
+
+        // queued packets are sent as they are, without waiting for the interval
+        let packet = tx_queue.try_next_packet().unwrap();
+        assert_eq!(3, packet.id);
+        assert_eq!(0x42, packet.msg_id);
+        assert_eq!(DataType::Sms, packet.data_type);
+    }
+
+    #[test]
+    fn next_packet_shutdown() {
+        let (_sender, receiver) = channel();
+        let mut tx_queue = TxQueue::new(&tx_config(), receiver);
+        let shutdown = Shutdown::default();
+
+        // nothing to send, the wait ends shortly after a shutdown is requested
+        let waiting_shutdown = shutdown.clone();
+        let handle = thread::spawn(move || tx_queue.next_packet(&waiting_shutdown));
+        thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        shutdown.request_shutdown();
+        assert!(handle.join().unwrap().is_none());
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+}